@@ -1,10 +1,14 @@
-use crate::utils::{math::cross, traits::Float};
-use nalgebra::{distance, Point2, Vector2};
+use crate::utils::{math::point_distance, traits::Float};
+use nalgebra::{Point2, Vector2};
 use serde::{Deserialize, Serialize};
 
+use super::bounding_box::BoundingBox;
+use super::error::GeometryError;
 use super::track::Track;
 
+/// Only the center, radius and angles are serialized, `new` computes the rest when deserializing
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "ArcPathFields<F>", into = "ArcPathFields<F>")]
 pub struct ArcPath<F: Float> {
     pub center: Point2<F>,
     pub r: F,
@@ -15,25 +19,30 @@ pub struct ArcPath<F: Float> {
     v1: Vector2<F>,
     counterclockwise: bool,
     length: F,
+    bounds: BoundingBox<F>,
 }
 
 impl<F> ArcPath<F>
 where
     F: Float,
 {
-    pub fn new(center: Point2<F>, r: F, theta0: F, theta1: F) -> Self {
+    /// Arc of radius `r` around `center`, from the angle `theta0` to `theta1`, counterclockwise
+    /// if `theta1` is the larger one. The radius must be positive, and the angles distinct
+    pub fn try_new(center: Point2<F>, r: F, theta0: F, theta1: F) -> Result<Self, GeometryError> {
+        if num::Float::is_nan(r) || r <= F::zero() {
+            return Err(GeometryError::InvalidRadius);
+        }
         let delta_t = theta1 - theta0;
-        assert!(
-            delta_t != F::zero(),
-            "the arc path must have a non-zero length"
-        );
+        if delta_t == F::zero() {
+            return Err(GeometryError::ZeroLengthArc);
+        }
         let length = r * num::Float::abs(delta_t);
         let counterclockwise = delta_t > F::zero();
         let v0 = Vector2::new(num::Float::cos(theta0), num::Float::sin(theta0));
         let v1 = Vector2::new(num::Float::cos(theta1), num::Float::sin(theta1));
         let p0 = center + v0 * r;
 
-        Self {
+        let mut arc = Self {
             center,
             r,
             theta0,
@@ -43,16 +52,86 @@ where
             v1,
             counterclockwise,
             length,
-        }
+            bounds: BoundingBox::new(p0, p0),
+        };
+        arc.bounds = arc.swept_bounds();
+        Ok(arc)
+    }
+
+    /// Same as `try_new`, panicking if the radius or the angles are invalid
+    pub fn new(center: Point2<F>, r: F, theta0: F, theta1: F) -> Self {
+        Self::try_new(center, r, theta0, theta1).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn bounding_box(&self) -> BoundingBox<F> {
+        self.bounds
+    }
+
+    /// Box of the swept sector only: the endpoints, along with the points of the circumference
+    /// furthest along each axis which lie within the arc's angular range
+    fn swept_bounds(&self) -> BoundingBox<F> {
+        let p1 = self.center + self.v1 * self.r;
+        let (zero, one) = (F::zero(), F::one());
+        let extremes = [
+            Vector2::new(one, zero),
+            Vector2::new(zero, one),
+            Vector2::new(-one, zero),
+            Vector2::new(zero, -one),
+        ]
+        .into_iter()
+        .map(|v| self.center + v * self.r)
+        .filter(|&p| self.within_bounds(p));
+        BoundingBox::from_points([self.p0, p1].into_iter().chain(extremes))
     }
 
     fn within_bounds(&self, p: Point2<F>) -> bool {
+        // angle swept from theta0 to reach p, in the direction of the arc and wrapped into
+        // [0, 2pi), compared against the total sweep
         let v = p - self.center;
-        let ord0 = cross(&self.v0, &v);
-        let ord1 = cross(&v, &self.v1);
-        match self.counterclockwise {
-            true => ord0 >= F::zero() && ord1 >= F::zero(),
-            false => ord0 <= F::zero() && ord1 <= F::zero(),
+        let two_pi = F::from(2.0 * std::f64::consts::PI).unwrap();
+        let tolerance = F::from(1e-9).unwrap();
+        let phi = num::Float::atan2(v.y, v.x);
+        let offset = match self.counterclockwise {
+            true => phi - self.theta0,
+            false => self.theta0 - phi,
+        };
+        let offset = offset - two_pi * num::Float::floor(offset / two_pi);
+        let sweep = num::Float::abs(self.theta1 - self.theta0);
+        // points on the ray of theta0 can land just below 2pi
+        offset <= sweep + tolerance || offset >= two_pi - tolerance
+    }
+}
+
+/// Defining fields of an `ArcPath`, which are all that it serializes
+#[derive(Serialize, Deserialize)]
+struct ArcPathFields<F: Float> {
+    center: Point2<F>,
+    r: F,
+    theta0: F,
+    theta1: F,
+}
+
+impl<F> TryFrom<ArcPathFields<F>> for ArcPath<F>
+where
+    F: Float,
+{
+    type Error = GeometryError;
+
+    fn try_from(fields: ArcPathFields<F>) -> Result<Self, Self::Error> {
+        Self::try_new(fields.center, fields.r, fields.theta0, fields.theta1)
+    }
+}
+
+impl<F> From<ArcPath<F>> for ArcPathFields<F>
+where
+    F: Float,
+{
+    fn from(arc: ArcPath<F>) -> Self {
+        Self {
+            center: arc.center,
+            r: arc.r,
+            theta0: arc.theta0,
+            theta1: arc.theta1,
         }
     }
 }
@@ -63,16 +142,42 @@ where
 {
     fn sdf(&self, p: Point2<F>) -> F {
         // we treat the arc as a circumference
-        if !self.within_bounds(p) {
-            return F::infinity();
-        }
-        let mut signed_dist = distance(&p, &self.center) - self.r;
+        let mut signed_dist = point_distance(&p, &self.center) - self.r;
         if !self.counterclockwise {
             signed_dist = -signed_dist;
         }
+        if !self.within_bounds(p) {
+            // outside of the arc's angular range, the closest point is one of the endpoints
+            // (same as for the line path), keeping the sign of the side of the circumference
+            let d0 = point_distance(&p, &self.p0);
+            let d1 = point_distance(&p, &(self.center + self.v1 * self.r));
+            let sign = num::Float::signum(signed_dist);
+            return sign * num::Float::min(d0, d1);
+        }
         signed_dist
     }
 
+    fn normal_at(&self, p: Point2<F>) -> Vector2<F> {
+        // within the arc's angular range the radial direction, outwards for a counterclockwise
+        // arc and inwards for a clockwise one, so to its right either way, and outside of it
+        // the direction away from the closest endpoint, with the sign of the side as in the sdf
+        let sign = match self.counterclockwise {
+            true => F::one(),
+            false => -F::one(),
+        };
+        if !self.within_bounds(p) {
+            let p1 = self.center + self.v1 * self.r;
+            let side = num::Float::signum(point_distance(&p, &self.center) - self.r) * sign;
+            let endpoint = if point_distance(&p, &self.p0) <= point_distance(&p, &p1) {
+                self.p0
+            } else {
+                p1
+            };
+            return (p - endpoint).normalize() * side;
+        }
+        (p - self.center).normalize() * sign
+    }
+
     fn length(&self) -> F {
         self.length
     }
@@ -115,6 +220,14 @@ where
         }
     }
 
+    fn curvature_at(&self, _d: F) -> F {
+        if self.counterclockwise {
+            F::one() / self.r
+        } else {
+            -F::one() / self.r
+        }
+    }
+
     fn point_projection_distance(&self, p: Point2<F>) -> F {
         // returns the distance of the point (x, y) when projected along the arc path
         // assumes that (x, y) is on the arc path
@@ -140,3 +253,169 @@ macro_rules! new_arc_path {
         ArcPath::new(Point2::new($center_x, $center_y), $r, $theta0, $theta1)
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::track::finite_difference_normal;
+    use std::f64::consts::PI;
+
+    fn point_on_ray(arc: &ArcPath<f64>, theta: f64, rho: f64) -> Point2<f64> {
+        arc.center + Vector2::new(theta.cos(), theta.sin()) * rho
+    }
+
+    #[test]
+    fn test_within_bounds_on_extremal_rays() {
+        let arcs = [
+            new_arc_path![1.0, -2.0, 1.0, -PI / 2.0, PI / 2.0],
+            new_arc_path![0.0, 0.0, 2.0, PI / 2.0, 3.0 * PI / 2.0],
+            new_arc_path![3.0, -11.0, 1.0, PI / 3.0, -PI / 7.0],
+            new_arc_path![-5.0, 4.0, 0.5, 0.1, 0.1 + 1.5 * PI],
+            new_arc_path![8.0, -10.0, 2.0, 5.0 * PI / 4.0, -PI / 4.0],
+        ];
+        for arc in arcs.iter() {
+            for theta in [arc.theta0, arc.theta1] {
+                for rho in [0.25 * arc.r, arc.r, 1.5 * arc.r, 3.0 * arc.r] {
+                    let p = point_on_ray(arc, theta, rho);
+                    assert!(arc.within_bounds(p), "theta = {}, rho = {}", theta, rho);
+                    let circle_sdf = if arc.counterclockwise {
+                        rho - arc.r
+                    } else {
+                        arc.r - rho
+                    };
+                    assert!((arc.sdf(p) - circle_sdf).abs() < 1e-12);
+                }
+            }
+            // slightly beyond the extremes, on either side
+            let direction = if arc.counterclockwise { 1.0 } else { -1.0 };
+            for (theta, outside) in [
+                (arc.theta0, -direction * 1e-3),
+                (arc.theta1, direction * 1e-3),
+            ] {
+                let p = point_on_ray(arc, theta + outside, arc.r);
+                assert!(!arc.within_bounds(p));
+            }
+        }
+    }
+
+    #[test]
+    fn test_bounding_box_covers_the_swept_sector() {
+        // a quarter of the circle, with no axis-extreme point strictly inside of it
+        let quarter = new_arc_path![1.0, 2.0, 2.0, 0.0, PI / 2.0];
+        let bounds = quarter.bounding_box();
+        assert!((bounds.min - Point2::new(1.0, 2.0)).norm() < 1e-12);
+        assert!((bounds.max - Point2::new(3.0, 4.0)).norm() < 1e-12);
+        // the same half circle, from the top to the bottom, covers either side of the center
+        // depending on its direction
+        let clockwise = new_arc_path![0.0, 0.0, 1.0, PI / 2.0, -PI / 2.0];
+        let bounds = clockwise.bounding_box();
+        assert!((bounds.min - Point2::new(0.0, -1.0)).norm() < 1e-12);
+        assert!((bounds.max - Point2::new(1.0, 1.0)).norm() < 1e-12);
+        let arc = new_arc_path![0.0, 0.0, 1.0, PI / 2.0, 3.0 * PI / 2.0];
+        let bounds = arc.bounding_box();
+        assert!((bounds.min - Point2::new(-1.0, -1.0)).norm() < 1e-12);
+        assert!((bounds.max - Point2::new(0.0, 1.0)).norm() < 1e-12);
+        for p in arc.sample_points_num(100) {
+            assert!(bounds.distance_to(p) < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_serde_keeps_only_the_defining_fields() {
+        let arc = new_arc_path![1.0, 2.0, 1.0, 0.0, PI / 2.0];
+        let mut json = serde_json::to_value(&arc).unwrap();
+        let keys = json.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(keys, ["center", "r", "theta0", "theta1"]);
+        // a hand-edited radius, along with stale caches of older files, which are ignored
+        json["r"] = serde_json::json!(2.0);
+        json["length"] = serde_json::json!(1.0);
+        json["counterclockwise"] = serde_json::json!(false);
+        let edited: ArcPath<f64> = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(edited.center, arc.center);
+        assert_eq!(edited.length(), PI);
+        assert!(edited.counterclockwise);
+        assert_eq!(edited.first_point(), Point2::new(3.0, 2.0));
+        assert_eq!(edited.curvature_at(0.0), 0.5);
+        json["theta1"] = json["theta0"].clone();
+        assert!(serde_json::from_value::<ArcPath<f64>>(json).is_err());
+    }
+
+    #[test]
+    fn test_zero_length_arcs_are_an_error() {
+        let center = Point2::new(1.0, 2.0);
+        for (r, theta0, theta1) in [(1.0, PI / 4.0, PI / 4.0), (2.0, -PI, -PI)] {
+            let error = ArcPath::try_new(center, r, theta0, theta1).err();
+            assert_eq!(error, Some(GeometryError::ZeroLengthArc));
+        }
+        assert!(ArcPath::try_new(center, 1.0, PI / 4.0, -PI / 4.0).is_ok());
+    }
+
+    #[test]
+    fn test_non_positive_radii_are_an_error() {
+        let center = Point2::new(1.0, 2.0);
+        for r in [0.0, -1.0, f64::NAN] {
+            let error = ArcPath::try_new(center, r, 0.0, PI).err();
+            assert_eq!(error, Some(GeometryError::InvalidRadius));
+        }
+    }
+
+    #[test]
+    fn test_bounding_box_is_tight() {
+        let arcs = [
+            new_arc_path![1.0, -2.0, 1.0, -PI / 2.0, PI / 2.0],
+            new_arc_path![3.0, -11.0, 1.0, PI / 3.0, -PI / 7.0],
+            new_arc_path![-5.0, 4.0, 0.5, 0.1, 0.1 + 1.5 * PI],
+            new_arc_path![8.0, -10.0, 2.0, 5.0 * PI / 4.0, -PI / 4.0],
+            new_arc_path![0.0, 0.0, 3.0, 0.2, 1.2],
+            new_arc_path![0.0, 0.0, 3.0, -2.9, 2.9],
+        ];
+        for arc in arcs.iter() {
+            let bounds = arc.bounding_box();
+            let samples = arc.sample_points_num(1000).collect::<Vec<_>>();
+            for p in samples.iter() {
+                assert!(bounds.distance_to(*p) < 1e-12);
+            }
+            // touched by the arc on every side, up to the spacing of the samples
+            let sampled = BoundingBox::from_points(samples);
+            let spacing = arc.length() / 1000.0;
+            assert!((bounds.min - sampled.min).norm() < spacing);
+            assert!((bounds.max - sampled.max).norm() < spacing);
+            // a little way past each side is outside of the box
+            let center = nalgebra::center(&bounds.min, &bounds.max);
+            for v in [
+                Vector2::new(1.0, 0.0),
+                Vector2::new(0.0, 1.0),
+                Vector2::new(-1.0, 0.0),
+                Vector2::new(0.0, -1.0),
+            ] {
+                let half_width = (bounds.max - bounds.min).dot(&v).abs() / 2.0;
+                let p = center + v * (half_width + 0.01);
+                assert!(!bounds.contains(p), "{:?} at {:?}", bounds, p);
+            }
+        }
+    }
+
+    #[test]
+    fn test_normal_is_the_gradient_of_the_sdf() {
+        let arcs = [
+            new_arc_path![0.0, 0.0, 1.0, 0.0, PI / 2.0],
+            new_arc_path![1.0, -1.0, 2.0, PI, PI / 4.0],
+        ];
+        for arc in arcs {
+            // all around the circle, off of it where the sdf changes sign past the ends
+            for k in 0..16 {
+                let theta = k as f64 * PI / 8.0 + 0.1;
+                for rho in [0.5, 0.8, 1.2, 1.5, 3.0].map(|rho| rho * arc.r) {
+                    let p = point_on_ray(&arc, theta, rho);
+                    let normal = arc.normal_at(p);
+                    let expected = finite_difference_normal(&arc, p);
+                    assert!((normal - expected).norm() < 1e-5, "{:?} at {:?}", arc, p);
+                }
+            }
+            // to the right of the arc, as seen from the direction it goes in
+            let t = arc.tangent_at(arc.length() / 2.0);
+            let p = arc.point_at(arc.length() / 2.0);
+            assert!((arc.normal_at(p) - Vector2::new(t.y, -t.x)).norm() < 1e-12);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use crate::utils::traits::Float;
+use nalgebra::Point2;
+
+/// Axis-aligned box, from its corner with the smallest coordinates to the one with the largest
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox<F: Float> {
+    pub min: Point2<F>,
+    pub max: Point2<F>,
+}
+
+impl<F> BoundingBox<F>
+where
+    F: Float,
+{
+    /// Smallest box containing all of the `points`, of which there must be at least one
+    pub fn from_points<I>(points: I) -> Self
+    where
+        I: IntoIterator<Item = Point2<F>>,
+    {
+        let mut points = points.into_iter();
+        let first = points
+            .next()
+            .expect("a bounding box needs at least one point");
+        points.fold(Self::new(first, first), |bounds, p| bounds.including(p))
+    }
+
+    pub fn new(min: Point2<F>, max: Point2<F>) -> Self {
+        Self { min, max }
+    }
+
+    /// Smallest box containing both this box and `p`
+    pub fn including(&self, p: Point2<F>) -> Self {
+        Self {
+            min: Point2::new(
+                num::Float::min(self.min.x, p.x),
+                num::Float::min(self.min.y, p.y),
+            ),
+            max: Point2::new(
+                num::Float::max(self.max.x, p.x),
+                num::Float::max(self.max.y, p.y),
+            ),
+        }
+    }
+
+    /// Whether the two boxes overlap, touching counting as overlapping
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.min.x <= other.max.x
+            && other.min.x <= self.max.x
+            && self.min.y <= other.max.y
+            && other.min.y <= self.max.y
+    }
+
+    pub fn contains(&self, p: Point2<F>) -> bool {
+        self.min.x <= p.x && p.x <= self.max.x && self.min.y <= p.y && p.y <= self.max.y
+    }
+
+    /// Distance from `p` to the closest point of the box, zero inside of it.
+    /// Nothing inside of the box is closer to `p`, so anything it contains
+    /// is farther than `r` when this is larger than `r`
+    pub fn distance_to(&self, p: Point2<F>) -> F {
+        let dx = num::Float::max(
+            num::Float::max(self.min.x - p.x, p.x - self.max.x),
+            F::zero(),
+        );
+        let dy = num::Float::max(
+            num::Float::max(self.min.y - p.y, p.y - self.max.y),
+            F::zero(),
+        );
+        num::Float::hypot(dx, dy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distance_to_the_box() {
+        let bounds = BoundingBox::from_points([
+            Point2::new(1.0, -1.0),
+            Point2::new(-2.0, 0.5),
+            Point2::new(0.0, 2.0),
+        ]);
+        assert_eq!(bounds.min, Point2::new(-2.0, -1.0));
+        assert_eq!(bounds.max, Point2::new(1.0, 2.0));
+        // inside, on the sides and at the corners
+        assert_eq!(bounds.distance_to(Point2::new(0.0, 0.0)), 0.0);
+        assert_eq!(bounds.distance_to(Point2::new(1.0, 2.0)), 0.0);
+        assert_eq!(bounds.distance_to(Point2::new(0.5, 5.0)), 3.0);
+        assert_eq!(bounds.distance_to(Point2::new(-3.5, 0.0)), 1.5);
+        assert_eq!(bounds.distance_to(Point2::new(4.0, -5.0)), 5.0);
+        assert!(bounds.contains(Point2::new(-2.0, 1.0)));
+        assert!(!bounds.contains(Point2::new(-2.0, 2.5)));
+    }
+}
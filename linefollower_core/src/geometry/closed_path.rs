@@ -1,18 +1,45 @@
 use super::arc_path::ArcPath;
+use super::bounding_box::BoundingBox;
+use super::error::GeometryError;
 use super::line_path::LinePath;
+use super::spline_path::SplinePath;
 use crate::new_arc_path;
 use crate::new_line_path;
+use crate::utils::math::menger_curvature;
 use crate::utils::traits::Float;
 use nalgebra::{Point2, Vector2};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 use std::f64::consts::PI;
 
-use super::track::Track;
+use super::track::{sample_offset_points, sample_points, Track};
+
+/// Upper bound on the relaxation sweeps of `racing_line`, on each level of detail
+const RACING_LINE_MAX_SWEEPS: usize = 10_000;
+/// Fewest points `racing_line` starts relaxing on
+const RACING_LINE_COARSEST_POINTS: usize = 8;
+/// Margin around the path in its SVG export, as a fraction of the larger side of its bounding box
+const SVG_MARGIN: f64 = 0.05;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SubPath<F: Float> {
     Arc(ArcPath<F>),
     Line(LinePath<F>),
+    Spline(SplinePath<F>),
+}
+
+impl<F> SubPath<F>
+where
+    F: Float,
+{
+    pub fn bounding_box(&self) -> BoundingBox<F> {
+        match self {
+            SubPath::Arc(arc) => arc.bounding_box(),
+            SubPath::Line(line) => line.bounding_box(),
+            SubPath::Spline(spline) => spline.bounding_box(),
+        }
+    }
 }
 
 impl<F> Track<F> for SubPath<F>
@@ -23,6 +50,15 @@ where
         match self {
             SubPath::Arc(arc) => arc.sdf(p),
             SubPath::Line(line) => line.sdf(p),
+            SubPath::Spline(spline) => spline.sdf(p),
+        }
+    }
+
+    fn normal_at(&self, p: Point2<F>) -> Vector2<F> {
+        match self {
+            SubPath::Arc(arc) => arc.normal_at(p),
+            SubPath::Line(line) => line.normal_at(p),
+            SubPath::Spline(spline) => spline.normal_at(p),
         }
     }
 
@@ -30,6 +66,7 @@ where
         match self {
             SubPath::Arc(arc) => arc.length(),
             SubPath::Line(line) => line.length(),
+            SubPath::Spline(spline) => spline.length(),
         }
     }
 
@@ -37,6 +74,7 @@ where
         match self {
             SubPath::Arc(arc) => arc.point_at(d),
             SubPath::Line(line) => line.point_at(d),
+            SubPath::Spline(spline) => spline.point_at(d),
         }
     }
 
@@ -44,6 +82,15 @@ where
         match self {
             SubPath::Arc(arc) => arc.tangent_at(d),
             SubPath::Line(line) => line.tangent_at(d),
+            SubPath::Spline(spline) => spline.tangent_at(d),
+        }
+    }
+
+    fn curvature_at(&self, d: F) -> F {
+        match self {
+            SubPath::Arc(arc) => arc.curvature_at(d),
+            SubPath::Line(line) => line.curvature_at(d),
+            SubPath::Spline(spline) => spline.curvature_at(d),
         }
     }
 
@@ -51,6 +98,7 @@ where
         match self {
             SubPath::Arc(arc) => arc.point_projection_distance(p),
             SubPath::Line(line) => line.point_projection_distance(p),
+            SubPath::Spline(spline) => spline.point_projection_distance(p),
         }
     }
     // SAME implementation as the default
@@ -77,12 +125,76 @@ where
     }
 }
 
+/// Why a path couldn't be smoothed, along with the index of the offending subpath
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmoothError {
+    /// A tight arc or a corner that isn't between two lines, which can't be replaced by a fillet
+    UnsupportedNeighbors(usize),
+    /// A turn of half a revolution or more, whose fillet would never meet the neighboring lines
+    TurnTooSharp(usize),
+    /// The lines next to a turn are too short for a fillet of the required radius
+    NotEnoughRoom(usize),
+}
+
+impl std::fmt::Display for SmoothError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SmoothError::UnsupportedNeighbors(i) => {
+                write!(f, "subpath {} is not between two lines", i)
+            }
+            SmoothError::TurnTooSharp(i) => write!(f, "the turn at subpath {} is too sharp", i),
+            SmoothError::NotEnoughRoom(i) => {
+                write!(f, "the lines around subpath {} are too short", i)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SmoothError {}
+
+/// Only the subpaths are serialized, `new` computes the rest when deserializing
 #[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "ClosedPathFields<F>", into = "ClosedPathFields<F>")]
 pub struct ClosedPath<F: Float> {
     p0: Point2<F>,
     subpaths: Vec<SubPath<F>>,
     starts: Vec<F>,
     length: F,
+    /// Bounding box of each subpath, to skip the subpaths which are too far in distance queries
+    bounds: Vec<BoundingBox<F>>,
+}
+
+/// Defining fields of a `ClosedPath`, which are all that it serializes. The start, the
+/// arc-lengths and the length which older files also have are ignored, so that they can't go
+/// stale when a subpath is edited by hand
+#[derive(Serialize, Deserialize)]
+struct ClosedPathFields<F: Float> {
+    subpaths: Vec<SubPath<F>>,
+}
+
+impl<F> TryFrom<ClosedPathFields<F>> for ClosedPath<F>
+where
+    F: Float,
+{
+    type Error = GeometryError;
+
+    fn try_from(fields: ClosedPathFields<F>) -> Result<Self, Self::Error> {
+        if !is_valid_closed_path(&fields.subpaths) {
+            return Err(GeometryError::OpenPath);
+        }
+        Ok(Self::new(fields.subpaths))
+    }
+}
+
+impl<F> From<ClosedPath<F>> for ClosedPathFields<F>
+where
+    F: Float,
+{
+    fn from(path: ClosedPath<F>) -> Self {
+        Self {
+            subpaths: path.subpaths,
+        }
+    }
 }
 
 impl<F> ClosedPath<F>
@@ -91,24 +203,49 @@ where
 {
     pub fn new(subpaths: Vec<SubPath<F>>) -> Self {
         debug_assert!(is_valid_closed_path(&subpaths), "invalid closed path");
-        let starts = subpaths
-            .iter()
-            .scan(F::zero(), |state, subpath| {
-                let start = *state;
-                *state += subpath.length();
-                Some(start)
-            })
-            .collect::<Vec<_>>();
-        let length = *starts.last().unwrap() + subpaths.last().unwrap().length();
+        let (starts, length) = cumulative_lengths(&subpaths);
         let p0 = subpaths.first().unwrap().point_at(F::zero());
+        let bounds = subpath_bounds(&subpaths);
         Self {
             p0,
             subpaths,
             starts,
             length,
+            bounds,
         }
     }
 
+    pub fn subpaths(&self) -> &[SubPath<F>] {
+        &self.subpaths
+    }
+
+    /// Replaces the subpath at `index`, keeping the cached `starts`, `length` and bounds in sync.
+    /// The new subpath must connect to its neighbors so that the path stays closed.
+    pub fn replace_subpath(&mut self, index: usize, subpath: SubPath<F>) {
+        self.subpaths[index] = subpath;
+        debug_assert!(is_valid_closed_path(&self.subpaths), "invalid closed path");
+        self.update_caches();
+    }
+
+    fn update_caches(&mut self) {
+        let (starts, length) = cumulative_lengths(&self.subpaths);
+        self.starts = starts;
+        self.length = length;
+        self.p0 = self.subpaths.first().unwrap().point_at(F::zero());
+        self.bounds = subpath_bounds(&self.subpaths);
+    }
+
+    /// Recomputes `starts`, `length` and the bounds from the subpaths and checks that
+    /// they match the cached values. Meant to be called in tests after
+    /// every operation that mutates the path.
+    #[cfg(any(test, debug_assertions))]
+    pub fn validate_caches(&self) -> bool {
+        let (starts, length) = cumulative_lengths(&self.subpaths);
+        starts == self.starts
+            && length == self.length
+            && subpath_bounds(&self.subpaths) == self.bounds
+    }
+
     fn first_subpath_dist(&self, d: F) -> (F, &SubPath<F>) {
         // returns the subpath that contains the point X on the path after traveling a distance d from the start
         // the point X is on the path (x_0, y_0) -> (x_1, y_1)
@@ -120,15 +257,301 @@ where
 
     fn closest_subpath(&self, p: Point2<F>) -> &SubPath<F> {
         // returns the subpath that is closest to the point P
-        let f = |sd| num::Float::abs(sd);
-        self.subpaths
+        &self.subpaths[self.closest_subpath_index(p)]
+    }
+
+    /// Indices of the subpaths whose bounding box overlaps `region`, e.g. the part of the track
+    /// in view, so that the other ones can be skipped
+    pub fn subpaths_in<'a>(
+        &'a self,
+        region: &'a BoundingBox<F>,
+    ) -> impl Iterator<Item = usize> + 'a {
+        self.bounds
             .iter()
-            .min_by(|a, b| {
-                let sd_a = a.sdf(p);
-                let sd_b = b.sdf(p);
-                f(sd_a).partial_cmp(&f(sd_b)).unwrap()
+            .enumerate()
+            .filter(move |(_, bounds)| bounds.intersects(region))
+            .map(|(i, _)| i)
+    }
+
+    /// Box of the whole path
+    pub fn bounding_box(&self) -> BoundingBox<F> {
+        BoundingBox::from_points(
+            self.bounds
+                .iter()
+                .flat_map(|bounds| [bounds.min, bounds.max]),
+        )
+    }
+
+    /// SVG document drawing the path with a line `stroke_width` wide, in world units. Lines
+    /// become `L` commands, arcs `A` commands and splines the cubic Bezier curves (`C`) they
+    /// are made of. The y axis is flipped by a transform, so that it points up like in the world
+    pub fn to_svg(&self, stroke_width: f64) -> String {
+        let mut d = format!("M {} {}", self.p0.x, self.p0.y);
+        for subpath in self.subpaths.iter() {
+            match subpath {
+                SubPath::Line(line) => d += &format!(" L {} {}", line.p1.x, line.p1.y),
+                SubPath::Arc(arc) => d += &svg_arc_commands(arc),
+                SubPath::Spline(spline) => {
+                    for [b1, b2, p] in spline.bezier_segments() {
+                        d += &format!(" C {} {} {} {} {} {}", b1.x, b1.y, b2.x, b2.y, p.x, p.y);
+                    }
+                }
+            }
+        }
+        d += " Z";
+        let bounds = self.bounding_box();
+        let size = bounds.max - bounds.min;
+        let margin = F::from(stroke_width).unwrap()
+            + F::from(SVG_MARGIN).unwrap() * num::Float::max(size.x, size.y);
+        // the view box is in the flipped coordinates, where the top of the path is at -max.y
+        let (x, y) = (bounds.min.x - margin, -bounds.max.y - margin);
+        let two = F::from(2.0).unwrap();
+        let (width, height) = (size.x + two * margin, size.y + two * margin);
+        format!(
+            concat!(
+                "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
+                "  <path d=\"{}\" transform=\"scale(1 -1)\" ",
+                "fill=\"none\" stroke=\"black\" stroke-width=\"{}\"/>\n",
+                "</svg>\n"
+            ),
+            x, y, width, height, d, stroke_width
+        )
+    }
+
+    pub fn closest_subpath_index(&self, p: Point2<F>) -> usize {
+        // returns the index of the subpath that is closest to the point P
+        // ties are broken in favor of the earlier subpath
+        self.closest_subpath_sdf(p).0
+    }
+
+    /// Index of the subpath closest to `p`, along with its sdf at `p`.
+    /// The subpaths whose bounding box is farther from `p` than the closest subpath so far
+    /// can't be any closer, so their sdf is never evaluated
+    fn closest_subpath_sdf(&self, p: Point2<F>) -> (usize, F) {
+        // the first subpath is always evaluated, so that a NaN distance comes out as is
+        let mut closest = (0, self.subpaths[0].sdf(p));
+        let mut min_distance = num::Float::abs(closest.1);
+        for (i, (subpath, bounds)) in self.subpaths.iter().zip(&self.bounds).enumerate().skip(1) {
+            if bounds.distance_to(p) >= min_distance {
+                continue;
+            }
+            let sd = subpath.sdf(p);
+            if num::Float::abs(sd) < min_distance {
+                closest = (i, sd);
+                min_distance = num::Float::abs(sd);
+            }
+        }
+        closest
+    }
+
+    /// Largest curvature along the path, i.e. the inverse of the tightest arc's radius.
+    /// Corners (where the tangent jumps between two subpaths) count as infinite curvature.
+    pub fn max_curvature(&self) -> F {
+        let n = self.subpaths.len();
+        let mut max = F::zero();
+        for (i, subpath) in self.subpaths.iter().enumerate() {
+            match subpath {
+                SubPath::Arc(arc) => max = num::Float::max(max, F::one() / arc.r),
+                SubPath::Spline(spline) => max = num::Float::max(max, spline.max_curvature()),
+                SubPath::Line(_) => {}
+            }
+            if num::Float::abs(turn_angle(subpath, &self.subpaths[(i + 1) % n]))
+                > corner_tolerance()
+            {
+                return F::infinity();
+            }
+        }
+        max
+    }
+
+    /// Returns a path whose curvature is at most `1 / min_radius`, so that a robot with
+    /// that turning radius can follow it. Arcs tighter than `min_radius` are replaced by
+    /// arcs of radius `min_radius` tangent to the same neighboring lines, and corners
+    /// between two lines are rounded by such a fillet, shortening the lines accordingly.
+    pub fn smooth(&self, min_radius: F) -> Result<ClosedPath<F>, SmoothError> {
+        let n = self.subpaths.len();
+        let pi = F::from(PI).unwrap();
+        // first turn the tight arcs back into corners, by extending their lines until they meet
+        let mut subpaths: Vec<(usize, SubPath<F>)> =
+            self.subpaths.iter().cloned().enumerate().collect();
+        for (i, subpath) in self.subpaths.iter().enumerate() {
+            let arc = match subpath {
+                SubPath::Arc(arc) if arc.r < min_radius => arc,
+                _ => continue,
+            };
+            let (prev, next) = ((i + n - 1) % n, (i + 1) % n);
+            let (prev_line, next_line) = match (&subpaths[prev].1, &subpaths[next].1) {
+                (SubPath::Line(a), SubPath::Line(b)) if prev != next => (a.clone(), b.clone()),
+                _ => return Err(SmoothError::UnsupportedNeighbors(i)),
+            };
+            let sweep = num::Float::abs(arc.theta1 - arc.theta0);
+            if sweep >= pi {
+                return Err(SmoothError::TurnTooSharp(i));
+            }
+            let two = F::one() + F::one();
+            let corner = arc.first_point()
+                + arc.tangent_at(F::zero()) * arc.r * num::Float::tan(sweep / two);
+            subpaths[prev].1 = SubPath::Line(LinePath::new(prev_line.p0, corner));
+            subpaths[next].1 = SubPath::Line(LinePath::new(corner, next_line.p1));
+        }
+        let subpaths: Vec<(usize, SubPath<F>)> = subpaths
+            .into_iter()
+            .filter(|(i, _)| match &self.subpaths[*i] {
+                SubPath::Arc(arc) => arc.r >= min_radius,
+                SubPath::Line(_) | SubPath::Spline(_) => true,
+            })
+            .collect();
+
+        // then round every corner, keeping track of how much of each line the fillets use up
+        let m = subpaths.len();
+        let mut trim_start = vec![F::zero(); m];
+        let mut trim_end = vec![F::zero(); m];
+        let mut fillets: Vec<Option<ArcPath<F>>> = vec![None; m];
+        for (j, (index, a)) in subpaths.iter().enumerate() {
+            let k = (j + 1) % m;
+            let b = &subpaths[k].1;
+            let turn = turn_angle(a, b);
+            if num::Float::abs(turn) <= corner_tolerance() {
+                continue;
+            }
+            let line_a = match (a, b) {
+                (SubPath::Line(line_a), SubPath::Line(_)) if j != k => line_a,
+                _ => return Err(SmoothError::UnsupportedNeighbors(*index)),
+            };
+            if num::Float::abs(turn) >= pi - corner_tolerance() {
+                return Err(SmoothError::TurnTooSharp(*index));
+            }
+            let two = F::one() + F::one();
+            let setback = min_radius * num::Float::tan(num::Float::abs(turn) / two);
+            let dir_a = line_a.tangent_at(F::zero());
+            let start = line_a.p1 - dir_a * setback;
+            // the center is on the inner side of the turn
+            let normal = Vector2::new(-dir_a.y, dir_a.x) * num::Float::signum(turn);
+            let center = start + normal * min_radius;
+            let v = start - center;
+            let theta0 = num::Float::atan2(v.y, v.x);
+            fillets[j] = Some(ArcPath::new(center, min_radius, theta0, theta0 + turn));
+            trim_end[j] = setback;
+            trim_start[k] = setback;
+        }
+
+        let mut smoothed = Vec::with_capacity(m * 2);
+        for (j, (index, subpath)) in subpaths.iter().enumerate() {
+            match subpath {
+                SubPath::Line(line) => {
+                    if trim_start[j] + trim_end[j] >= line.length {
+                        return Err(SmoothError::NotEnoughRoom(*index));
+                    }
+                    let dir = line.tangent_at(F::zero());
+                    smoothed.push(SubPath::Line(LinePath::new(
+                        line.p0 + dir * trim_start[j],
+                        line.p1 - dir * trim_end[j],
+                    )));
+                }
+                SubPath::Arc(_) | SubPath::Spline(_) => smoothed.push(subpath.clone()),
+            }
+            if let Some(fillet) = fillets[j].take() {
+                smoothed.push(SubPath::Arc(fillet));
+            }
+        }
+        Ok(ClosedPath::new(smoothed))
+    }
+
+    /// Whether the path goes around counterclockwise, i.e. the area it encloses is on its left
+    pub fn is_counterclockwise(&self) -> bool {
+        let dx = self.length / F::from(1000.0).unwrap();
+        let points = sample_points(self, dx).collect::<Vec<_>>();
+        // shoelace formula, twice the signed area
+        let area = points
+            .iter()
+            .zip(points.iter().cycle().skip(1))
+            .fold(F::zero(), |area, (p, q)| area + p.x * q.y - q.x * p.y);
+        area > F::zero()
+    }
+
+    /// Inner and outer edges of a track of width `2 * half_width` centered on the path,
+    /// sampled every `dx`. Used to draw lane boundaries and to keep robots within them.
+    pub fn edges(&self, half_width: F, dx: F) -> (Vec<Point2<F>>, Vec<Point2<F>>) {
+        let left = sample_offset_points(self, dx, half_width).collect::<Vec<_>>();
+        let right = sample_offset_points(self, dx, -half_width).collect::<Vec<_>>();
+        if self.is_counterclockwise() {
+            (left, right)
+        } else {
+            (right, left)
+        }
+    }
+
+    /// Smoother line through a track of width `2 * half_width` centered on the path, which
+    /// cuts the corners to reduce its curvature. The path is sampled at `num_points` points,
+    /// each of which may move sideways within the lane. They are relaxed one at a time so that
+    /// the curvature at each of them becomes the average of the curvature at its neighbours,
+    /// which spreads the turns over the straights, until the line settles.
+    /// The result goes through the relaxed points.
+    pub fn racing_line(&self, half_width: F, num_points: usize) -> SplinePath<F> {
+        assert!(num_points >= 5, "the racing line needs at least 5 points");
+        let n = num_points;
+        let step = self.length / F::from_usize(n).unwrap();
+        let (centers, normals): (Vec<_>, Vec<_>) = (0..n)
+            .map(|i| {
+                let d = F::from_usize(i).unwrap() * step;
+                let t = self.tangent_at(d);
+                (self.point_at(d), Vector2::new(-t.y, t.x))
             })
-            .unwrap()
+            .unzip();
+        let mut offsets = vec![F::zero(); n];
+        // the relaxation only evens out the curvature locally, so on its own it takes forever
+        // to move whole stretches of the line: it is first done on every few points only
+        let mut stride = 1;
+        while n / (2 * stride) >= RACING_LINE_COARSEST_POINTS {
+            stride *= 2;
+        }
+        loop {
+            let active = (0..n).step_by(stride).collect::<Vec<_>>();
+            relax_racing_line(&centers, &normals, &mut offsets, &active, half_width);
+            if stride == 1 {
+                break;
+            }
+            // the points in between start on the straight line joining the relaxed ones
+            for (k, &a) in active.iter().enumerate() {
+                let b = active.get(k + 1).copied().unwrap_or(n);
+                let (offset_a, offset_b) = (offsets[a], offsets[b % n]);
+                for j in 1..b - a {
+                    let t = F::from_usize(j).unwrap() / F::from_usize(b - a).unwrap();
+                    offsets[a + j] = offset_a + (offset_b - offset_a) * t;
+                }
+            }
+            stride /= 2;
+        }
+        SplinePath::new(
+            centers
+                .iter()
+                .zip(normals.iter().zip(offsets))
+                .map(|(c, (n, offset))| *c + n * offset)
+                .collect(),
+        )
+    }
+
+    /// Arc-length parameter of the point on the path which is nearest to `p`.
+    /// This is the inverse of `point_at` for points that lie on the path.
+    pub fn arc_length_of(&self, p: Point2<F>) -> F {
+        let i = self.closest_subpath_index(p);
+        let subpath = &self.subpaths[i];
+        // the local projection may fall slightly outside the subpath near its endpoints
+        let local_d = num::Float::min(
+            num::Float::max(subpath.point_projection_distance(p), F::zero()),
+            subpath.length(),
+        );
+        (self.starts[i] + local_d) % self.length
+    }
+}
+
+impl<F> From<SplinePath<F>> for ClosedPath<F>
+where
+    F: Float,
+{
+    /// A closed path made of the spline alone, which is already closed
+    fn from(spline: SplinePath<F>) -> Self {
+        ClosedPath::new(vec![SubPath::Spline(spline)])
     }
 }
 
@@ -138,12 +561,12 @@ where
 {
     fn sdf(&self, p: Point2<F>) -> F {
         // returns the sdf of the path which is closest to the point P
-        let f = |sd| num::Float::abs(sd);
-        self.subpaths
-            .iter()
-            .map(|subpath| subpath.sdf(p))
-            .min_by(|a, b| f(*a).partial_cmp(&f(*b)).unwrap())
-            .unwrap()
+        self.closest_subpath_sdf(p).1
+    }
+
+    fn normal_at(&self, p: Point2<F>) -> Vector2<F> {
+        // the sdf is the closest subpath's, and so is its gradient
+        self.closest_subpath(p).normal_at(p)
     }
 
     fn length(&self) -> F {
@@ -168,8 +591,15 @@ where
         subpath.tangent_at(x)
     }
 
-    fn point_projection_distance(&self, _p: Point2<F>) -> F {
-        todo!()
+    fn curvature_at(&self, d: F) -> F {
+        let (x, subpath) = self.first_subpath_dist(d);
+        subpath.curvature_at(x)
+    }
+
+    fn point_projection_distance(&self, p: Point2<F>) -> F {
+        // at a junction, the earlier subpath is the closest one, which gives the same
+        // distance as the start of the next one; the start of the path gives 0, not the length
+        self.arc_length_of(p)
     }
 
     fn point_projection_tangent(&self, p: Point2<F>) -> Vector2<F> {
@@ -178,6 +608,126 @@ where
     }
 }
 
+/// Relaxes the offsets of the `active` points of a racing line, each towards the offset at which
+/// the line's curvature there is the average of the curvature at the two neighbouring points
+fn relax_racing_line<F>(
+    centers: &[Point2<F>],
+    normals: &[Vector2<F>],
+    offsets: &mut [F],
+    active: &[usize],
+    half_width: F,
+) where
+    F: Float,
+{
+    let m = active.len();
+    let mut points = active
+        .iter()
+        .map(|&i| centers[i] + normals[i] * offsets[i])
+        .collect::<Vec<_>>();
+    let tolerance = half_width * F::from(1e-6).unwrap();
+    let nudge = half_width * F::from(1e-3).unwrap();
+    let half = F::from(0.5).unwrap();
+    for _ in 0..RACING_LINE_MAX_SWEEPS {
+        let mut max_change = F::zero();
+        for (k, &i) in active.iter().enumerate() {
+            let at = |j: usize| points[(k + m + j - 2) % m];
+            let target = (menger_curvature(at(0), at(1), at(2))
+                + menger_curvature(at(2), at(3), at(4)))
+                * half;
+            let (prev, next) = (at(1), at(3));
+            // the curvature at the point is about linear in its offset, for small moves
+            let current = menger_curvature(prev, points[k], next);
+            let nudged = menger_curvature(prev, points[k] + normals[i] * nudge, next);
+            let slope = (nudged - current) / nudge;
+            if slope == F::zero() {
+                continue;
+            }
+            let offset = offsets[i] + (target - current) / slope;
+            let offset = num::Float::min(num::Float::max(offset, -half_width), half_width);
+            max_change = num::Float::max(max_change, num::Float::abs(offset - offsets[i]));
+            offsets[i] = offset;
+            points[k] = centers[i] + normals[i] * offset;
+        }
+        if max_change < tolerance {
+            break;
+        }
+    }
+}
+
+/// Signed angle by which the direction changes from the end of `a` to the start of `b`,
+/// positive for left turns
+fn turn_angle<F>(a: &SubPath<F>, b: &SubPath<F>) -> F
+where
+    F: Float,
+{
+    let t0 = a.tangent_at(a.length());
+    let t1 = b.tangent_at(F::zero());
+    let cross = t0.x * t1.y - t0.y * t1.x;
+    num::Float::atan2(cross, t0.dot(&t1))
+}
+
+/// Direction changes below this (in radians) are not considered corners
+fn corner_tolerance<F>() -> F
+where
+    F: Float,
+{
+    F::from(1e-6).unwrap()
+}
+
+/// SVG `A` command from the current point, the start of `arc`, to its end. Full circles, whose
+/// ends are the same point which SVG draws no arc between, are split into two halves
+fn svg_arc_commands<F>(arc: &ArcPath<F>) -> String
+where
+    F: Float,
+{
+    let pi = F::from(PI).unwrap();
+    let sweep = num::Float::abs(arc.theta1 - arc.theta0);
+    let two = F::from(2.0).unwrap();
+    if sweep >= two * pi - corner_tolerance() {
+        let middle = (arc.theta0 + arc.theta1) / two;
+        return [(arc.theta0, middle), (middle, arc.theta1)]
+            .into_iter()
+            .map(|(theta0, theta1)| {
+                svg_arc_commands(&ArcPath::new(arc.center, arc.r, theta0, theta1))
+            })
+            .collect();
+    }
+    // the sweep flag is set for arcs in the direction of increasing angles, counterclockwise
+    // in the world coordinates the path is given in
+    let large_arc = u8::from(sweep > pi);
+    let counterclockwise = u8::from(arc.curvature_at(F::zero()) > F::zero());
+    let end = arc.last_point();
+    format!(
+        " A {} {} 0 {} {} {} {}",
+        arc.r, arc.r, large_arc, counterclockwise, end.x, end.y
+    )
+}
+
+/// Bounding box of each subpath, in order
+fn subpath_bounds<F>(subpaths: &[SubPath<F>]) -> Vec<BoundingBox<F>>
+where
+    F: Float,
+{
+    subpaths.iter().map(SubPath::bounding_box).collect()
+}
+
+/// Returns the arc-length at which each subpath starts, along with the total length
+fn cumulative_lengths<F>(subpaths: &[SubPath<F>]) -> (Vec<F>, F)
+where
+    F: Float,
+{
+    let starts = subpaths
+        .iter()
+        .scan(F::zero(), |state, subpath| {
+            let start = *state;
+            *state += subpath.length();
+            Some(start)
+        })
+        .collect::<Vec<_>>();
+    let length = *starts.last().unwrap() + subpaths.last().unwrap().length();
+    (starts, length)
+}
+
 pub fn predefined_closed_path() -> ClosedPath<f64> {
     ClosedPath::new(vec![
         SubPath::Line(new_line_path![0.0, -4.0, 8.0, -4.0]),
@@ -194,6 +744,64 @@ pub fn predefined_closed_path() -> ClosedPath<f64> {
     ])
 }
 
+/// A counterclockwise circle centered at the origin, starting from its lowest point
+pub fn predefined_circle(radius: f64) -> ClosedPath<f64> {
+    ClosedPath::new(vec![
+        SubPath::Arc(new_arc_path![0.0, 0.0, radius, -PI / 2.0, PI / 2.0]),
+        SubPath::Arc(new_arc_path![0.0, 0.0, radius, PI / 2.0, 3.0 * PI / 2.0]),
+    ])
+}
+
+/// All the predefined tracks, along with a name for each of them
+pub fn predefined_tracks() -> Vec<(&'static str, ClosedPath<f64>)> {
+    vec![
+        ("default", predefined_closed_path()),
+        ("circle", predefined_circle(2.0)),
+    ]
+}
+
+/// Generates a random closed path from a seed, useful for testing on many different geometries.
+///
+/// The path is made of random lines and arcs, each continuing in the direction
+/// the previous one ended, plus a final line which closes the loop back to the start.
+pub fn random_closed_path(seed: u64, num_segments: usize) -> ClosedPath<f64> {
+    assert!(
+        num_segments >= 2,
+        "a closed path needs at least two segments"
+    );
+    let mut rng = StdRng::seed_from_u64(seed);
+    let start = Point2::new(0.0, 0.0);
+    let mut p = start;
+    let mut heading: f64 = 0.0;
+    let mut subpaths = Vec::with_capacity(num_segments);
+    for _ in 0..num_segments - 1 {
+        let subpath = if rng.gen_bool(0.5) {
+            let length = rng.gen_range(0.5..2.0);
+            let dir = Vector2::new(heading.cos(), heading.sin());
+            SubPath::Line(LinePath::new(p, p + dir * length))
+        } else {
+            let r = rng.gen_range(0.5..2.0);
+            let sweep = rng.gen_range(PI / 6.0..PI / 2.0);
+            // favor left turns so that the path tends to wind around instead of wandering off
+            if rng.gen_bool(0.7) {
+                let center = p + Vector2::new(-heading.sin(), heading.cos()) * r;
+                let theta0 = heading - PI / 2.0;
+                heading += sweep;
+                SubPath::Arc(ArcPath::new(center, r, theta0, theta0 + sweep))
+            } else {
+                let center = p + Vector2::new(heading.sin(), -heading.cos()) * r;
+                let theta0 = heading + PI / 2.0;
+                heading -= sweep;
+                SubPath::Arc(ArcPath::new(center, r, theta0, theta0 - sweep))
+            }
+        };
+        p = subpath.last_point();
+        subpaths.push(subpath);
+    }
+    subpaths.push(SubPath::Line(LinePath::new(p, start)));
+    ClosedPath::new(subpaths)
+}
+
 pub fn is_valid_closed_path<F>(subpaths: &[SubPath<F>]) -> bool
 where
     F: Float,
@@ -201,7 +809,8 @@ where
     // checks if the subpaths form a valid closed path
     // a valid closed path is a path that starts and ends at the same point
     // and the subpaths are connected to each other
-    if subpaths.len() < 2 {
+    // a single subpath is only valid if it closes on itself, like a spline path
+    if subpaths.is_empty() {
         return false;
     }
     let mut it = subpaths.iter();
@@ -236,4 +845,548 @@ pub mod tests {
         let path = predefined_closed_path();
         assert!(is_valid_closed_path(&path.subpaths));
     }
+
+    #[test]
+    fn test_point_at_time_constant_speed() {
+        use crate::geometry::speed_profile::SpeedProfile;
+        let path = predefined_closed_path();
+        let speed = 1.3;
+        let profile = SpeedProfile::Constant(speed);
+        for i in 0..100 {
+            let t = i as f64 * 0.37;
+            let expected = path.point_at(speed * t);
+            let actual = path.point_at_time(t, &profile);
+            assert!((expected - actual).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_curvature_sign_follows_the_direction_of_the_arcs() {
+        let path = predefined_closed_path();
+        let mut start = 0.0;
+        for subpath in path.subpaths() {
+            let middle = start + subpath.length() / 2.0;
+            let curvature = path.curvature_at(middle);
+            match subpath {
+                SubPath::Line(_) => assert_eq!(curvature, 0.0),
+                SubPath::Arc(arc) => {
+                    // positive when turning left, counterclockwise, negative when turning right
+                    let expected = (arc.theta1 - arc.theta0).signum() / arc.r;
+                    assert_eq!(curvature, expected, "{:?}", arc);
+                    let h = 1e-3;
+                    let (before, after) =
+                        (path.tangent_at(middle - h), path.tangent_at(middle + h));
+                    let turn = before.x * after.y - before.y * after.x;
+                    assert_eq!(turn.signum(), curvature.signum(), "{:?}", arc);
+                }
+                SubPath::Spline(_) => unreachable!(),
+            }
+            start += subpath.length();
+        }
+        // the first arc turns right, down around the bottom right corner, the next one left
+        assert!(matches!(path.subpaths()[2], SubPath::Arc(_)));
+        assert_eq!(path.curvature_at(13.0 + PI / 4.0), -1.0);
+        assert!(matches!(path.subpaths()[4], SubPath::Arc(_)));
+        assert_eq!(path.curvature_at(17.0 + PI / 2.0 + PI / 2.0), 1.0);
+    }
+
+    #[test]
+    fn test_replace_subpath_keeps_caches_consistent() {
+        let mut path = predefined_closed_path();
+        assert!(path.validate_caches());
+        let old_length = path.length();
+        // swap the straight top edge (8, 0) -> (0, 0) for a semicircle bulging upwards
+        path.replace_subpath(9, SubPath::Arc(new_arc_path![4.0, 0.0, 4.0, 0.0, PI]));
+        assert!(path.validate_caches());
+        let expected_length = old_length - 8.0 + 4.0 * PI;
+        assert!((path.length() - expected_length).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_serde_recomputes_the_arc_lengths() {
+        let path = predefined_circle(2.0);
+        let mut json = serde_json::to_value(&path).unwrap();
+        let keys = json.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(keys, ["subpaths"]);
+        // both halves edited by hand, along with the stale caches of older files
+        for subpath in json["subpaths"].as_array_mut().unwrap() {
+            subpath["Arc"]["r"] = serde_json::json!(3.0);
+        }
+        json["p0"] = serde_json::to_value(path.p0).unwrap();
+        json["starts"] = serde_json::to_value(&path.starts).unwrap();
+        json["length"] = serde_json::json!(path.length());
+        let edited: ClosedPath<f64> = serde_json::from_value(json.clone()).unwrap();
+        assert!(edited.validate_caches());
+        assert!((edited.length() - 6.0 * PI).abs() < 1e-12);
+        assert!((edited.point_at(0.0) - Point2::new(0.0, -3.0)).norm() < 1e-12);
+        assert!((edited.point_at(3.0 * PI) - Point2::new(0.0, 3.0)).norm() < 1e-12);
+        assert!((edited.point_projection_distance(Point2::new(-3.0, 0.0)) - 4.5 * PI).abs() < 1e-9);
+
+        // a single edited half leaves a gap
+        json["subpaths"][0]["Arc"]["r"] = serde_json::json!(2.0);
+        assert!(serde_json::from_value::<ClosedPath<f64>>(json).is_err());
+    }
+
+    #[test]
+    fn test_edges_are_at_half_width_from_the_centerline() {
+        let half_width = 0.1;
+        // the smoothed path has no corners, where the offset curves would overlap or leave gaps
+        let smoothed = predefined_closed_path().smooth(0.5).unwrap();
+        let circle = predefined_circle(2.0);
+        let clockwise_circle = ClosedPath::new(vec![
+            SubPath::Arc(new_arc_path![0.0, 0.0, 2.0, -PI / 2.0, -3.0 * PI / 2.0]),
+            SubPath::Arc(new_arc_path![
+                0.0,
+                0.0,
+                2.0,
+                -3.0 * PI / 2.0,
+                -5.0 * PI / 2.0
+            ]),
+        ]);
+        assert!(smoothed.is_counterclockwise());
+        assert!(circle.is_counterclockwise());
+        assert!(!clockwise_circle.is_counterclockwise());
+        for path in [smoothed, circle, clockwise_circle] {
+            // the inner edge is on the left of a counterclockwise path, where the sdf is negative
+            let inner_sdf = if path.is_counterclockwise() {
+                -half_width
+            } else {
+                half_width
+            };
+            let (inner, outer) = path.edges(half_width, 0.05);
+            assert_eq!(inner.len(), outer.len());
+            assert!(inner.len() >= (path.length() / 0.05) as usize - 1);
+            for p in inner.iter() {
+                assert!((path.sdf(*p) - inner_sdf).abs() < 1e-9, "{}", path.sdf(*p));
+            }
+            for p in outer.iter() {
+                assert!((path.sdf(*p) + inner_sdf).abs() < 1e-9, "{}", path.sdf(*p));
+            }
+        }
+    }
+
+    #[test]
+    fn test_point_projection_distance_near_junctions() {
+        fn projection<T: Track<f64>>(track: &T, p: Point2<f64>) -> f64 {
+            track.point_projection_distance(p)
+        }
+        let path = predefined_closed_path();
+        // the first line, (0, -4) -> (8, -4), meets the second one, (8, -4) -> (8, -9)
+        let cases = [
+            (Point2::new(8.0, -4.0), 8.0),
+            (Point2::new(7.9, -3.9), 7.9),
+            (Point2::new(8.1, -4.3), 8.3),
+            // start of the path, where the last arc meets the first line
+            (Point2::new(0.0, -4.0), 0.0),
+            (Point2::new(0.5, -4.1), 0.5),
+        ];
+        for (p, expected) in cases {
+            let d = projection(&path, p);
+            assert!(
+                (d - expected).abs() < 1e-9,
+                "expected {}, got {}",
+                expected,
+                d
+            );
+        }
+    }
+
+    #[test]
+    fn test_robot_follows_a_closed_spline_path() {
+        use crate::ode_solver::ode_system::Vector;
+        use crate::simulation::robot::OPTIMIZED_CONFIG;
+        use crate::simulation::run::run_headless;
+        use std::sync::Arc;
+
+        let waypoints = [
+            (0.0, -4.0),
+            (6.0, -4.5),
+            (9.0, -1.0),
+            (6.0, 2.0),
+            (1.0, 1.5),
+            (-2.0, -1.0),
+        ]
+        .map(|(x, y)| Point2::new(x, y));
+        let path: ClosedPath<f64> = SplinePath::new(waypoints.to_vec()).into();
+        assert!(is_valid_closed_path(&path.subpaths));
+        assert!(path.max_curvature().is_finite());
+
+        let heading = path.tangent_at(0.0);
+        let x0 = Vector::<7>::from_column_slice(&[
+            0.0,
+            -4.0,
+            heading.y.atan2(heading.x),
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+        ]);
+        let length = path.length();
+        let result = run_headless(x0, &OPTIMIZED_CONFIG, Arc::new(path), 1.0 / 240.0, 25.0);
+        assert!(!result.stats.diverged);
+        assert!(result.stats.completed_laps >= 1, "{:?}", result.stats);
+        assert!(result.stats.max_abs_error < 0.3, "{:?}", result.stats);
+        assert!(result.stats.progress > length);
+    }
+
+    #[test]
+    fn test_arc_length_of_inverts_point_at() {
+        let path = predefined_closed_path();
+        for d in [1.0, 5.5, 9.2, 14.0, 20.0, 27.3, 33.0, 41.7, 50.0, 55.0] {
+            let p = path.point_at(d);
+            let recovered = path.arc_length_of(p);
+            assert!(
+                (recovered - d).abs() < 1e-6,
+                "expected {}, got {}",
+                d,
+                recovered
+            );
+        }
+    }
+
+    #[test]
+    fn test_random_closed_paths_are_valid() {
+        for seed in 0..50 {
+            for num_segments in [2, 5, 12] {
+                let path = random_closed_path(seed, num_segments);
+                assert!(is_valid_closed_path(&path.subpaths));
+                assert_eq!(path.subpaths.len(), num_segments);
+            }
+        }
+    }
+
+    #[test]
+    fn test_points_on_random_paths_have_zero_sdf() {
+        let mut rng = StdRng::seed_from_u64(2222);
+        for seed in 0..100 {
+            let num_segments = rng.gen_range(2..16);
+            let path = random_closed_path(seed, num_segments);
+            // random positions, plus the junctions between subpaths where
+            // the arcs' angular bounds checks are the most fragile
+            let random_ds = (0..200).map(|_| rng.gen_range(0.0..path.length()));
+            let junction_ds = path.starts.iter().flat_map(|&s| [s - 1e-12, s, s + 1e-12]);
+            let ds = random_ds.chain(junction_ds).collect::<Vec<_>>();
+            for d in ds {
+                let sd = path.sdf(path.point_at(d));
+                assert!(sd.abs() < 1e-9, "seed {}: sdf at d = {} is {}", seed, d, sd);
+                let t = path.tangent_at(d);
+                assert!((t.norm() - 1.0).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sdf_is_finite_everywhere_on_random_paths() {
+        let mut rng = StdRng::seed_from_u64(2265);
+        let mut paths = (0..200)
+            .map(|seed| random_closed_path(seed, rng.gen_range(2..16)))
+            .collect::<Vec<_>>();
+        // splines and smoothed corners too
+        paths.push(predefined_closed_path().smooth(0.5).unwrap());
+        paths.push(random_closed_path(7, 8).racing_line(0.1, 40).into());
+        for (i, path) in paths.iter().enumerate() {
+            let mut points = Vec::new();
+            // uniformly around the path, then at scales up to the extreme coordinates
+            for scale in [1e-6, 1.0, 10.0, 1e3, 1e8, 1e50, 1e150, 1e300, f64::MAX] {
+                for _ in 0..50 {
+                    let (x, y): (f64, f64) = (rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+                    points.push(Point2::new(x * scale, y * scale));
+                }
+            }
+            // the degenerate spots of each kind of subpath: the centers of the arcs, where every
+            // direction is the closest one, and the junctions, where the bounds checks switch
+            for subpath in path.subpaths.iter() {
+                if let SubPath::Arc(arc) = subpath {
+                    points.push(arc.center);
+                }
+                points.push(subpath.first_point());
+                points.push(subpath.last_point());
+            }
+            for extreme in [f64::MAX, f64::MIN, f64::MIN_POSITIVE, 0.0, -0.0] {
+                points.push(Point2::new(extreme, extreme));
+                points.push(Point2::new(extreme, 1.0));
+                points.push(Point2::new(-1.0, extreme));
+            }
+            for p in points {
+                let sd = path.sdf(p);
+                assert!(sd.is_finite(), "path {}: sdf at {:?} is {}", i, p, sd);
+            }
+        }
+    }
+
+    #[test]
+    fn test_normal_follows_the_closest_subpath() {
+        use crate::geometry::track::finite_difference_normal;
+        let paths = [
+            predefined_closed_path(),
+            predefined_closed_path().smooth(0.5).unwrap(),
+            random_closed_path(3, 8),
+        ];
+        for path in paths {
+            let n = 60;
+            for k in 0..n {
+                let d = (k as f64 + 0.5) * path.length() / n as f64;
+                let (p, t) = (path.point_at(d), path.tangent_at(d));
+                let right = Vector2::new(t.y, -t.x);
+                for offset in [-0.05, 0.05] {
+                    let q = p + right * offset;
+                    let normal = path.normal_at(q);
+                    assert!((normal - finite_difference_normal(&path, q)).norm() < 1e-6);
+                    assert!((normal - right).norm() < 1e-9, "at {:?}", q);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_culled_sdf_matches_every_subpath() {
+        let mut rng = StdRng::seed_from_u64(2269);
+        let mut paths = (0..50)
+            .map(|seed| random_closed_path(seed, rng.gen_range(2..16)))
+            .collect::<Vec<_>>();
+        paths.push(predefined_closed_path());
+        paths.push(predefined_closed_path().smooth(0.5).unwrap());
+        paths.push(random_closed_path(7, 8).racing_line(0.1, 40).into());
+        for (i, path) in paths.iter().enumerate() {
+            assert!(path.validate_caches());
+            for _ in 0..200 {
+                let scale = [0.1, 1.0, 5.0, 20.0][rng.gen_range(0..4)];
+                let (x, y): (f64, f64) = (rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+                let p = Point2::new(x * scale, y * scale);
+                // against the sdf of every one of the subpaths
+                let (closest, sd) = path
+                    .subpaths
+                    .iter()
+                    .map(|subpath| subpath.sdf(p))
+                    .enumerate()
+                    .min_by(|(_, a), (_, b)| a.abs().partial_cmp(&b.abs()).unwrap())
+                    .unwrap();
+                assert_eq!(path.sdf(p), sd, "path {} at {:?}", i, p);
+                assert_eq!(path.closest_subpath_index(p), closest);
+            }
+        }
+        // culling never hides a NaN distance
+        assert!(paths[0].sdf(Point2::new(f64::NAN, 0.0)).is_nan());
+    }
+
+    #[test]
+    fn test_subpaths_in_a_region() {
+        let path = predefined_closed_path();
+        // a thin window across the first straight, (0, -4) -> (8, -4), away from its ends
+        let window = BoundingBox::new(Point2::new(3.0, -4.5), Point2::new(4.0, -3.5));
+        assert_eq!(path.subpaths_in(&window).collect::<Vec<_>>(), [0]);
+        // nothing far away from the track, and everything when the whole track is in view
+        let far = BoundingBox::new(Point2::new(100.0, 100.0), Point2::new(101.0, 101.0));
+        assert_eq!(path.subpaths_in(&far).count(), 0);
+        let all = BoundingBox::new(Point2::new(-100.0, -100.0), Point2::new(100.0, 100.0));
+        assert_eq!(path.subpaths_in(&all).count(), path.subpaths().len());
+        // every subpath which has a point in the region is kept
+        for subpath in path.subpaths() {
+            let p = subpath.point_at(0.5 * subpath.length());
+            let region = BoundingBox::new(p, p);
+            let kept = path.subpaths_in(&region).collect::<Vec<_>>();
+            assert!(kept.iter().any(|&i| path.subpaths[i].sdf(p).abs() < 1e-9));
+        }
+    }
+
+    /// Commands of the `d` attribute of the SVG path, each with its numbers
+    fn svg_commands(svg: &str) -> Vec<(String, Vec<f64>)> {
+        let start = svg.find(" d=\"").unwrap() + 4;
+        let d = &svg[start..start + svg[start..].find('"').unwrap()];
+        let mut commands: Vec<(String, Vec<f64>)> = Vec::new();
+        for token in d.split_whitespace() {
+            match token.parse::<f64>() {
+                Ok(x) => commands.last_mut().unwrap().1.push(x),
+                Err(_) => commands.push((token.to_owned(), Vec::new())),
+            }
+        }
+        commands
+    }
+
+    /// Center of an SVG arc of radius `r` from `p1` to `p2` with the given flags,
+    /// as SVG computes it (section F.6.5 of the specification)
+    fn svg_arc_center(
+        p1: Point2<f64>,
+        p2: Point2<f64>,
+        r: f64,
+        large_arc: bool,
+        sweep: bool,
+    ) -> Point2<f64> {
+        let half_chord = (p1 - p2) / 2.0;
+        let squared = half_chord.norm_squared();
+        let factor = ((r * r - squared).max(0.0) / squared).sqrt();
+        let sign = if large_arc != sweep { 1.0 } else { -1.0 };
+        nalgebra::center(&p1, &p2) + Vector2::new(half_chord.y, -half_chord.x) * (sign * factor)
+    }
+
+    #[test]
+    fn test_svg_export_draws_lines_and_arcs() {
+        // a three-quarter arc, which needs the large arc flag, closed by two lines
+        let keyhole = ClosedPath::new(vec![
+            SubPath::Arc(new_arc_path![0.0, 0.0, 1.0, -PI / 2.0, PI]),
+            SubPath::Line(new_line_path![-1.0, 0.0, -1.0, -1.0]),
+            SubPath::Line(new_line_path![-1.0, -1.0, 0.0, -1.0]),
+        ]);
+        for path in [predefined_closed_path(), keyhole] {
+            let svg = path.to_svg(0.05);
+            assert!(svg.starts_with("<svg ") && svg.ends_with("</svg>\n"));
+            let commands = svg_commands(&svg);
+            let count = |name: &str| commands.iter().filter(|(c, _)| c == name).count();
+            let arcs = path
+                .subpaths()
+                .iter()
+                .filter_map(|subpath| match subpath {
+                    SubPath::Arc(arc) => Some(arc),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(count("A"), arcs.len());
+            assert_eq!(count("L"), path.subpaths().len() - arcs.len());
+            assert_eq!(commands[0], ("M".to_owned(), vec![path.p0.x, path.p0.y]));
+            assert_eq!(commands.last().unwrap().0, "Z");
+            // the flags pick the arc of the simulation, convex or concave, out of the four
+            // circular arcs between its ends
+            let mut current = path.p0;
+            let mut arcs = arcs.into_iter();
+            for (command, numbers) in commands.iter() {
+                if command == "A" {
+                    let arc = arcs.next().unwrap();
+                    let end = Point2::new(numbers[5], numbers[6]);
+                    assert_eq!(numbers[..3], [arc.r, arc.r, 0.0]);
+                    let (large_arc, sweep) = (numbers[3] == 1.0, numbers[4] == 1.0);
+                    let center = svg_arc_center(current, end, arc.r, large_arc, sweep);
+                    assert!((center - arc.center).norm() < 1e-9, "{:?}", arc);
+                    assert_eq!(sweep, arc.curvature_at(0.0) > 0.0);
+                    assert!((end - arc.last_point()).norm() < 1e-12);
+                }
+                if let [.., x, y] = numbers[..] {
+                    current = Point2::new(x, y);
+                }
+            }
+            // the whole path is in view, once its y axis is flipped
+            let start = svg.find("viewBox=\"").unwrap() + 9;
+            let view_box = svg[start..start + svg[start..].find('"').unwrap()]
+                .split_whitespace()
+                .map(|x| x.parse::<f64>().unwrap())
+                .collect::<Vec<_>>();
+            for p in sample_points(&path, 0.01) {
+                assert!(view_box[0] < p.x && p.x < view_box[0] + view_box[2]);
+                assert!(view_box[1] < -p.y && -p.y < view_box[1] + view_box[3]);
+            }
+        }
+        // a full circle is split in two halves
+        let circle = ClosedPath::new(vec![SubPath::Arc(new_arc_path![
+            0.0,
+            0.0,
+            2.0,
+            0.0,
+            2.0 * PI
+        ])]);
+        let commands = svg_commands(&circle.to_svg(0.05));
+        assert_eq!(commands.iter().filter(|(c, _)| c == "A").count(), 2);
+    }
+
+    #[test]
+    fn test_smooth_limits_curvature() {
+        // the predefined path has a corner at (8, -4)
+        let path = predefined_closed_path();
+        assert_eq!(path.max_curvature(), f64::INFINITY);
+        let smoothed = path.smooth(0.5).unwrap();
+        assert!(smoothed.max_curvature() <= 1.0 / 0.5);
+        assert!(is_valid_closed_path(&smoothed.subpaths));
+        assert_eq!(smoothed.subpaths.len(), path.subpaths.len() + 1);
+
+        // a square with rounded corners of radius 0.2, plus a sharp corner
+        let tight = ClosedPath::new(vec![
+            SubPath::Line(new_line_path![0.0, 0.0, 1.8, 0.0]),
+            SubPath::Arc(new_arc_path![1.8, 0.2, 0.2, -PI / 2.0, 0.0]),
+            SubPath::Line(new_line_path![2.0, 0.2, 2.0, 1.8]),
+            SubPath::Arc(new_arc_path![1.8, 1.8, 0.2, 0.0, PI / 2.0]),
+            SubPath::Line(new_line_path![1.8, 2.0, 0.2, 2.0]),
+            SubPath::Arc(new_arc_path![0.2, 1.8, 0.2, PI / 2.0, PI]),
+            SubPath::Line(new_line_path![0.0, 1.8, 0.0, 0.0]),
+        ]);
+        let min_radius = 0.5;
+        let smoothed = tight.smooth(min_radius).unwrap();
+        assert!(smoothed.max_curvature() <= 1.0 / min_radius + 1e-12);
+        assert!(is_valid_closed_path(&smoothed.subpaths));
+        // every corner is now a quarter circle of radius 0.5 inside the 2x2 square
+        let expected_length = 4.0 * (2.0 - 2.0 * min_radius) + 2.0 * PI * min_radius;
+        assert!((smoothed.length() - expected_length).abs() < 1e-9);
+        // already smooth enough, nothing changes
+        let unchanged = smoothed.smooth(0.1).unwrap();
+        assert!((unchanged.length() - smoothed.length()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_smooth_rejects_infeasible_paths() {
+        let path = predefined_closed_path();
+        // the U-turn around (3, -11) has radius 1
+        assert_eq!(path.smooth(1.5).err(), Some(SmoothError::TurnTooSharp(4)));
+        // the lines of the square are too short for such a fillet
+        let square = ClosedPath::new(vec![
+            SubPath::Line(new_line_path![0.0, 0.0, 1.0, 0.0]),
+            SubPath::Line(new_line_path![1.0, 0.0, 1.0, 1.0]),
+            SubPath::Line(new_line_path![1.0, 1.0, 0.0, 1.0]),
+            SubPath::Line(new_line_path![0.0, 1.0, 0.0, 0.0]),
+        ]);
+        assert!(matches!(
+            square.smooth(0.6),
+            Err(SmoothError::NotEnoughRoom(_))
+        ));
+        assert!(square.smooth(0.4).unwrap().max_curvature() <= 1.0 / 0.4 + 1e-12);
+    }
+
+    /// Integral of the squared curvature, i.e. how much the path bends overall.
+    /// The integral of the absolute curvature can't tell convex loops apart, as it is always 2 pi
+    fn bending_energy<T: Track<f64>>(track: &T) -> f64 {
+        let n = 20_000;
+        let ds = track.length() / n as f64;
+        (0..n)
+            .map(|i| track.curvature_at((i as f64 + 0.5) * ds).powi(2) * ds)
+            .sum()
+    }
+
+    #[test]
+    fn test_racing_line_bends_less_than_the_centerline() {
+        // a stadium-shaped oval, 4 m long with turns of radius 1
+        let oval = ClosedPath::new(vec![
+            SubPath::Line(new_line_path![0.0, -1.0, 4.0, -1.0]),
+            SubPath::Arc(new_arc_path![4.0, 0.0, 1.0, -PI / 2.0, PI / 2.0]),
+            SubPath::Line(new_line_path![4.0, 1.0, 0.0, 1.0]),
+            SubPath::Arc(new_arc_path![0.0, 0.0, 1.0, PI / 2.0, 3.0 * PI / 2.0]),
+        ]);
+        let half_width = 0.3;
+        let racing_line = oval.racing_line(half_width, 200);
+        let (energy, centerline_energy) = (bending_energy(&racing_line), bending_energy(&oval));
+        assert!(
+            energy < 0.9 * centerline_energy,
+            "{} vs {}",
+            energy,
+            centerline_energy
+        );
+        // out-in-out: it stays at the outer edge on the straights and touches the inner one
+        let outermost = oval.sdf(racing_line.point_at(2.0));
+        let innermost = racing_line
+            .waypoints()
+            .iter()
+            .map(|p| oval.sdf(*p))
+            .fold(f64::INFINITY, f64::min);
+        assert!((outermost - half_width).abs() < 1e-3 && (innermost + half_width).abs() < 1e-9);
+        // it stays within the lane, up to the spline bulging between the waypoints
+        for p in racing_line.waypoints() {
+            assert!(oval.sdf(*p).abs() <= half_width + 1e-9);
+        }
+        for k in 0..1000 {
+            let p = racing_line.point_at(k as f64 * racing_line.length() / 1000.0);
+            assert!(oval.sdf(p).abs() <= half_width + 1e-3);
+        }
+        // the curvature of a circle is already as even as it gets
+        let circle = predefined_circle(2.0);
+        let racing_line = circle.racing_line(half_width, 100);
+        assert!(racing_line
+            .waypoints()
+            .iter()
+            .all(|p| circle.sdf(*p).abs() < 1e-6));
+    }
 }
@@ -0,0 +1,38 @@
+/// Why a subpath, or a path out of subpaths, couldn't be built from the given parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeometryError {
+    /// A line whose endpoints are the same point
+    ZeroLengthLine,
+    /// An arc whose start and end angles are the same
+    ZeroLengthArc,
+    /// An arc whose radius isn't a positive number
+    InvalidRadius,
+    /// A spline through fewer than the 3 waypoints a closed curve needs
+    TooFewWaypoints { count: usize },
+    /// Subpaths which don't follow on from each other into a closed path, or no subpaths at all
+    OpenPath,
+}
+
+impl std::fmt::Display for GeometryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GeometryError::ZeroLengthLine => {
+                write!(f, "a line path needs distinct endpoints")
+            }
+            GeometryError::ZeroLengthArc => {
+                write!(f, "an arc path needs distinct start and end angles")
+            }
+            GeometryError::InvalidRadius => {
+                write!(f, "an arc path needs a positive radius")
+            }
+            GeometryError::TooFewWaypoints { count } => {
+                write!(f, "a spline path needs at least 3 waypoints, got {}", count)
+            }
+            GeometryError::OpenPath => {
+                write!(f, "the subpaths of a closed path must join up end to start")
+            }
+        }
+    }
+}
+
+impl std::error::Error for GeometryError {}
@@ -1,10 +1,17 @@
-use crate::utils::{math::cross, traits::Float};
+use crate::utils::{
+    math::{cross, point_distance},
+    traits::Float,
+};
 use nalgebra::{distance, Point2, Vector2};
 use serde::{Deserialize, Serialize};
 
+use super::bounding_box::BoundingBox;
+use super::error::GeometryError;
 use super::track::Track;
 
+/// Only the endpoints are serialized, `new` computes the rest when deserializing
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "LinePathFields<F>", into = "LinePathFields<F>")]
 pub struct LinePath<F: Float> {
     pub p0: Point2<F>,
     pub p1: Point2<F>,
@@ -16,14 +23,53 @@ impl<F> LinePath<F>
 where
     F: Float,
 {
-    pub fn new(p0: Point2<F>, p1: Point2<F>) -> Self {
+    /// Line from `p0` to `p1`, which must be distinct points
+    pub fn try_new(p0: Point2<F>, p1: Point2<F>) -> Result<Self, GeometryError> {
         let length = distance(&p0, &p1);
+        if length == F::zero() {
+            return Err(GeometryError::ZeroLengthLine);
+        }
         let v = (p1 - p0) / length;
-        assert!(
-            length != F::zero(),
-            "the line path must have a non-zero length"
-        );
-        Self { p0, p1, length, v }
+        Ok(Self { p0, p1, length, v })
+    }
+
+    /// Same as `try_new`, panicking if `p0` and `p1` are the same point
+    pub fn new(p0: Point2<F>, p1: Point2<F>) -> Self {
+        Self::try_new(p0, p1).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn bounding_box(&self) -> BoundingBox<F> {
+        BoundingBox::from_points([self.p0, self.p1])
+    }
+}
+
+/// Defining fields of a `LinePath`, which are all that it serializes
+#[derive(Serialize, Deserialize)]
+struct LinePathFields<F: Float> {
+    p0: Point2<F>,
+    p1: Point2<F>,
+}
+
+impl<F> TryFrom<LinePathFields<F>> for LinePath<F>
+where
+    F: Float,
+{
+    type Error = GeometryError;
+
+    fn try_from(fields: LinePathFields<F>) -> Result<Self, Self::Error> {
+        Self::try_new(fields.p0, fields.p1)
+    }
+}
+
+impl<F> From<LinePath<F>> for LinePathFields<F>
+where
+    F: Float,
+{
+    fn from(line: LinePath<F>) -> Self {
+        Self {
+            p0: line.p0,
+            p1: line.p1,
+        }
     }
 }
 
@@ -45,19 +91,33 @@ where
         // A x B = |A| |B| sin(theta)
         // then we divide by the length of the line path to get the signed distance (which is the height of the parallelogram)
         // d = A x B / |A|
+        // the direction v is already normalized, which keeps the products from overflowing for
+        // far away points
         let u = p - self.p0;
-        let v = self.p1 - self.p0;
-        let signed_dist = cross(&u, &v) / self.length;
+        let signed_dist = cross(&u, &self.v);
         let dot = u.dot(&self.v);
         if !(dot >= F::zero() && dot <= self.length) {
-            let d0 = distance(&p, &self.p0);
-            let d1 = distance(&p, &self.p1);
+            let d0 = point_distance(&p, &self.p0);
+            let d1 = point_distance(&p, &self.p1);
             let sign = num::Float::signum(signed_dist);
             return sign * num::Float::min(d0, d1);
         }
         signed_dist
     }
 
+    fn normal_at(&self, p: Point2<F>) -> Vector2<F> {
+        // the right-hand normal alongside the line, and past its ends the direction
+        // away from the closest endpoint, with the sign of the side as in the sdf
+        let u = p - self.p0;
+        let dot = u.dot(&self.v);
+        if !(dot >= F::zero() && dot <= self.length) {
+            let sign = num::Float::signum(cross(&u, &self.v));
+            let endpoint = if dot < F::zero() { self.p0 } else { self.p1 };
+            return (p - endpoint).normalize() * sign;
+        }
+        Vector2::new(self.v.y, -self.v.x)
+    }
+
     fn length(&self) -> F {
         self.length
     }
@@ -73,6 +133,10 @@ where
         self.p0 + self.v * d
     }
 
+    fn curvature_at(&self, _d: F) -> F {
+        F::zero()
+    }
+
     fn tangent_at(&self, _d: F) -> Vector2<F> {
         // returns the tangent vector of the point at distance d on the line path
         (self.p1 - self.p0) / self.length
@@ -98,3 +162,52 @@ macro_rules! new_line_path {
         LinePath::new(Point2::new($x0, $y0), Point2::new($x1, $y1))
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::track::finite_difference_normal;
+
+    #[test]
+    fn test_normal_is_the_gradient_of_the_sdf() {
+        let line = new_line_path![0.0, 0.0, 2.0, 0.0];
+        // alongside the line and past both of its ends, off of it where the sdf changes sign
+        for x in [-1.0, -0.5, 0.0, 0.5, 1.5, 2.0, 2.5, 3.0] {
+            for y in [-1.0, -0.5, -0.25, 0.25, 0.5, 1.0] {
+                let p = Point2::new(x, y);
+                let normal = line.normal_at(p);
+                // the differences straddle the bend of the sdf at the ends
+                assert!((normal - finite_difference_normal(&line, p)).norm() < 1e-5);
+                if (0.0..=2.0).contains(&x) {
+                    assert_eq!(normal, Vector2::new(0.0, -1.0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_serde_keeps_only_the_endpoints() {
+        let line = new_line_path![1.0, -2.0, 4.0, 2.0];
+        let mut json = serde_json::to_value(&line).unwrap();
+        let keys = json.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(keys, ["p0", "p1"]);
+        // a hand-edited endpoint, along with a stale cache of older files, which is ignored
+        json["p1"] = serde_json::to_value(Point2::new(1.0, 0.0)).unwrap();
+        json["length"] = serde_json::json!(5.0);
+        let edited: LinePath<f64> = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(edited.p0, line.p0);
+        assert_eq!(edited.length, 2.0);
+        assert_eq!(edited.point_at(1.0), Point2::new(1.0, -1.0));
+        assert_eq!(edited.tangent_at(0.0), Vector2::new(0.0, 1.0));
+        json["p1"] = json["p0"].clone();
+        assert!(serde_json::from_value::<LinePath<f64>>(json).is_err());
+    }
+
+    #[test]
+    fn test_coincident_endpoints_are_an_error() {
+        let p = Point2::new(1.0, -2.0);
+        let error = LinePath::try_new(p, p).err();
+        assert_eq!(error, Some(GeometryError::ZeroLengthLine));
+        assert!(LinePath::try_new(p, Point2::new(1.0, -1.0)).is_ok());
+    }
+}
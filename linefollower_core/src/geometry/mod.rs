@@ -1,4 +1,10 @@
 pub mod arc_path;
+pub mod bounding_box;
 pub mod closed_path;
+pub mod error;
 pub mod line_path;
+pub mod speed_profile;
+pub mod spline_path;
+pub mod svg;
 pub mod track;
+pub mod track_file;
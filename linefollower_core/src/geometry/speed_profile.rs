@@ -0,0 +1,224 @@
+use crate::utils::traits::Float;
+use serde::{Deserialize, Serialize};
+
+/// Describes how fast a point travels along a track as time passes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SpeedProfile<F: Float> {
+    /// Travels at the given speed from the start
+    Constant(F),
+    /// Accelerates uniformly from rest until `max_speed` is reached, then cruises
+    Trapezoidal { max_speed: F, acceleration: F },
+    /// Speeds up from rest to `max_speed` in `ramp_time` along the minimum-jerk profile, whose
+    /// acceleration rises from 0 and falls back to it smoothly, then cruises
+    MinimumJerk { max_speed: F, ramp_time: F },
+    /// Starts from rest and goes through `changes`, `(time, speed)` pairs in increasing order
+    /// of time: from each change's time, the speed moves to the new one along a minimum-jerk
+    /// ramp of `ramp_time`, then holds it. A speed of 0 stops the point. Changes at least
+    /// `ramp_time` apart keep the acceleration continuous; a closer one cuts the ramp short
+    MinimumJerkChanges { changes: Vec<(F, F)>, ramp_time: F },
+}
+
+/// Fraction of a minimum-jerk ramp's speed change after the fraction `tau` of its duration,
+/// `10 tau^3 - 15 tau^4 + 6 tau^5`
+fn ramp_fraction<F: Float>(tau: F) -> F {
+    let tau = num::Float::min(tau, F::one());
+    let c = |x: f64| F::from(x).unwrap();
+    num::Float::powi(tau, 3) * (c(10.0) + tau * (c(6.0) * tau - c(15.0)))
+}
+
+/// Integral of `ramp_fraction` from 0 to `tau`, `5/2 tau^4 - 3 tau^5 + tau^6` during the ramp
+fn ramp_integral<F: Float>(tau: F) -> F {
+    let c = |x: f64| F::from(x).unwrap();
+    if tau < F::one() {
+        num::Float::powi(tau, 4) * (c(2.5) + tau * (tau - c(3.0)))
+    } else {
+        c(0.5) + (tau - F::one())
+    }
+}
+
+/// Minimum-jerk ramp from `from` to `to` starting at `start`, which goes on after the ramp
+/// at the speed `to`
+#[derive(Clone, Copy)]
+struct Ramp<F> {
+    start: F,
+    from: F,
+    to: F,
+}
+
+impl<F: Float> Ramp<F> {
+    fn speed_at(&self, t: F, ramp_time: F) -> F {
+        self.from + (self.to - self.from) * ramp_fraction((t - self.start) / ramp_time)
+    }
+
+    /// Arc-length traveled from `start` to `t`
+    fn distance_at(&self, t: F, ramp_time: F) -> F {
+        let elapsed = t - self.start;
+        self.from * elapsed + (self.to - self.from) * ramp_time * ramp_integral(elapsed / ramp_time)
+    }
+}
+
+/// The ramp in progress at `t`, once `t` is past the start, along with the distance traveled
+/// up to the start of that ramp
+fn current_ramp<F: Float>(changes: &[(F, F)], ramp_time: F, t: F) -> (Ramp<F>, F) {
+    let mut ramp = Ramp {
+        start: F::zero(),
+        from: F::zero(),
+        to: F::zero(),
+    };
+    let mut distance = F::zero();
+    for &(start, speed) in changes.iter().take_while(|&&(start, _)| start <= t) {
+        distance += ramp.distance_at(start, ramp_time);
+        ramp = Ramp {
+            start,
+            from: ramp.speed_at(start, ramp_time),
+            to: speed,
+        };
+    }
+    (ramp, distance)
+}
+
+impl<F> SpeedProfile<F>
+where
+    F: Float,
+{
+    pub fn speed_at(&self, t: F) -> F {
+        if t <= F::zero() {
+            return F::zero();
+        }
+        match *self {
+            SpeedProfile::Constant(speed) => speed,
+            SpeedProfile::Trapezoidal {
+                max_speed,
+                acceleration,
+            } => num::Float::min(acceleration * t, max_speed),
+            SpeedProfile::MinimumJerk {
+                max_speed,
+                ramp_time,
+            } => max_speed * ramp_fraction(t / ramp_time),
+            SpeedProfile::MinimumJerkChanges {
+                ref changes,
+                ramp_time,
+            } => current_ramp(changes, ramp_time, t).0.speed_at(t, ramp_time),
+        }
+    }
+
+    /// Arc-length traveled after a time `t`, i.e. the integral of the speed from 0 to `t`
+    pub fn distance_at(&self, t: F) -> F {
+        if t <= F::zero() {
+            return F::zero();
+        }
+        let half = F::from(0.5).unwrap();
+        match *self {
+            SpeedProfile::Constant(speed) => speed * t,
+            SpeedProfile::Trapezoidal {
+                max_speed,
+                acceleration,
+            } => {
+                let t_ramp = max_speed / acceleration;
+                if t < t_ramp {
+                    half * acceleration * t * t
+                } else {
+                    half * max_speed * t_ramp + max_speed * (t - t_ramp)
+                }
+            }
+            SpeedProfile::MinimumJerk {
+                max_speed,
+                ramp_time,
+            } => max_speed * ramp_time * ramp_integral(t / ramp_time),
+            SpeedProfile::MinimumJerkChanges {
+                ref changes,
+                ramp_time,
+            } => {
+                let (ramp, distance) = current_ramp(changes, ramp_time, t);
+                distance + ramp.distance_at(t, ramp_time)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Largest change of the acceleration, by finite differences of the speed, between
+    /// consecutive steps of `h` up to `t_end`
+    fn largest_acceleration_jump(profile: &SpeedProfile<f64>, t_end: f64, h: f64) -> f64 {
+        let acceleration = |t: f64| (profile.speed_at(t + h) - profile.speed_at(t)) / h;
+        let steps = (t_end / h) as usize;
+        (1..steps)
+            .map(|i| (acceleration(i as f64 * h) - acceleration((i - 1) as f64 * h)).abs())
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn test_minimum_jerk_acceleration_is_continuous() {
+        let (max_speed, ramp_time, h) = (1.5, 2.0, 1e-4);
+        let minimum_jerk = SpeedProfile::MinimumJerk {
+            max_speed,
+            ramp_time,
+        };
+        // reaching the same speed at the same time, with a jump of its acceleration at each end
+        // of the ramp
+        let trapezoidal = SpeedProfile::Trapezoidal {
+            max_speed,
+            acceleration: max_speed / ramp_time,
+        };
+        assert!(largest_acceleration_jump(&minimum_jerk, 2.0 * ramp_time, h) < 1e-3);
+        let jump = largest_acceleration_jump(&trapezoidal, 2.0 * ramp_time, h);
+        assert!((jump - max_speed / ramp_time).abs() < 1e-6, "{}", jump);
+
+        assert_eq!(minimum_jerk.speed_at(0.0), 0.0);
+        assert!((minimum_jerk.speed_at(ramp_time / 2.0) - max_speed / 2.0).abs() < 1e-12);
+        assert!((minimum_jerk.speed_at(ramp_time) - max_speed).abs() < 1e-12);
+        assert_eq!(minimum_jerk.speed_at(3.0 * ramp_time), max_speed);
+
+        // the distance is the integral of the speed, through the end of the ramp
+        let mut distance = 0.0;
+        for i in 0..30000 {
+            let t = i as f64 * h;
+            distance += 0.5 * (minimum_jerk.speed_at(t) + minimum_jerk.speed_at(t + h)) * h;
+            assert!(
+                (minimum_jerk.distance_at(t + h) - distance).abs() < 1e-6,
+                "{}",
+                t + h
+            );
+        }
+    }
+
+    #[test]
+    fn test_minimum_jerk_changes_ramp_between_the_speeds() {
+        let (ramp_time, h) = (1.0, 1e-4);
+        // speeds up, slows down to a stop, then starts again
+        let profile = SpeedProfile::MinimumJerkChanges {
+            changes: vec![(0.0, 1.0), (2.0, 0.4), (4.0, 0.0), (6.0, 0.8)],
+            ramp_time,
+        };
+        assert!(largest_acceleration_jump(&profile, 8.0, h) < 1e-3);
+        for (t, speed) in [(1.5, 1.0), (3.0, 0.4), (5.5, 0.0), (7.5, 0.8)] {
+            assert!((profile.speed_at(t) - speed).abs() < 1e-12, "{}", t);
+        }
+        // halfway through the ramp, halfway between the speeds
+        assert!((profile.speed_at(2.5) - 0.7).abs() < 1e-12);
+
+        // a single change from rest is the same as the ramp from rest
+        let single = SpeedProfile::MinimumJerkChanges {
+            changes: vec![(0.0, 1.5)],
+            ramp_time: 2.0,
+        };
+        let minimum_jerk = SpeedProfile::MinimumJerk {
+            max_speed: 1.5,
+            ramp_time: 2.0,
+        };
+        let mut distance = 0.0;
+        for i in 0..80000 {
+            let t = i as f64 * h;
+            assert!((single.speed_at(t) - minimum_jerk.speed_at(t)).abs() < 1e-12);
+            distance += 0.5 * (profile.speed_at(t) + profile.speed_at(t + h)) * h;
+            assert!(
+                (profile.distance_at(t + h) - distance).abs() < 1e-6,
+                "{}",
+                t + h
+            );
+        }
+    }
+}
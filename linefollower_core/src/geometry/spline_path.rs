@@ -0,0 +1,390 @@
+use crate::utils::{
+    math::{cross, point_distance},
+    traits::Float,
+};
+use nalgebra::{distance, Point2, Vector2};
+use serde::{Deserialize, Serialize};
+
+use super::bounding_box::BoundingBox;
+use super::error::GeometryError;
+use super::track::Track;
+
+/// Points sampled on each segment for the arc-length table,
+/// which also makes up the polyline used for the distance queries
+const SAMPLES_PER_SEGMENT: usize = 64;
+
+/// Closed curve through a list of waypoints, interpolated by a uniform Catmull-Rom spline.
+/// Unlike the corners between lines, the tangent is continuous at the waypoints, so the
+/// derivative term of the controller doesn't spike when going through them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "Vec<Point2<F>>", into = "Vec<Point2<F>>")]
+pub struct SplinePath<F: Float> {
+    waypoints: Vec<Point2<F>>,
+    /// `SAMPLES_PER_SEGMENT` points on each segment, plus the first waypoint again at the end
+    samples: Vec<Point2<F>>,
+    /// Arc-length at each of the samples
+    sample_distances: Vec<F>,
+    length: F,
+    /// Box of the samples
+    bounds: BoundingBox<F>,
+}
+
+impl<F> SplinePath<F>
+where
+    F: Float,
+{
+    pub fn new(waypoints: Vec<Point2<F>>) -> Self {
+        assert!(
+            waypoints.len() >= 3,
+            "the spline path must have at least 3 waypoints"
+        );
+        let mut spline = Self {
+            waypoints,
+            samples: Vec::new(),
+            sample_distances: Vec::new(),
+            length: F::zero(),
+            bounds: BoundingBox::new(Point2::origin(), Point2::origin()),
+        };
+        let n = spline.waypoints.len();
+        let samples_per_segment = F::from_usize(SAMPLES_PER_SEGMENT).unwrap();
+        let mut samples = Vec::with_capacity(n * SAMPLES_PER_SEGMENT + 1);
+        for i in 0..n {
+            for j in 0..SAMPLES_PER_SEGMENT {
+                let t = F::from_usize(j).unwrap() / samples_per_segment;
+                samples.push(spline.eval(i, t));
+            }
+        }
+        samples.push(spline.waypoints[0]);
+        // arc-length reparameterization, by accumulating the length of the chords
+        let mut sample_distances = Vec::with_capacity(samples.len());
+        let mut d = F::zero();
+        sample_distances.push(d);
+        for pair in samples.windows(2) {
+            d += distance(&pair[0], &pair[1]);
+            sample_distances.push(d);
+        }
+        assert!(d > F::zero(), "the spline path must have a non-zero length");
+        spline.bounds = BoundingBox::from_points(samples.iter().copied());
+        spline.samples = samples;
+        spline.sample_distances = sample_distances;
+        spline.length = d;
+        spline
+    }
+
+    pub fn waypoints(&self) -> &[Point2<F>] {
+        &self.waypoints
+    }
+
+    /// Box of the sampled polyline, which is the curve the distance queries measure from
+    pub fn bounding_box(&self) -> BoundingBox<F> {
+        self.bounds
+    }
+
+    /// The spline as cubic Bezier curves, one per segment, each given by its two control points
+    /// followed by its end point. The first one starts at the first waypoint, and each of the
+    /// others where the previous one ends
+    pub fn bezier_segments(&self) -> Vec<[Point2<F>; 3]> {
+        let n = self.waypoints.len();
+        let (two, three) = (F::from(2.0).unwrap(), F::from(3.0).unwrap());
+        (0..n)
+            .map(|i| {
+                // the control points are a third of the end tangents away from the end points
+                let (p1, a, b, c) = self.coefficients(i);
+                let p2 = self.waypoints[(i + 1) % n];
+                let end_tangent = a + b * two + c * three;
+                [p1 + a / three, p2 - end_tangent / three, p2]
+            })
+            .collect()
+    }
+
+    /// Largest absolute curvature over the samples of the spline
+    pub fn max_curvature(&self) -> F {
+        let samples_per_segment = F::from_usize(SAMPLES_PER_SEGMENT).unwrap();
+        (0..self.waypoints.len())
+            .flat_map(|i| (0..SAMPLES_PER_SEGMENT).map(move |j| (i, j)))
+            .map(|(i, j)| {
+                let t = F::from_usize(j).unwrap() / samples_per_segment;
+                num::Float::abs(self.curvature(i, t))
+            })
+            .fold(F::zero(), num::Float::max)
+    }
+
+    /// Polynomial of segment `i`, which goes from waypoint `i` to the next one:
+    /// p(t) = p1 + a t + b t^2 + c t^3 for t in [0, 1]
+    fn coefficients(&self, i: usize) -> (Point2<F>, Vector2<F>, Vector2<F>, Vector2<F>) {
+        let n = self.waypoints.len();
+        let p1 = self.waypoints[i];
+        // the standard basis, written relative to p1
+        let d0 = self.waypoints[(i + n - 1) % n] - p1;
+        let d2 = self.waypoints[(i + 1) % n] - p1;
+        let d3 = self.waypoints[(i + 2) % n] - p1;
+        let half = F::from(0.5).unwrap();
+        let (two, three, four) = (
+            F::from(2.0).unwrap(),
+            F::from(3.0).unwrap(),
+            F::from(4.0).unwrap(),
+        );
+        let a = (d2 - d0) * half;
+        let b = (d0 * two + d2 * four - d3) * half;
+        let c = (d3 - d0 - d2 * three) * half;
+        (p1, a, b, c)
+    }
+
+    fn eval(&self, i: usize, t: F) -> Point2<F> {
+        let (p1, a, b, c) = self.coefficients(i);
+        p1 + (a + (b + c * t) * t) * t
+    }
+
+    /// First and second derivatives of segment `i` with respect to t
+    fn derivatives(&self, i: usize, t: F) -> (Vector2<F>, Vector2<F>) {
+        let (_, a, b, c) = self.coefficients(i);
+        let (two, three, six) = (
+            F::from(2.0).unwrap(),
+            F::from(3.0).unwrap(),
+            F::from(6.0).unwrap(),
+        );
+        (a + (b * two + c * (three * t)) * t, b * two + c * (six * t))
+    }
+
+    fn curvature(&self, i: usize, t: F) -> F {
+        let (d1, d2) = self.derivatives(i, t);
+        let speed = d1.norm();
+        cross(&d1, &d2) / (speed * speed * speed)
+    }
+
+    /// Segment and parameter t of the point at arc-length `d`, from the arc-length table
+    fn locate(&self, d: F) -> (usize, F) {
+        let d = num::Float::min(num::Float::max(d, F::zero()), self.length);
+        let k = self
+            .sample_distances
+            .partition_point(|&s| s <= d)
+            .saturating_sub(1)
+            .min(self.samples.len() - 2);
+        let (s0, s1) = (self.sample_distances[k], self.sample_distances[k + 1]);
+        let fraction = if s1 > s0 {
+            (d - s0) / (s1 - s0)
+        } else {
+            F::zero()
+        };
+        let t = (F::from_usize(k % SAMPLES_PER_SEGMENT).unwrap() + fraction)
+            / F::from_usize(SAMPLES_PER_SEGMENT).unwrap();
+        (k / SAMPLES_PER_SEGMENT, t)
+    }
+
+    /// Index of the chord between samples which is closest to `p`,
+    /// along with the fraction of the chord at which `p` projects
+    fn closest_chord(&self, p: Point2<F>) -> (usize, F) {
+        let mut closest = (0, F::zero());
+        let mut min_distance = F::infinity();
+        for (k, pair) in self.samples.windows(2).enumerate() {
+            let v = pair[1] - pair[0];
+            let squared_length = v.norm_squared();
+            let u = if squared_length > F::zero() {
+                let u = (p - pair[0]).dot(&v) / squared_length;
+                num::Float::min(num::Float::max(u, F::zero()), F::one())
+            } else {
+                F::zero()
+            };
+            let d = point_distance(&p, &(pair[0] + v * u));
+            if d < min_distance {
+                min_distance = d;
+                closest = (k, u);
+            }
+        }
+        closest
+    }
+}
+
+impl<F> TryFrom<Vec<Point2<F>>> for SplinePath<F>
+where
+    F: Float,
+{
+    type Error = GeometryError;
+
+    fn try_from(waypoints: Vec<Point2<F>>) -> Result<Self, Self::Error> {
+        if waypoints.len() < 3 {
+            return Err(GeometryError::TooFewWaypoints {
+                count: waypoints.len(),
+            });
+        }
+        Ok(Self::new(waypoints))
+    }
+}
+
+impl<F> From<SplinePath<F>> for Vec<Point2<F>>
+where
+    F: Float,
+{
+    fn from(spline: SplinePath<F>) -> Self {
+        spline.waypoints
+    }
+}
+
+impl<F> Track<F> for SplinePath<F>
+where
+    F: Float,
+{
+    fn sdf(&self, p: Point2<F>) -> F {
+        // distance to the closest chord of the sampled curve,
+        // negative to its left like for the line path
+        let (k, u) = self.closest_chord(p);
+        let (a, b) = (self.samples[k], self.samples[k + 1]);
+        let q = a + (b - a) * u;
+        let d = point_distance(&p, &q);
+        if d == F::zero() {
+            return d;
+        }
+        num::Float::signum(cross(&(p - a), &(b - a))) * d
+    }
+
+    fn length(&self) -> F {
+        self.length
+    }
+
+    fn first_point(&self) -> Point2<F> {
+        self.waypoints[0]
+    }
+
+    fn point_at(&self, d: F) -> Point2<F> {
+        let (i, t) = self.locate(d);
+        self.eval(i, t)
+    }
+
+    fn tangent_at(&self, d: F) -> Vector2<F> {
+        let (i, t) = self.locate(d);
+        self.derivatives(i, t).0.normalize()
+    }
+
+    fn curvature_at(&self, d: F) -> F {
+        let (i, t) = self.locate(d);
+        self.curvature(i, t)
+    }
+
+    fn point_projection_distance(&self, p: Point2<F>) -> F {
+        let (k, u) = self.closest_chord(p);
+        let (s0, s1) = (self.sample_distances[k], self.sample_distances[k + 1]);
+        s0 + (s1 - s0) * u
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    /// Waypoints on an ellipse, unevenly spaced
+    fn ellipse_spline() -> SplinePath<f64> {
+        let angles = [0.0, 0.5, 1.4, 2.0, 3.0, 3.5, 4.4, 5.5];
+        SplinePath::new(
+            angles
+                .iter()
+                .map(|&a: &f64| Point2::new(4.0 * a.cos(), 2.5 * a.sin()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_spline_goes_through_the_waypoints() {
+        let spline = ellipse_spline();
+        for (i, w) in spline.waypoints().iter().enumerate() {
+            let d = spline.sample_distances[i * SAMPLES_PER_SEGMENT];
+            assert!((spline.point_at(d) - *w).norm() < 1e-12);
+            assert!(spline.sdf(*w).abs() < 1e-12);
+        }
+        assert!((spline.point_at(spline.length()) - spline.first_point()).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_spline_tangent_is_continuous_at_the_waypoints() {
+        let spline = ellipse_spline();
+        for i in 0..spline.waypoints().len() {
+            let d = spline.sample_distances[i * SAMPLES_PER_SEGMENT];
+            let before = spline.tangent_at((d - 1e-6).rem_euclid(spline.length()));
+            let after = spline.tangent_at(d + 1e-6);
+            assert!((before - after).norm() < 1e-4, "waypoint {}", i);
+        }
+    }
+
+    #[test]
+    fn test_spline_arc_length_parameterization() {
+        let spline = ellipse_spline();
+        let n = 500;
+        let step = spline.length() / n as f64;
+        for k in 0..n {
+            let (p, q) = (
+                spline.point_at(k as f64 * step),
+                spline.point_at((k + 1) as f64 * step),
+            );
+            // equally spaced in arc-length, up to the linear interpolation of the table
+            assert!(((p - q).norm() - step).abs() < 1e-2 * step);
+            let d = spline.point_projection_distance(p);
+            assert!((d - k as f64 * step).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_spline_sdf_sign_and_curvature() {
+        // counterclockwise, so the inside is on the left and the curvature positive
+        let spline = ellipse_spline();
+        let offset = 0.05;
+        for k in 0..100 {
+            let d = k as f64 * spline.length() / 100.0;
+            let t = spline.tangent_at(d);
+            let left = spline.point_at(d) + Vector2::new(-t.y, t.x) * offset;
+            assert!((spline.sdf(left) + offset).abs() < 1e-4);
+            assert!(spline.curvature_at(d) > 0.0);
+        }
+        let sampled_max = (0..1000)
+            .map(|k| {
+                spline
+                    .curvature_at(k as f64 * spline.length() / 1000.0)
+                    .abs()
+            })
+            .fold(0.0, f64::max);
+        let max_curvature = spline.max_curvature();
+        assert!(sampled_max <= 1.05 * max_curvature && max_curvature <= 1.05 * sampled_max);
+        let circle = SplinePath::new(
+            (0..16)
+                .map(|i| {
+                    let a = i as f64 * 2.0 * PI / 16.0;
+                    Point2::new(a.cos(), a.sin())
+                })
+                .collect(),
+        );
+        // a cubic spline only approximates a circle
+        for k in 0..100 {
+            let d = k as f64 * circle.length() / 100.0;
+            assert!((circle.curvature_at(d) - 1.0).abs() < 0.15);
+        }
+    }
+
+    #[test]
+    fn test_bezier_segments_trace_the_spline() {
+        let spline = ellipse_spline();
+        let segments = spline.bezier_segments();
+        assert_eq!(segments.len(), spline.waypoints().len());
+        let mut start = spline.first_point();
+        for (i, &[b1, b2, end]) in segments.iter().enumerate() {
+            for k in 0..=10 {
+                let t = k as f64 / 10.0;
+                let s = 1.0 - t;
+                let bezier = Point2::from(
+                    start.coords * (s * s * s)
+                        + b1.coords * (3.0 * s * s * t)
+                        + b2.coords * (3.0 * s * t * t)
+                        + end.coords * (t * t * t),
+                );
+                assert!((bezier - spline.eval(i, t)).norm() < 1e-12, "segment {}", i);
+            }
+            start = end;
+        }
+        assert_eq!(start, spline.first_point());
+    }
+
+    #[test]
+    fn test_too_few_waypoints_are_an_error() {
+        let waypoints = vec![Point2::new(0.0, 0.0), Point2::new(1.0, 0.0)];
+        let error = SplinePath::<f64>::try_from(waypoints).err();
+        assert_eq!(error, Some(GeometryError::TooFewWaypoints { count: 2 }));
+    }
+}
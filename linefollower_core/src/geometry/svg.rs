@@ -0,0 +1,422 @@
+use super::arc_path::ArcPath;
+use super::closed_path::{is_valid_closed_path, ClosedPath, SubPath};
+use super::error::GeometryError;
+use super::line_path::LinePath;
+use super::track::Track;
+use nalgebra::{distance, Point2, Vector2};
+use std::f64::consts::PI;
+
+/// Distance under which two points of an SVG path are taken to be the same, e.g. the end of the
+/// last arc of an exported path, which is only as precise as its printed coordinates, and the start
+const SAME_POINT_TOLERANCE: f64 = 1e-9;
+
+/// Why an SVG path couldn't be turned into a `ClosedPath`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// The SVG document has no `<path>` element with a `d` attribute
+    MissingPath,
+    /// The path data has no segments
+    Empty,
+    /// The path data doesn't start with a `M` command
+    MissingMoveTo,
+    /// A command other than `M`, `L`, `A` and `Z`, e.g. a relative or a curve one,
+    /// or a character which is neither a command nor a number
+    UnsupportedCommand(char),
+    /// A command which isn't followed by all the numbers it takes
+    MissingNumbers(char),
+    /// An arc flag which is neither 0 nor 1
+    InvalidFlag(char),
+    /// A `M` command, or any other one after a `Z`, which would start a second path
+    MultipleSubpaths,
+    /// An elliptical arc, which an `ArcPath` can't follow
+    NonCircularArc { rx: f64, ry: f64 },
+    /// The path doesn't come back to its first point
+    NotClosed,
+    /// A segment which gives no subpath, e.g. an arc too flat for its angles to differ
+    InvalidSubpath(GeometryError),
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::MissingPath => write!(f, "the document has no <path> with a d attribute"),
+            ParseError::Empty => write!(f, "the path has no segments"),
+            ParseError::MissingMoveTo => write!(f, "the path doesn't start with a M command"),
+            ParseError::UnsupportedCommand(c) => {
+                write!(f, "unsupported command '{}', only M, L, A and Z are", c)
+            }
+            ParseError::MissingNumbers(c) => {
+                write!(f, "the '{}' command is missing some of its numbers", c)
+            }
+            ParseError::InvalidFlag(c) => write!(f, "arc flags are 0 or 1, got '{}'", c),
+            ParseError::MultipleSubpaths => write!(f, "the path has more than one subpath"),
+            ParseError::NonCircularArc { rx, ry } => {
+                write!(f, "arcs must be circular, got radii {} and {}", rx, ry)
+            }
+            ParseError::NotClosed => write!(f, "the path doesn't come back to its start"),
+            ParseError::InvalidSubpath(e) => write!(f, "invalid segment: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<GeometryError> for ParseError {
+    fn from(e: GeometryError) -> Self {
+        ParseError::InvalidSubpath(e)
+    }
+}
+
+/// Reads the data of an SVG `<path>` (its `d` attribute) made of absolute `M`, `L`, `A` and
+/// `Z` commands, like `ClosedPath::to_svg` writes for lines and arcs. Each `L` becomes a
+/// `LinePath` and each `A` an `ArcPath`, from SVG's parameterization of an arc by its ends.
+/// The path must end at its start, or with a `Z`, which closes it with a line if needed
+pub fn closed_path_from_svg(d: &str) -> Result<ClosedPath<f64>, ParseError> {
+    let mut scanner = Scanner::new(d);
+    let start = match scanner.command()? {
+        None => return Err(ParseError::Empty),
+        Some('M') => scanner.point('M')?,
+        Some(_) => return Err(ParseError::MissingMoveTo),
+    };
+    let mut subpaths = Vec::new();
+    let mut current = start;
+    let mut closed = false;
+    // numbers right after the numbers of a command repeat it, as lines after a `M`
+    let mut previous = 'L';
+    loop {
+        let command = if scanner.has_number() {
+            previous
+        } else {
+            match scanner.command()? {
+                Some(command) => command,
+                None => break,
+            }
+        };
+        if closed {
+            return Err(ParseError::MultipleSubpaths);
+        }
+        match command {
+            'M' => return Err(ParseError::MultipleSubpaths),
+            'L' => {
+                let end = scanner.point('L')?;
+                push_line(&mut subpaths, &mut current, end)?;
+            }
+            'A' => {
+                let (rx, ry) = (scanner.number('A')?.abs(), scanner.number('A')?.abs());
+                // the rotation of the ellipse's axes, which doesn't change a circle
+                scanner.number('A')?;
+                let (large_arc, sweep) = (scanner.flag('A')?, scanner.flag('A')?);
+                let end = scanner.point('A')?;
+                if (rx - ry).abs() > SAME_POINT_TOLERANCE * rx.max(ry) {
+                    return Err(ParseError::NonCircularArc { rx, ry });
+                }
+                push_arc(&mut subpaths, &mut current, end, rx, large_arc, sweep)?;
+            }
+            'Z' | 'z' => {
+                push_line(&mut subpaths, &mut current, start)?;
+                closed = true;
+            }
+            c => return Err(ParseError::UnsupportedCommand(c)),
+        }
+        previous = command;
+    }
+    if subpaths.is_empty() {
+        return Err(ParseError::Empty);
+    }
+    if !is_valid_closed_path(&subpaths) {
+        return Err(ParseError::NotClosed);
+    }
+    Ok(ClosedPath::new(subpaths))
+}
+
+/// Reads the first `<path>` of an SVG document, like the ones `ClosedPath::to_svg` writes, with
+/// `closed_path_from_svg`. Its `transform` is ignored, so the path is in the coordinates of its
+/// data, which for an exported path are the track's own
+pub fn closed_path_from_svg_document(svg: &str) -> Result<ClosedPath<f64>, ParseError> {
+    let element = &svg[svg.find("<path").ok_or(ParseError::MissingPath)?..];
+    let element = &element[..element.find('>').unwrap_or(element.len())];
+    let data = element
+        .match_indices("d=")
+        .filter(|(i, _)| element[..*i].ends_with(char::is_whitespace))
+        .find_map(|(i, _)| {
+            let value = &element[i + 2..];
+            let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            let value = &value[1..];
+            Some(&value[..value.find(quote)?])
+        })
+        .ok_or(ParseError::MissingPath)?;
+    closed_path_from_svg(data)
+}
+
+/// Line from `current` to `end`, unless they are the same point
+fn push_line(
+    subpaths: &mut Vec<SubPath<f64>>,
+    current: &mut Point2<f64>,
+    end: Point2<f64>,
+) -> Result<(), GeometryError> {
+    if distance(current, &end) > SAME_POINT_TOLERANCE {
+        subpaths.push(SubPath::Line(LinePath::try_new(*current, end)?));
+        *current = end;
+    }
+    Ok(())
+}
+
+/// Arc of radius `r` from `current` to `end`, out of the four such arcs the one which turns
+/// counterclockwise if `sweep` is set and by more than half a turn if `large_arc` is set.
+/// As in SVG, the radius is scaled up if the ends are too far apart for it, a zero radius
+/// gives a line, and nothing is drawn between two ends at the same point
+fn push_arc(
+    subpaths: &mut Vec<SubPath<f64>>,
+    current: &mut Point2<f64>,
+    end: Point2<f64>,
+    r: f64,
+    large_arc: bool,
+    sweep: bool,
+) -> Result<(), GeometryError> {
+    if distance(current, &end) <= SAME_POINT_TOLERANCE {
+        return Ok(());
+    }
+    if r == 0.0 {
+        return push_line(subpaths, current, end);
+    }
+    // the center is on the bisector of the chord, on the side given by the flags
+    // (section F.6.5 of the SVG specification, for a circle)
+    let half_chord = (*current - end) / 2.0;
+    let squared_half_chord = half_chord.norm_squared();
+    let r = r.max(squared_half_chord.sqrt());
+    let factor = ((r * r - squared_half_chord).max(0.0) / squared_half_chord).sqrt();
+    let sign = if large_arc != sweep { 1.0 } else { -1.0 };
+    let center = nalgebra::center(current, &end)
+        + Vector2::new(half_chord.y, -half_chord.x) * (sign * factor);
+    let (v0, v1) = (*current - center, end - center);
+    let theta0 = v0.y.atan2(v0.x);
+    // the signed angle from one end to the other, in the direction of the sweep
+    let mut delta = (v0.x * v1.y - v0.y * v1.x).atan2(v0.dot(&v1));
+    if sweep && delta <= 0.0 {
+        delta += 2.0 * PI;
+    } else if !sweep && delta >= 0.0 {
+        delta -= 2.0 * PI;
+    }
+    let arc = ArcPath::try_new(center, r, theta0, theta0 + delta)?;
+    *current = arc.last_point();
+    subpaths.push(SubPath::Arc(arc));
+    Ok(())
+}
+
+/// Reads the commands and numbers of SVG path data, which may be separated by whitespace
+/// and commas, or not at all when that is unambiguous, as in `M0-1.5.5L2,0`
+struct Scanner<'a> {
+    data: &'a str,
+    position: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(data: &'a str) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        // separators never matter, so they are skipped here
+        let rest = &self.data[self.position..];
+        let trimmed = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        self.position += rest.len() - trimmed.len();
+        trimmed.chars().next()
+    }
+
+    fn has_number(&mut self) -> bool {
+        matches!(self.peek(), Some(c) if c.is_ascii_digit() || matches!(c, '.' | '+' | '-'))
+    }
+
+    /// Next command letter, or `None` at the end of the data
+    fn command(&mut self) -> Result<Option<char>, ParseError> {
+        match self.peek() {
+            None => Ok(None),
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.position += 1;
+                Ok(Some(c))
+            }
+            Some(c) => Err(ParseError::UnsupportedCommand(c)),
+        }
+    }
+
+    /// Next number, taken by `command`
+    fn number(&mut self, command: char) -> Result<f64, ParseError> {
+        if !self.has_number() {
+            return Err(ParseError::MissingNumbers(command));
+        }
+        let rest = &self.data.as_bytes()[self.position..];
+        let digits_from = |mut i: usize| {
+            while i < rest.len() && rest[i].is_ascii_digit() {
+                i += 1;
+            }
+            i
+        };
+        let mut end = usize::from(matches!(rest[0], b'+' | b'-'));
+        end = digits_from(end);
+        if end < rest.len() && rest[end] == b'.' {
+            end = digits_from(end + 1);
+        }
+        // an exponent only if it has digits, so that the `e` can't be mistaken for a command
+        if end < rest.len() && matches!(rest[end], b'e' | b'E') {
+            let sign = usize::from(end + 1 < rest.len() && matches!(rest[end + 1], b'+' | b'-'));
+            let exponent_end = digits_from(end + 1 + sign);
+            if exponent_end > end + 1 + sign {
+                end = exponent_end;
+            }
+        }
+        let number = self.data[self.position..self.position + end]
+            .parse()
+            .map_err(|_| ParseError::MissingNumbers(command))?;
+        self.position += end;
+        Ok(number)
+    }
+
+    fn point(&mut self, command: char) -> Result<Point2<f64>, ParseError> {
+        Ok(Point2::new(self.number(command)?, self.number(command)?))
+    }
+
+    /// Next arc flag, a single digit which needs no separator from what follows it
+    fn flag(&mut self, command: char) -> Result<bool, ParseError> {
+        match self.peek() {
+            Some('0') => {
+                self.position += 1;
+                Ok(false)
+            }
+            Some('1') => {
+                self.position += 1;
+                Ok(true)
+            }
+            Some(c) if !c.is_ascii_alphabetic() => Err(ParseError::InvalidFlag(c)),
+            _ => Err(ParseError::MissingNumbers(command)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::closed_path::{
+        predefined_circle, predefined_closed_path, random_closed_path,
+    };
+    use crate::{new_arc_path, new_line_path};
+
+    /// The `d` attribute of the only path of an exported SVG document
+    fn path_data(svg: &str) -> &str {
+        let start = svg.find(" d=\"").unwrap() + 4;
+        &svg[start..start + svg[start..].find('"').unwrap()]
+    }
+
+    /// Checks that the subpaths are of the same kinds and in the same places
+    fn assert_same_path(a: &ClosedPath<f64>, b: &ClosedPath<f64>) {
+        assert_eq!(a.subpaths().len(), b.subpaths().len());
+        for (a, b) in a.subpaths().iter().zip(b.subpaths()) {
+            assert_eq!(std::mem::discriminant(a), std::mem::discriminant(b));
+            assert!((a.length() - b.length()).abs() < 1e-9);
+            for d in [0.0, 0.5 * a.length(), a.length()] {
+                assert!((a.point_at(d) - b.point_at(d)).norm() < 1e-9);
+                assert!((a.tangent_at(d) - b.tangent_at(d)).norm() < 1e-9);
+            }
+            assert!((a.curvature_at(0.0) - b.curvature_at(0.0)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_exported_paths_load_back() {
+        // a three-quarter arc, which needs the large arc flag, closed by two lines
+        let keyhole = ClosedPath::new(vec![
+            SubPath::Arc(new_arc_path![0.0, 0.0, 1.0, -PI / 2.0, PI]),
+            SubPath::Line(new_line_path![-1.0, 0.0, -1.0, -1.0]),
+            SubPath::Line(new_line_path![-1.0, -1.0, 0.0, -1.0]),
+        ]);
+        let mut paths = vec![predefined_closed_path(), predefined_circle(2.0), keyhole];
+        paths.extend((0..20).map(|seed| random_closed_path(seed, 8)));
+        for path in paths {
+            let svg = path.to_svg(0.05);
+            let loaded = closed_path_from_svg(path_data(&svg)).unwrap();
+            assert_same_path(&loaded, &path);
+            // and it's stable over another round trip
+            let reloaded = closed_path_from_svg(path_data(&loaded.to_svg(0.05))).unwrap();
+            assert_same_path(&reloaded, &loaded);
+        }
+    }
+
+    #[test]
+    fn test_path_of_a_document() {
+        let path = predefined_closed_path();
+        let loaded = closed_path_from_svg_document(&path.to_svg(0.05)).unwrap();
+        assert_same_path(&loaded, &path);
+        // the first path is read, whatever its other attributes and quotes
+        let svg =
+            "<svg>\n<path id='track'\n d='M 0 0 L 1 0 L 1 1 Z' fill=\"none\"/><path d=\"M 5 5\"/>";
+        assert_eq!(
+            closed_path_from_svg_document(svg).unwrap().subpaths().len(),
+            3
+        );
+
+        for svg in [
+            "<svg></svg>",
+            "<svg><path id=\"d=\"/></svg>",
+            "<svg><path/></svg>",
+        ] {
+            assert_eq!(
+                closed_path_from_svg_document(svg).err(),
+                Some(ParseError::MissingPath),
+                "{:?}",
+                svg
+            );
+        }
+    }
+
+    #[test]
+    fn test_compact_path_data() {
+        // a counterclockwise stadium, with no separators where they can be left out,
+        // implicit lines after the move and exponents among the numbers
+        let path = closed_path_from_svg("M0-1 2-1A1,1 0 012 1L0 1A1e0 1 0 0 1 0-1.0Z").unwrap();
+        assert_eq!(path.subpaths().len(), 4);
+        assert!((path.length() - (4.0 + 2.0 * PI)).abs() < 1e-12);
+        assert!((path.curvature_at(2.0 + PI / 2.0) - 1.0).abs() < 1e-12);
+        assert!((path.curvature_at(4.0 + 1.5 * PI) - 1.0).abs() < 1e-12);
+        assert!((path.sdf(Point2::new(1.0, 0.0)) + 1.0).abs() < 1e-12);
+        // relative commands aren't supported
+        let error = closed_path_from_svg("M0-1 2-1A1,1 0 012 1L0 1a1 1 0 01 0-2").err();
+        assert_eq!(error, Some(ParseError::UnsupportedCommand('a')));
+    }
+
+    #[test]
+    fn test_invalid_path_data_is_rejected() {
+        let cases = [
+            ("", ParseError::Empty),
+            ("M 0 0 Z", ParseError::Empty),
+            ("L 1 0 L 1 1 Z", ParseError::MissingMoveTo),
+            ("M 0 0 L 1 0 L 1 Z", ParseError::MissingNumbers('L')),
+            (
+                "M 0 0 L 1 0 C 1 1 0 1 0 0",
+                ParseError::UnsupportedCommand('C'),
+            ),
+            ("M 0 0 L 1 0 # Z", ParseError::UnsupportedCommand('#')),
+            ("M 0 0 A 1 1 0 2 1 1 1 Z", ParseError::InvalidFlag('2')),
+            (
+                "M 0 0 A 1 2 0 0 1 1 1 Z",
+                ParseError::NonCircularArc { rx: 1.0, ry: 2.0 },
+            ),
+            ("M 0 0 L 1 0 L 1 1", ParseError::NotClosed),
+            ("M 0 0 L 1 0 L 1 1 Z M 2 2", ParseError::MultipleSubpaths),
+            ("M 0 0 L 1 0 L 1 1 Z L 2 2", ParseError::MultipleSubpaths),
+            // so large a radius that the angles of the ends round to the same
+            (
+                "M 0 0 A 1e30 1e30 0 0 1 1 0 Z",
+                ParseError::InvalidSubpath(GeometryError::ZeroLengthArc),
+            ),
+        ];
+        for (d, error) in cases {
+            assert_eq!(closed_path_from_svg(d).err(), Some(error), "{:?}", d);
+        }
+        // splines have no SVG command this can read
+        let spline: ClosedPath<f64> = random_closed_path(7, 8).racing_line(0.1, 40).into();
+        let svg = spline.to_svg(0.05);
+        assert_eq!(
+            closed_path_from_svg(path_data(&svg)).err(),
+            Some(ParseError::UnsupportedCommand('C'))
+        );
+    }
+}
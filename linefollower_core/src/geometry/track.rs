@@ -1,10 +1,38 @@
+use super::arc_path::ArcPath;
+use super::closed_path::{ClosedPath, SubPath};
+use super::line_path::LinePath;
+use super::speed_profile::SpeedProfile;
+use super::spline_path::SplinePath;
 use crate::utils::traits::Float;
 use nalgebra::{Point2, Vector2};
+use std::io::{self, BufWriter, Write};
+use std::sync::Arc;
+
+/// Any track, shared between the simulations which follow it, possibly on other threads
+pub type SharedTrack = Arc<dyn Track<f64> + Send + Sync>;
+
+// all the tracks are plain data, which keeps them usable as a `SharedTrack`
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync + ?Sized>() {}
+    assert_send_sync::<ClosedPath<f64>>();
+    assert_send_sync::<SubPath<f64>>();
+    assert_send_sync::<LinePath<f64>>();
+    assert_send_sync::<ArcPath<f64>>();
+    assert_send_sync::<SplinePath<f64>>();
+    assert_send_sync::<dyn Track<f64> + Send + Sync>();
+};
+
 pub trait Track<F>
 where
     F: Float,
 {
     fn sdf(&self, p: Point2<F>) -> F;
+    /// Unit gradient of `sdf` at `p`, which points away from the track on its right and
+    /// towards it on its left. Defaults to a finite difference of `sdf`,
+    /// implementers with an analytic expression should override it.
+    fn normal_at(&self, p: Point2<F>) -> Vector2<F> {
+        finite_difference_normal(self, p)
+    }
     fn length(&self) -> F;
     fn first_point(&self) -> Point2<F> {
         self.point_at(F::zero())
@@ -13,7 +41,26 @@ where
         self.point_at(self.length())
     }
     fn point_at(&self, d: F) -> Point2<F>;
-    fn tangent_at(&self, d: F) -> Vector2<F>;
+    /// Unit tangent at arc-length `d`. Defaults to a finite difference of `point_at`,
+    /// implementers with an analytic expression should override it.
+    fn tangent_at(&self, d: F) -> Vector2<F> {
+        finite_difference_tangent(self, d)
+    }
+    /// Signed curvature at arc-length `d`, positive where the track turns left.
+    /// Defaults to a finite difference of `tangent_at`.
+    fn curvature_at(&self, d: F) -> F {
+        let length = self.length();
+        let h = F::from(1e-6).unwrap() * num::Float::max(length, F::one());
+        let d0 = num::Float::max(d - h, F::zero());
+        let d1 = num::Float::min(d + h, length);
+        let (t0, t1) = (self.tangent_at(d0), self.tangent_at(d1));
+        let turn = num::Float::atan2(t0.x * t1.y - t0.y * t1.x, t0.dot(&t1));
+        turn / (d1 - d0)
+    }
+    /// Point reached after traveling along the track for a time `t` following `profile`
+    fn point_at_time(&self, t: F, profile: &SpeedProfile<F>) -> Point2<F> {
+        self.point_at(profile.distance_at(t))
+    }
     fn point_projection_distance(&self, p: Point2<F>) -> F;
     fn point_projection_tangent(&self, p: Point2<F>) -> Vector2<F> {
         let d = self.point_projection_distance(p);
@@ -39,6 +86,51 @@ where
     }
 }
 
+/// Unit tangent from a central difference of `point_at`, one-sided at the ends of the track
+pub fn finite_difference_tangent<F, T>(track: &T, d: F) -> Vector2<F>
+where
+    F: Float,
+    T: Track<F> + ?Sized,
+{
+    let length = track.length();
+    let h = F::from(1e-6).unwrap() * num::Float::max(length, F::one());
+    let d0 = num::Float::max(d - h, F::zero());
+    let d1 = num::Float::min(d + h, length);
+    (track.point_at(d1) - track.point_at(d0)).normalize()
+}
+
+/// Unit gradient of `sdf` from central differences along each axis, at a step relative
+/// to the length of the track
+pub fn finite_difference_normal<F, T>(track: &T, p: Point2<F>) -> Vector2<F>
+where
+    F: Float,
+    T: Track<F> + ?Sized,
+{
+    let h = F::from(1e-6).unwrap() * num::Float::max(track.length(), F::one());
+    let (dx, dy) = (Vector2::new(h, F::zero()), Vector2::new(F::zero(), h));
+    Vector2::new(
+        track.sdf(p + dx) - track.sdf(p - dx),
+        track.sdf(p + dy) - track.sdf(p - dy),
+    )
+    .normalize()
+}
+
+/// Polyline interpolating between two tracks, for animating one into the other.
+/// Both are sampled at `n + 1` matching fractions of their arc-length, and the samples
+/// are blended linearly: `t = 0` gives the samples of `track_a`, `t = 1` those of `track_b`.
+pub fn morph<F, A, B>(track_a: &A, track_b: &B, t: F, n: usize) -> Vec<Point2<F>>
+where
+    F: Float,
+    A: Track<F> + ?Sized,
+    B: Track<F> + ?Sized,
+{
+    track_a
+        .sample_points_num(n)
+        .zip(track_b.sample_points_num(n))
+        .map(|(a, b)| a + (b - a) * t)
+        .collect()
+}
+
 pub fn sample_points<F, T>(track: &T, dx: F) -> impl Iterator<Item = Point2<F>> + '_
 where
     F: Float,
@@ -54,3 +146,231 @@ where
         }
     })
 }
+
+/// Curvature every `spacing` along the track, as (arc-length, curvature) pairs from the start of
+/// the track up to its end. At a jump of the curvature, like where a line meets an arc,
+/// it is whatever `curvature_at` gives there.
+pub fn curvature_profile<F, T>(track: &T, spacing: F) -> Vec<(F, F)>
+where
+    F: Float,
+    T: Track<F> + ?Sized,
+{
+    assert!(spacing > F::zero(), "the spacing must be positive");
+    let n = num::Float::floor(track.length() / spacing)
+        .to_usize()
+        .unwrap();
+    (0..=n)
+        .map(|i| {
+            let d = F::from_usize(i).unwrap() * spacing;
+            (d, track.curvature_at(d))
+        })
+        .collect()
+}
+
+/// Writes a profile from `curvature_profile` as CSV, with an `arc_length,curvature` header
+pub fn write_curvature_profile_csv<F, W>(profile: &[(F, F)], writer: W) -> io::Result<()>
+where
+    F: Float,
+    W: Write,
+{
+    let mut writer = BufWriter::new(writer);
+    writeln!(writer, "arc_length,curvature")?;
+    for (d, curvature) in profile {
+        writeln!(writer, "{},{}", d, curvature)?;
+    }
+    writer.flush()
+}
+
+/// Like `sample_points`, but on the curve parallel to the track at a distance `offset`
+/// to its left (to its right if negative). Such points have an sdf of `-offset` as long
+/// as `offset` is smaller than the radius of the tightest turn and they aren't next to a corner.
+pub fn sample_offset_points<F, T>(
+    track: &T,
+    dx: F,
+    offset: F,
+) -> impl Iterator<Item = Point2<F>> + '_
+where
+    F: Float,
+    T: Track<F>,
+{
+    let mut d = F::zero();
+    std::iter::from_fn(move || {
+        d += dx;
+        if d > track.length() {
+            None
+        } else {
+            let t = track.tangent_at(d);
+            Some(track.point_at(d) + Vector2::new(-t.y, t.x) * offset)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::arc_path::ArcPath;
+    use crate::new_arc_path;
+    use std::f64::consts::PI;
+
+    // only provides what a parametric curve would, using the default tangent
+    struct PointsOnly(ArcPath<f64>);
+
+    impl Track<f64> for PointsOnly {
+        fn sdf(&self, p: Point2<f64>) -> f64 {
+            self.0.sdf(p)
+        }
+        fn length(&self) -> f64 {
+            self.0.length()
+        }
+        fn point_at(&self, d: f64) -> Point2<f64> {
+            self.0.point_at(d)
+        }
+        fn point_projection_distance(&self, p: Point2<f64>) -> f64 {
+            self.0.point_projection_distance(p)
+        }
+    }
+
+    #[test]
+    fn test_default_tangent_matches_arc() {
+        for arc in [
+            new_arc_path![1.0, -2.0, 1.5, -PI / 3.0, PI],
+            new_arc_path![0.0, 0.0, 0.5, PI / 2.0, -PI / 2.0],
+        ] {
+            let track = PointsOnly(arc.clone());
+            for i in 0..=50 {
+                let d = i as f64 * arc.length() / 50.0;
+                let error = (track.tangent_at(d) - arc.tangent_at(d)).norm();
+                assert!(error < 1e-5, "d = {}, error = {}", d, error);
+            }
+        }
+    }
+
+    // analytic tangents, but the default curvature
+    struct WithTangents(ArcPath<f64>);
+
+    impl Track<f64> for WithTangents {
+        fn sdf(&self, p: Point2<f64>) -> f64 {
+            self.0.sdf(p)
+        }
+        fn length(&self) -> f64 {
+            self.0.length()
+        }
+        fn point_at(&self, d: f64) -> Point2<f64> {
+            self.0.point_at(d)
+        }
+        fn tangent_at(&self, d: f64) -> Vector2<f64> {
+            self.0.tangent_at(d)
+        }
+        fn point_projection_distance(&self, p: Point2<f64>) -> f64 {
+            self.0.point_projection_distance(p)
+        }
+    }
+
+    #[test]
+    fn test_default_curvature_matches_arc() {
+        for arc in [
+            new_arc_path![1.0, -2.0, 1.5, -PI / 3.0, PI],
+            new_arc_path![0.0, 0.0, 0.5, PI / 2.0, -PI / 2.0],
+        ] {
+            let track = WithTangents(arc.clone());
+            for i in 0..=50 {
+                let d = i as f64 * arc.length() / 50.0;
+                let error = track.curvature_at(d) - arc.curvature_at(d);
+                assert!(error.abs() < 1e-6, "d = {}, error = {}", d, error);
+            }
+        }
+    }
+
+    #[test]
+    fn test_morph_endpoints_are_the_tracks() {
+        use crate::geometry::closed_path::{predefined_circle, predefined_closed_path};
+        let a = predefined_closed_path();
+        let b = predefined_circle(3.0);
+        let n = 200;
+        let samples_a = a.sample_points_num(n).collect::<Vec<_>>();
+        let samples_b = b.sample_points_num(n).collect::<Vec<_>>();
+        assert_eq!(morph(&a, &b, 0.0, n), samples_a);
+        let at_one = morph(&a, &b, 1.0, n);
+        assert_eq!(at_one.len(), n + 1);
+        for (p, q) in at_one.iter().zip(samples_b.iter()) {
+            assert!((p - q).norm() < 1e-12);
+        }
+        // halfway, each point is the midpoint of the matching samples
+        let halfway = morph(&a, &b, 0.5, n);
+        for ((p, a), b) in halfway.iter().zip(samples_a.iter()).zip(samples_b.iter()) {
+            let midpoint = *a + (b - a) * 0.5;
+            assert!((*p - midpoint).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_shared_tracks_are_usable_from_other_threads() {
+        use crate::geometry::closed_path::predefined_closed_path;
+        // a track which closes on itself and one which doesn't
+        let closed: SharedTrack = Arc::new(predefined_closed_path());
+        let open: SharedTrack =
+            Arc::new(LinePath::new(Point2::new(0.0, 0.0), Point2::new(4.0, 0.0)));
+        let p = Point2::new(1.0, -1.0);
+        let expected = [closed.sdf(p), open.sdf(p)];
+        assert_eq!(expected[1], 1.0);
+        let handles = [closed, open].map(|track| std::thread::spawn(move || track.sdf(p)));
+        for (handle, expected) in handles.into_iter().zip(expected) {
+            assert_eq!(handle.join().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_curvature_profile_of_an_oval() {
+        use crate::geometry::closed_path::{ClosedPath, SubPath};
+        use crate::new_line_path;
+        // 4 m straights joined by half circles of radius 2
+        let r = 2.0;
+        let oval = ClosedPath::new(vec![
+            SubPath::Line(new_line_path![0.0, -r, 4.0, -r]),
+            SubPath::Arc(new_arc_path![4.0, 0.0, r, -PI / 2.0, PI / 2.0]),
+            SubPath::Line(new_line_path![4.0, r, 0.0, r]),
+            SubPath::Arc(new_arc_path![0.0, 0.0, r, PI / 2.0, 3.0 * PI / 2.0]),
+        ]);
+        let spacing = 0.05;
+        let profile = curvature_profile(&oval, spacing);
+        assert_eq!(
+            profile.len(),
+            (oval.length() / spacing).floor() as usize + 1
+        );
+        // the straights and the turns, by arc-length
+        let junctions = [0.0, 4.0, 4.0 + PI * r, 8.0 + PI * r, oval.length()];
+        for &(d, curvature) in profile.iter() {
+            if junctions.iter().any(|j| (d - j).abs() < 1e-9) {
+                continue;
+            }
+            let section = junctions.iter().filter(|&&j| j < d).count() - 1;
+            let expected = if section % 2 == 0 { 0.0 } else { 1.0 / r };
+            assert!(
+                (curvature - expected).abs() < 1e-9,
+                "d = {}: {}",
+                d,
+                curvature
+            );
+        }
+        // the transitions show up where the turns start and end
+        let turning = profile
+            .windows(2)
+            .filter(|w| (w[0].1 > 0.25) != (w[1].1 > 0.25))
+            .map(|w| w[1].0)
+            .collect::<Vec<_>>();
+        assert_eq!(turning.len(), 3, "{:?}", turning);
+        for (d, j) in turning.iter().zip(&junctions[1..4]) {
+            assert!(d - j > -1e-9 && d - j <= spacing + 1e-9, "{} vs {}", d, j);
+        }
+        // the last turn goes on until the end
+        assert!((profile.last().unwrap().1 - 1.0 / r).abs() < 1e-9);
+
+        let mut csv = Vec::new();
+        write_curvature_profile_csv(&profile, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("arc_length,curvature"));
+        assert_eq!(lines.next(), Some("0,0"));
+        assert_eq!(lines.count(), profile.len() - 1);
+    }
+}
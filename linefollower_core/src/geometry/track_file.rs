@@ -0,0 +1,221 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::closed_path::{predefined_tracks, ClosedPath};
+use super::svg::closed_path_from_svg_document;
+
+/// Version of the track file format written by `track_to_json`
+pub const TRACK_FILE_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct TrackFileRef<'a> {
+    version: u32,
+    track: &'a ClosedPath<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TrackFile {
+    Versioned {
+        version: u32,
+        track: ClosedPath<f64>,
+    },
+    // bare paths, as saved by the first versions of the path editor
+    Legacy(ClosedPath<f64>),
+}
+
+pub fn track_to_json(track: &ClosedPath<f64>) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(&TrackFileRef {
+        version: TRACK_FILE_VERSION,
+        track,
+    })
+}
+
+/// Parses a track in the versioned format, falling back to a bare serialized `ClosedPath`
+pub fn track_from_json(json: &str) -> io::Result<ClosedPath<f64>> {
+    let file: TrackFile =
+        serde_json::from_str(json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    match file {
+        TrackFile::Versioned { version, track } if version <= TRACK_FILE_VERSION => Ok(track),
+        TrackFile::Versioned { version, .. } => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "unsupported track file version {} (latest supported is {})",
+                version, TRACK_FILE_VERSION
+            ),
+        )),
+        TrackFile::Legacy(track) => Ok(track),
+    }
+}
+
+pub fn save_track<P: AsRef<Path>>(track: &ClosedPath<f64>, filename: P) -> io::Result<()> {
+    let json = track_to_json(track).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    std::fs::write(filename, json)
+}
+
+pub fn load_track<P: AsRef<Path>>(filename: P) -> io::Result<ClosedPath<f64>> {
+    let json = std::fs::read_to_string(filename)?;
+    track_from_json(&json)
+}
+
+/// File formats a track can be stored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackFormat {
+    Json,
+    Svg,
+    Dxf,
+}
+
+impl TrackFormat {
+    fn from_extension(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => Some(TrackFormat::Json),
+            "svg" => Some(TrackFormat::Svg),
+            "dxf" => Some(TrackFormat::Dxf),
+            _ => None,
+        }
+    }
+
+    fn from_contents(contents: &str) -> Option<Self> {
+        let contents = contents.trim_start();
+        if contents.starts_with('{') {
+            Some(TrackFormat::Json)
+        } else if contents.starts_with("<?xml") || contents.starts_with("<svg") {
+            Some(TrackFormat::Svg)
+        } else if contents.starts_with('0') && contents.contains("SECTION") {
+            Some(TrackFormat::Dxf)
+        } else {
+            None
+        }
+    }
+}
+
+/// Loads a track in any of the supported formats, chosen from the file extension
+/// or, if the extension is missing or unknown, from the file contents.
+pub fn load_track_auto<P: AsRef<Path>>(filename: P) -> io::Result<ClosedPath<f64>> {
+    let filename = filename.as_ref();
+    let contents = std::fs::read_to_string(filename)?;
+    let format = TrackFormat::from_extension(filename)
+        .or_else(|| TrackFormat::from_contents(&contents))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "unknown track format for {} (supported formats: JSON (.json), SVG (.svg))",
+                    filename.display()
+                ),
+            )
+        })?;
+    match format {
+        TrackFormat::Json => track_from_json(&contents),
+        TrackFormat::Svg => closed_path_from_svg_document(&contents)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        // detected so that the error is clear, but there is no importer for it yet
+        TrackFormat::Dxf => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "DXF tracks can't be imported yet (supported formats: JSON (.json), SVG (.svg))",
+        )),
+    }
+}
+
+/// Writes every predefined track as `<name>.json` into `dir`, creating it if needed.
+/// Returns the paths of the written files.
+pub fn export_predefined_tracks<P: AsRef<Path>>(dir: P) -> io::Result<Vec<PathBuf>> {
+    let dir = dir.as_ref();
+    std::fs::create_dir_all(dir)?;
+    predefined_tracks()
+        .into_iter()
+        .map(|(name, track)| {
+            let filename = dir.join(format!("{}.json", name));
+            save_track(&track, &filename)?;
+            Ok(filename)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::closed_path::predefined_closed_path;
+    use crate::geometry::track::Track;
+
+    #[test]
+    fn test_export_predefined_tracks() {
+        let dir = std::env::temp_dir().join(format!("linefollower_tracks_{}", std::process::id()));
+        let files = export_predefined_tracks(&dir).unwrap();
+        let tracks = predefined_tracks();
+        assert_eq!(files.len(), tracks.len());
+        for (file, (_, track)) in files.iter().zip(tracks.iter()) {
+            let loaded = load_track(file).unwrap();
+            assert!((loaded.length() - track.length()).abs() < 1e-12);
+            for i in 0..100 {
+                let d = i as f64 * track.length() / 100.0;
+                assert!((loaded.point_at(d) - track.point_at(d)).norm() < 1e-12);
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_track_auto_detects_format() {
+        let dir = std::env::temp_dir().join(format!("linefollower_auto_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let track = predefined_closed_path();
+        let json = track_to_json(&track).unwrap();
+
+        // by extension, and by contents when the extension doesn't say
+        for name in ["track.json", "track.TRACK", "track"] {
+            let filename = dir.join(name);
+            std::fs::write(&filename, &json).unwrap();
+            let loaded = load_track_auto(&filename).unwrap();
+            assert!((loaded.length() - track.length()).abs() < 1e-12);
+        }
+
+        let filename = dir.join("track.svg");
+        std::fs::write(&filename, "<svg></svg>").unwrap();
+        let error = load_track_auto(&filename).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let filename = dir.join("track.dxf");
+        std::fs::write(&filename, "0\nSECTION\n").unwrap();
+        let error = load_track_auto(&filename).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+        assert!(error.to_string().contains("SVG (.svg)"));
+
+        let filename = dir.join("track.txt");
+        std::fs::write(&filename, "not a track").unwrap();
+        let error = load_track_auto(&filename).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("supported formats"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_json_and_svg_files_load_the_same_track() {
+        let dir = std::env::temp_dir().join(format!("linefollower_svg_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let track = predefined_closed_path();
+        let svg = track.to_svg(0.05);
+        let (json_file, svg_file) = (dir.join("track.json"), dir.join("track.svg"));
+        save_track(&track, &json_file).unwrap();
+        std::fs::write(&svg_file, &svg).unwrap();
+
+        let from_json = load_track_auto(&json_file).unwrap();
+        // without an extension, too
+        let contents_only = dir.join("track");
+        std::fs::write(&contents_only, &svg).unwrap();
+        for filename in [&svg_file, &contents_only] {
+            let from_svg = load_track_auto(filename).unwrap();
+            assert!((from_svg.length() - from_json.length()).abs() < 1e-9);
+            for i in 0..200 {
+                let d = i as f64 * from_json.length() / 200.0;
+                assert!((from_svg.point_at(d) - from_json.point_at(d)).norm() < 1e-9);
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
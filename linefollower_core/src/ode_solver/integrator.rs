@@ -42,6 +42,14 @@ where
     pub fn get_state(&self) -> Vector<N> {
         self.system.x
     }
+
+    pub fn set_state(&mut self, x: Vector<N>) {
+        self.system.x = x;
+    }
+
+    pub fn get_time(&self) -> f64 {
+        self.system.t
+    }
 }
 
 impl<const N: usize, const U: usize> Integrator<N, U>
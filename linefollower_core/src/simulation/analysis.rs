@@ -0,0 +1,376 @@
+use std::sync::Arc;
+
+use crate::geometry::closed_path::predefined_circle;
+use crate::geometry::track::Track;
+use crate::ode_solver::ode_system::Vector;
+use nalgebra::Vector2;
+
+use super::robot::{ControllerConfig, RobotParams, RobotSimulation};
+
+/// Integration time step of the analysis runs
+const DT: f64 = 1.0 / 240.0;
+/// Duration of the window over which the error is averaged when checking for settling
+const SETTLING_WINDOW: f64 = 2.0;
+/// The error is considered settled when its window average changes less than this
+const SETTLING_TOLERANCE: f64 = 1e-5;
+/// Give up waiting for the error to settle after this long
+const MAX_SETTLING_TIME: f64 = 300.0;
+/// Radius of the circle followed while measuring the disturbance response,
+/// large enough to be close to a straight line
+const DISTURBANCE_RADIUS: f64 = 10.0;
+/// Give up waiting for the robot to reach the line after this long
+const MAX_CAPTURE_TIME: f64 = 60.0;
+/// Range of frequencies the crossovers of the linearized loop are looked for in, in rad/s
+const MARGIN_FREQUENCIES: (f64, f64) = (1e-3, 1e4);
+/// Resolution of the search for crossovers, refined by bisection once one is found
+const MARGIN_SAMPLES_PER_DECADE: usize = 200;
+
+/// Runs the robot on a circle of the given radius until the lateral error settles,
+/// returning the residual (signed) lateral error averaged over the last window.
+///
+/// Since following a circle requires a constant turn rate, a pure P controller needs
+/// a nonzero error to produce it, while integral action drives the error to zero.
+pub fn steady_state_error(config: &ControllerConfig, radius: f64) -> f64 {
+    let path = Arc::new(predefined_circle(radius));
+    let p0 = path.first_point();
+    // start on the circle, already facing along it
+    let x0 = Vector::<7>::from_column_slice(&[p0.x, p0.y, 0.0, 0.0, 0.0, 0.0, 0.0]);
+    let mut robot_sim = RobotSimulation::from_config(x0, config, path);
+    let steps_per_window = (SETTLING_WINDOW / DT) as usize;
+    let mut prev_mean = f64::INFINITY;
+    loop {
+        let mut total = 0.0;
+        for _ in 0..steps_per_window {
+            robot_sim.step(DT);
+            total += robot_sim.robot_sdf_to_path();
+        }
+        let mean = total / steps_per_window as f64;
+        if (mean - prev_mean).abs() < SETTLING_TOLERANCE
+            || robot_sim.get_time() >= MAX_SETTLING_TIME
+        {
+            return mean;
+        }
+        prev_mean = mean;
+    }
+}
+
+/// One point of the closed-loop response to a sinusoidal disturbance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrequencyResponse {
+    /// Amplitude of the lateral error divided by the amplitude of the disturbance
+    pub amplitude_ratio: f64,
+    /// Phase of the lateral error relative to the disturbance, in radians
+    pub phase: f64,
+}
+
+/// Pushes the robot sideways by `amplitude * sin(2 pi frequency t)` while it follows a circle,
+/// and measures the lateral error it ends up with once the response settles.
+///
+/// The disturbance displaces the robot towards its right (the positive sdf side), so this is
+/// one point of the sensitivity function from a lateral output disturbance to the error:
+/// at low frequencies the controller has time to correct and the ratio is small.
+pub fn disturbance_response(
+    config: &ControllerConfig,
+    frequency: f64,
+    amplitude: f64,
+) -> FrequencyResponse {
+    let path = Arc::new(predefined_circle(DISTURBANCE_RADIUS));
+    let p0 = path.first_point();
+    let x0 = Vector::<7>::from_column_slice(&[p0.x, p0.y, 0.0, 0.0, 0.0, 0.0, 0.0]);
+    let mut robot_sim = RobotSimulation::from_config(x0, config, path);
+    let omega = 2.0 * std::f64::consts::PI * frequency;
+    // measure over whole periods so that the phase estimate isn't biased
+    let period_steps = (1.0 / (frequency * DT)).round() as usize;
+    let periods = ((SETTLING_WINDOW * frequency).ceil() as usize).max(1);
+    let steps_per_window = period_steps * periods;
+    let mut disturbance = 0.0;
+    let mut prev_ratio = f64::INFINITY;
+    loop {
+        // lock-in detection of the component of the error at the disturbance frequency
+        let (mut in_phase, mut quadrature) = (0.0, 0.0);
+        for _ in 0..steps_per_window {
+            robot_sim.step(DT);
+            let t = robot_sim.get_time();
+            let next_disturbance = amplitude * (omega * t).sin();
+            let theta = robot_sim.get_state()[2];
+            let right = Vector2::new(theta.sin(), -theta.cos());
+            robot_sim.displace(right * (next_disturbance - disturbance));
+            disturbance = next_disturbance;
+
+            let error = robot_sim.robot_sdf_to_path();
+            in_phase += error * (omega * t).sin();
+            quadrature += error * (omega * t).cos();
+        }
+        let scale = 2.0 / steps_per_window as f64;
+        let (in_phase, quadrature) = (in_phase * scale, quadrature * scale);
+        let ratio = in_phase.hypot(quadrature) / amplitude;
+        if (ratio - prev_ratio).abs() < SETTLING_TOLERANCE.max(1e-3 * ratio)
+            || robot_sim.get_time() >= MAX_SETTLING_TIME
+        {
+            return FrequencyResponse {
+                amplitude_ratio: ratio,
+                phase: quadrature.atan2(in_phase),
+            };
+        }
+        prev_ratio = ratio;
+    }
+}
+
+/// Starts the robot `initial_offset` to the right of a wide circle (to its left if negative),
+/// facing along it, and returns the time at which it first reaches the line, i.e. when the
+/// sign of its distance to the path changes. This measures how fast the line is acquired,
+/// unlike the tracking error once on it. While the loop is about linear, the time hardly
+/// depends on the offset; it grows once the robot has to turn sharply to head for the line.
+/// Returns `None` if the robot doesn't get there within `MAX_CAPTURE_TIME`, which includes
+/// loops so damped that the robot only creeps up to the line.
+pub fn time_to_capture(config: &ControllerConfig, initial_offset: f64) -> Option<f64> {
+    let path = Arc::new(predefined_circle(DISTURBANCE_RADIUS));
+    let p0 = path.first_point();
+    // facing along +x, so the right is -y
+    let x0 =
+        Vector::<7>::from_column_slice(&[p0.x, p0.y - initial_offset, 0.0, 0.0, 0.0, 0.0, 0.0]);
+    let mut robot_sim = RobotSimulation::from_config(x0, config, path);
+    let initial_sign = robot_sim.robot_sdf_to_path().signum();
+    if robot_sim.robot_sdf_to_path() == 0.0 {
+        return Some(0.0);
+    }
+    while robot_sim.get_time() < MAX_CAPTURE_TIME {
+        robot_sim.step(DT);
+        if robot_sim.is_diverged() {
+            return None;
+        }
+        let sdf = robot_sim.robot_sdf_to_path();
+        if sdf == 0.0 || sdf.signum() != initial_sign {
+            return Some(robot_sim.get_time());
+        }
+    }
+    None
+}
+
+/// How close the motor commands came to a limit during a run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ControlHeadroom {
+    /// Largest magnitude of either motor command
+    pub peak_command: f64,
+    /// Fraction of the steps in which a motor command was within the margin of the limit
+    pub near_limit_fraction: f64,
+}
+
+/// Steps `robot_sim` for `duration` seconds and reports how much of the motor command range
+/// it used: a command counts as near the limit when its magnitude is at least
+/// `(1 - margin) * limit`, e.g. `margin = 0.1` for "within 10% of the limit".
+pub fn control_headroom(
+    robot_sim: &mut RobotSimulation,
+    duration: f64,
+    limit: f64,
+    margin: f64,
+) -> ControlHeadroom {
+    let steps = (duration / DT).round() as usize;
+    let threshold = (1.0 - margin) * limit;
+    let mut peak_command: f64 = 0.0;
+    let mut near_limit_steps = 0;
+    for _ in 0..steps {
+        robot_sim.step(DT);
+        let command = robot_sim.get_commanded_controls().amax();
+        peak_command = peak_command.max(command);
+        if command >= threshold {
+            near_limit_steps += 1;
+        }
+    }
+    ControlHeadroom {
+        peak_command,
+        near_limit_fraction: near_limit_steps as f64 / steps.max(1) as f64,
+    }
+}
+
+/// Gain and phase margins, in dB and degrees, of the loop linearized around following a straight
+/// line with the default robot and error, its signed distance to the path. Near the line, the
+/// distance is the integral of `speed` times the heading error, which is the integral of the yaw
+/// rate, and the wheel commands make the yaw rate follow the PID's output through the second
+/// order response of the motors, so the open loop is
+/// `L(s) = (kp + ki / s + kd s) * speed * w0^2 / ((s^2 + 2 xi w0 s + w0^2) s^2)`.
+/// The actuator delay and the integral limit are neglected.
+///
+/// The phase margin is the smallest one at the frequencies where `|L| = 1`, negative when the
+/// linearized loop is unstable. The gain margin is how much the loop gain can grow before
+/// `|L| = 1` where the phase is -180°; the integral term also makes the phase cross -180° at low
+/// frequencies, where reducing the gain is what destabilizes the loop, which this doesn't report.
+/// Either margin is infinite when there is no such frequency.
+pub fn stability_margins(config: &ControllerConfig) -> (f64, f64) {
+    let RobotParams { w0, xi, .. } = RobotParams::default();
+    let ControllerConfig { kp, ki, kd, speed } = *config;
+    let log_gain = |w: f64| {
+        let pid = f64::hypot(kp, kd * w - ki / w);
+        let motor = w0 * w0 / f64::hypot(w0 * w0 - w * w, 2.0 * xi * w0 * w);
+        (pid * speed * motor / (w * w)).ln()
+    };
+    // continuous in w, unlike the argument of L(jw)
+    let phase = |w: f64| {
+        -std::f64::consts::PI + f64::atan2(kd * w - ki / w, kp)
+            - f64::atan2(2.0 * xi * w0 * w, w0 * w0 - w * w)
+    };
+    let gain_crossovers = crossovers(log_gain);
+    let phase_crossovers = crossovers(|w| phase(w) + std::f64::consts::PI);
+
+    let phase_margin = gain_crossovers
+        .iter()
+        .map(|&w| 180.0 + phase(w).to_degrees())
+        .fold(f64::INFINITY, f64::min);
+    let gain_margin = phase_crossovers
+        .iter()
+        .map(|&w| -20.0 * log_gain(w) / std::f64::consts::LN_10)
+        .filter(|&margin| margin > 0.0)
+        .fold(f64::INFINITY, f64::min);
+    (gain_margin, phase_margin)
+}
+
+/// Frequencies in `MARGIN_FREQUENCIES` at which `f` changes sign
+fn crossovers<F: Fn(f64) -> f64>(f: F) -> Vec<f64> {
+    let (low, high) = MARGIN_FREQUENCIES;
+    let samples = ((high / low).log10() * MARGIN_SAMPLES_PER_DECADE as f64) as usize;
+    let frequency = |k: usize| low * (high / low).powf(k as f64 / samples as f64);
+    let mut crossovers = Vec::new();
+    for k in 0..samples {
+        let (mut a, mut b) = (frequency(k), frequency(k + 1));
+        let fa = f(a);
+        if fa.signum() == f(b).signum() {
+            continue;
+        }
+        for _ in 0..60 {
+            let middle = (a * b).sqrt();
+            if f(middle).signum() == fa.signum() {
+                a = middle;
+            } else {
+                b = middle;
+            }
+        }
+        crossovers.push((a * b).sqrt());
+    }
+    crossovers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::closed_path::predefined_closed_path;
+
+    #[test]
+    fn test_integral_gain_reduces_steady_state_error() {
+        let p_only = ControllerConfig {
+            kp: 10.0,
+            ki: 0.0,
+            kd: 5.0,
+            speed: 0.5,
+        };
+        let with_integral = ControllerConfig { ki: 10.0, ..p_only };
+        let radius = 2.0;
+        let e_p = steady_state_error(&p_only, radius);
+        let e_pi = steady_state_error(&with_integral, radius);
+        // a P controller settles where kp * e matches the turn rate speed / radius
+        let expected = p_only.speed / (radius * p_only.kp);
+        assert!((e_p - expected).abs() < 0.1 * expected, "{}", e_p);
+        assert!(e_pi.abs() < 0.1 * e_p.abs(), "{} vs {}", e_pi, e_p);
+    }
+
+    #[test]
+    fn test_low_frequency_disturbances_are_rejected() {
+        let config = ControllerConfig {
+            kp: 10.0,
+            ki: 0.0,
+            kd: 5.0,
+            speed: 0.5,
+        };
+        let low = disturbance_response(&config, 0.02, 0.01);
+        let high = disturbance_response(&config, 2.0, 0.01);
+        assert!(low.amplitude_ratio < 0.2, "{:?}", low);
+        assert!(
+            low.amplitude_ratio < high.amplitude_ratio,
+            "{:?} {:?}",
+            low,
+            high
+        );
+        // the heading responds to the lateral error through a double integration,
+        // so slow disturbances leave an error close to antiphase
+        assert!(low.phase > std::f64::consts::FRAC_PI_2, "{:?}", low);
+    }
+
+    #[test]
+    fn test_aggressive_gains_use_up_the_control_range() {
+        let run = |kp: f64, kd: f64| {
+            let path = Arc::new(predefined_closed_path());
+            // slightly off the first straight
+            let x0 = Vector::<7>::from_column_slice(&[1.0, -3.97, 0.0, 0.0, 0.0, 0.0, 0.0]);
+            let mut robot_sim = RobotSimulation::new(x0, kp, 0.0, kd, 0.5, path);
+            control_headroom(&mut robot_sim, 20.0, 20.0, 0.1)
+        };
+        let mild = run(10.0, 1.0);
+        let aggressive = run(1000.0, 100.0);
+        assert!(mild.near_limit_fraction < 0.05, "{:?}", mild);
+        assert!(aggressive.near_limit_fraction > 0.5, "{:?}", aggressive);
+        assert!(aggressive.peak_command > mild.peak_command);
+    }
+
+    #[test]
+    fn test_larger_offsets_take_longer_to_capture() {
+        let config = ControllerConfig {
+            kp: 20.0,
+            ki: 0.0,
+            kd: 10.0,
+            speed: 0.5,
+        };
+        let times = [0.1, 0.3, 0.5].map(|offset| time_to_capture(&config, offset).unwrap());
+        assert!(times[0] > 0.0, "{:?}", times);
+        assert!(times[1] > times[0] && times[2] > times[1], "{:?}", times);
+        // from the other side too
+        assert!(time_to_capture(&config, -0.3).unwrap() > times[0]);
+        assert_eq!(time_to_capture(&config, 0.0), Some(0.0));
+        // turning in place never gets it anywhere
+        let standing = ControllerConfig {
+            speed: 0.0,
+            ..config
+        };
+        assert_eq!(time_to_capture(&standing, 0.3), None);
+    }
+
+    #[test]
+    fn test_detuned_gains_have_a_larger_phase_margin() {
+        // fast enough for the motors' lag to eat most of the phase lead of the derivative
+        let config = ControllerConfig {
+            kp: 20.0,
+            ki: 0.0,
+            kd: 20.0,
+            speed: 1.0,
+        };
+        let scaled = |factor: f64| ControllerConfig {
+            kp: config.kp * factor,
+            ki: config.ki * factor,
+            kd: config.kd * factor,
+            ..config
+        };
+        let (gain_margin, phase_margin) = stability_margins(&config);
+        let (detuned_gain_margin, detuned_phase_margin) = stability_margins(&scaled(0.5));
+        assert!(
+            gain_margin > 0.0 && phase_margin > 0.0,
+            "{} {}",
+            gain_margin,
+            phase_margin
+        );
+        assert!(
+            detuned_phase_margin > phase_margin + 10.0,
+            "{} vs {}",
+            detuned_phase_margin,
+            phase_margin
+        );
+        // halving the gains takes 6 dB out of the loop gain
+        assert!((detuned_gain_margin - gain_margin - 20.0 * f64::log10(2.0)).abs() < 1e-6);
+        // and growing them by the gain margin leaves no phase margin
+        let critical = scaled(10f64.powf(gain_margin / 20.0));
+        assert!(stability_margins(&critical).1.abs() < 1e-3);
+        // the loop is open when standing still
+        let standing = ControllerConfig {
+            speed: 0.0,
+            ..config
+        };
+        assert_eq!(stability_margins(&standing), (f64::INFINITY, f64::INFINITY));
+    }
+}
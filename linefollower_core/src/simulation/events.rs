@@ -0,0 +1,25 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SimEventKind {
+    /// The robot went once around the whole track
+    LapCompleted,
+    /// None of the sensors detect the line anymore
+    OffTrack,
+    /// The line is detected again after being lost
+    BackOnTrack,
+    /// The state stopped being finite, i.e. the integration diverged
+    NonFiniteState,
+    /// The initial heading of the robot differs too much from the direction of the path
+    InitialHeadingMismatch,
+    /// A motor command had to be clamped to the control limit, after being within it
+    MotorSaturated,
+}
+
+/// A discrete event that happened during a simulation run
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SimEvent {
+    /// Simulation time at which the event was detected, in seconds
+    pub time: f64,
+    pub kind: SimEventKind,
+}
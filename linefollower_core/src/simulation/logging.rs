@@ -0,0 +1,137 @@
+use std::io::{self, BufWriter, Write};
+
+use serde::{Deserialize, Serialize};
+
+use super::robot::RobotSimulation;
+
+/// State of the robot and of its controller after a step, as streamed by a `StreamLogger`.
+/// The same values as the columns of `RobotSimulation::record_csv`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StepRecord {
+    pub time: f64,
+    pub x: f64,
+    pub y: f64,
+    pub theta: f64,
+    pub wl: f64,
+    pub wr: f64,
+    pub sdf_error: f64,
+    pub p_term: f64,
+    pub i_term: f64,
+    pub d_term: f64,
+}
+
+impl StepRecord {
+    pub fn of(robot_sim: &RobotSimulation) -> Self {
+        let x = robot_sim.get_state();
+        Self {
+            time: robot_sim.get_time(),
+            x: x[0],
+            y: x[1],
+            theta: x[2],
+            wl: x[3],
+            wr: x[5],
+            sdf_error: robot_sim.robot_sdf_to_path(),
+            p_term: robot_sim.get_proportional_term(),
+            i_term: robot_sim.get_integral_term(),
+            d_term: robot_sim.get_derivative_term(),
+        }
+    }
+}
+
+/// Writes a `StepRecord` after every step of the simulation it is attached to, as one JSON
+/// object per line (NDJSON), so that runs of any length can be exported without keeping their
+/// trajectory in memory. The output is buffered. Since stepping can't fail, the first write
+/// error is kept and stops the logging, to be returned by `finish`.
+pub struct StreamLogger {
+    writer: BufWriter<Box<dyn Write + Send>>,
+    records: usize,
+    error: Option<io::Error>,
+}
+
+impl StreamLogger {
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        Self {
+            writer: BufWriter::new(Box::new(writer)),
+            records: 0,
+            error: None,
+        }
+    }
+
+    pub fn log(&mut self, record: &StepRecord) {
+        if self.error.is_some() {
+            return;
+        }
+        let result = serde_json::to_writer(&mut self.writer, record)
+            .map_err(io::Error::from)
+            .and_then(|_| self.writer.write_all(b"\n"));
+        match result {
+            Ok(()) => self.records += 1,
+            Err(error) => self.error = Some(error),
+        }
+    }
+
+    /// Number of records written so far
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    /// Flushes the output, returning the number of records written or the first error
+    pub fn finish(mut self) -> io::Result<usize> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.writer.flush()?;
+        Ok(self.records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::closed_path::predefined_closed_path;
+    use crate::ode_solver::ode_system::Vector;
+    use std::sync::{Arc, Mutex};
+
+    /// In-memory output which can still be read once the logger owns it
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_stream_logger_writes_a_json_line_per_step() {
+        let x0 = Vector::<7>::from_column_slice(&[0.5, -3.95, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        let path = Arc::new(predefined_closed_path());
+        let mut robot = RobotSimulation::new(x0, 1.0, 0.0, 0.0, 0.5, path);
+        let buffer = SharedBuffer::default();
+        robot.attach_stream_logger(StreamLogger::new(buffer.clone()));
+        for _ in 0..100 {
+            robot.step(0.01);
+        }
+        let logger = robot.detach_stream_logger().unwrap();
+        assert_eq!(logger.records(), 100);
+        assert_eq!(logger.finish().unwrap(), 100);
+        let last = StepRecord::of(&robot);
+        // nothing is logged once detached
+        robot.step(0.01);
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let records = output
+            .lines()
+            .map(|line| serde_json::from_str::<StepRecord>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 100);
+        assert!((records[0].time - 0.01).abs() < 1e-12);
+        assert!(records.windows(2).all(|w| w[1].time > w[0].time));
+        assert!((records[99].x - last.x).abs() < 1e-12);
+        assert!((records[99].sdf_error - last.sdf_error).abs() < 1e-12);
+    }
+}
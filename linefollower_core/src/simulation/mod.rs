@@ -1 +1,8 @@
+pub mod analysis;
+pub mod events;
+pub mod logging;
+pub mod perception;
+pub mod recording;
 pub mod robot;
+pub mod run;
+pub mod tuning;
@@ -0,0 +1,114 @@
+/// Pose of a straight line under a bar of sensors, from their analog readings alone, as a
+/// `(lateral_offset, heading_error)` pair. The sensors are at `offsets` along the bar, positive
+/// to the robot's left, and each reading fades linearly from 1 right above the line to 0 at
+/// `max_sensor_distance` from it. A line crossing the bar at `y0` with an angle `phi` to the
+/// robot's heading is at `|s - y0| cos(phi)` from the sensor at `s`, so the readings draw a V
+/// whose tip gives the crossing and whose slope gives the angle.
+///
+/// The lateral offset is the distance from the middle of the bar to the line, positive when the
+/// line is on the robot's left, like the sdf of a robot facing along the line. The heading error
+/// is the magnitude of `phi`: the bar can't tell a line crossing it at `phi` from its mirror
+/// image at `-phi`, whose distance to every point of the bar is the same.
+/// Returns `None` unless at least three sensors detect the line, since the V of two of them could
+/// either be on one side of the tip, or straddle it.
+pub fn line_pose_from_signals(
+    offsets: &[f64],
+    signals: &[f64],
+    max_sensor_distance: f64,
+) -> Option<(f64, f64)> {
+    let mut detected: Vec<(f64, f64)> = offsets
+        .iter()
+        .zip(signals)
+        .filter(|(_, &signal)| signal > 0.0)
+        .map(|(&s, &signal)| (s, (1.0 - signal) * max_sensor_distance))
+        .collect();
+    if detected.len() < 3 {
+        return None;
+    }
+    detected.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    // for each pair of neighboring sensors the tip could be between, as well as past either
+    // end, a least-squares fit of `distance = cos(phi) * |s - y0|`, which is linear in cos(phi)
+    // and cos(phi) * y0 once the side of each sensor is fixed; the consistent fit of least
+    // residual wins
+    let mut best: Option<(f64, f64, f64)> = None;
+    for k in 0..=detected.len() {
+        let side = |i: usize| if i < k { -1.0 } else { 1.0 };
+        let (mut suu, mut suw, mut sww, mut sua, mut swa) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for (i, &(s, a)) in detected.iter().enumerate() {
+            let (u, w) = (side(i) * s, -side(i));
+            suu += u * u;
+            suw += u * w;
+            sww += w * w;
+            sua += u * a;
+            swa += w * a;
+        }
+        let det = suu * sww - suw * suw;
+        let cos_phi = (sua * sww - swa * suw) / det;
+        if cos_phi <= 0.0 {
+            continue;
+        }
+        let y0 = (suu * swa - suw * sua) / det / cos_phi;
+        let after = k == 0 || detected[k - 1].0 <= y0;
+        let before = k == detected.len() || y0 <= detected[k].0;
+        if !(after && before) {
+            continue;
+        }
+        let residual: f64 = detected
+            .iter()
+            .map(|&(s, a)| (a - cos_phi * (s - y0).abs()).powi(2))
+            .sum();
+        if best.iter().all(|&(_, _, r)| residual < r) {
+            best = Some((y0, cos_phi, residual));
+        }
+    }
+    best.map(|(y0, cos_phi, _)| {
+        // noise can make the slope a little steeper than the sensors allow
+        let cos_phi = cos_phi.min(1.0);
+        (y0 * cos_phi, cos_phi.acos())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pose_of_a_synthetic_line() {
+        let offsets = [-0.06, -0.03, 0.0, 0.03, 0.06];
+        let max_sensor_distance = 0.05;
+        for (y0, phi) in [
+            (0.0, 0.0),
+            (0.01, 0.3),
+            (-0.02, -0.5),
+            (0.05, 0.4),
+            // past the end of the bar, so that all the sensors are on the same side
+            (0.07, 1.0),
+        ] {
+            let signals = offsets.map(|s: f64| {
+                let distance = (s - y0).abs() * f64::cos(phi);
+                (1.0 - distance / max_sensor_distance).max(0.0)
+            });
+            let (offset, heading_error) =
+                line_pose_from_signals(&offsets, &signals, max_sensor_distance).unwrap();
+            assert!(
+                (offset - y0 * f64::cos(phi)).abs() < 1e-12,
+                "{} {}",
+                y0,
+                phi
+            );
+            assert!(
+                (heading_error - f64::abs(phi)).abs() < 1e-9,
+                "{} {}",
+                y0,
+                phi
+            );
+        }
+        // two sensors can't tell which side of them the line is on
+        let signals = [0.0, 0.0, 0.0, 0.8, 0.4];
+        assert_eq!(
+            line_pose_from_signals(&offsets, &signals, max_sensor_distance),
+            None
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::robot::RobotSimulation;
+
+/// Full state of the robot at some instant of a recorded run
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StateSample {
+    pub time: f64,
+    pub state: [f64; 7],
+}
+
+/// States of a run, to be saved as a golden file and compared against by `replay` once the
+/// dynamics or the controller change
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrajectoryRecorder {
+    samples: Vec<StateSample>,
+}
+
+impl TrajectoryRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the current state of `robot_sim`
+    pub fn record(&mut self, robot_sim: &RobotSimulation) {
+        let mut state = [0.0; 7];
+        state.copy_from_slice(robot_sim.get_state().as_slice());
+        self.samples.push(StateSample {
+            time: robot_sim.get_time(),
+            state,
+        });
+    }
+
+    /// Runs `steps` steps of `dt`, recording the state before the first step and after each
+    /// of them. Stops early, without recording the non-finite state, if the run diverges.
+    pub fn record_run(robot_sim: &mut RobotSimulation, steps: usize, dt: f64) -> Self {
+        let mut recorder = Self::new();
+        recorder.record(robot_sim);
+        for _ in 0..steps {
+            robot_sim.step(dt);
+            if robot_sim.is_diverged() {
+                break;
+            }
+            recorder.record(robot_sim);
+        }
+        recorder
+    }
+
+    pub fn samples(&self) -> &[StateSample] {
+        &self.samples
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, filename: P) -> io::Result<()> {
+        let json = self
+            .to_json()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        std::fs::write(filename, json)
+    }
+
+    pub fn load<P: AsRef<Path>>(filename: P) -> io::Result<Self> {
+        let json = std::fs::read_to_string(filename)?;
+        Self::from_json(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Runs `robot_sim` with a step of `dt` alongside the `reference` recording, which must have been
+/// recorded with the same step from the same state, and returns the largest absolute deviation
+/// of each state variable from it. A run which diverges, or whose time falls out of step with
+/// the reference, deviates infinitely.
+pub fn replay(
+    robot_sim: &mut RobotSimulation,
+    reference: &TrajectoryRecorder,
+    dt: f64,
+) -> [f64; 7] {
+    let mut deviations = [0.0f64; 7];
+    for (k, sample) in reference.samples.iter().enumerate() {
+        if k > 0 {
+            robot_sim.step(dt);
+        }
+        let time_tolerance = 1e-9 * sample.time.abs().max(1.0);
+        if robot_sim.is_diverged() || (robot_sim.get_time() - sample.time).abs() > time_tolerance {
+            return [f64::INFINITY; 7];
+        }
+        for (deviation, (x, x_ref)) in deviations
+            .iter_mut()
+            .zip(robot_sim.get_state().iter().zip(&sample.state))
+        {
+            *deviation = deviation.max((x - x_ref).abs());
+        }
+    }
+    deviations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::closed_path::predefined_closed_path;
+    use crate::ode_solver::ode_system::Vector;
+    use crate::simulation::robot::OPTIMIZED_CONFIG;
+    use std::sync::Arc;
+
+    const DT: f64 = 1.0 / 240.0;
+
+    fn predefined_run() -> RobotSimulation {
+        let x0 = Vector::<7>::from_column_slice(&[0.0, -4.0, 0.1, 0.0, 0.0, 0.0, 0.0]);
+        RobotSimulation::from_config(x0, &OPTIMIZED_CONFIG, Arc::new(predefined_closed_path()))
+    }
+
+    #[test]
+    fn test_replay_of_a_recorded_run() {
+        let reference = TrajectoryRecorder::record_run(&mut predefined_run(), 2400, DT);
+        assert_eq!(reference.samples().len(), 2401);
+        assert_eq!(reference.samples()[0].time, 0.0);
+        assert_eq!(reference.samples()[0].state[1], -4.0);
+
+        // the dynamics are deterministic
+        assert_eq!(replay(&mut predefined_run(), &reference, DT), [0.0; 7]);
+
+        // a different controller goes elsewhere, but starts from the same state
+        let mut other = predefined_run();
+        other.kp *= 2.0;
+        let deviations = replay(&mut other, &reference, DT);
+        assert!(
+            deviations[0] > 1e-3 && deviations[1] > 1e-3,
+            "{:?}",
+            deviations
+        );
+        assert!(deviations.iter().all(|d| d.is_finite()));
+
+        // stepping differently than the reference makes the comparison meaningless
+        let deviations = replay(&mut predefined_run(), &reference, DT / 2.0);
+        assert_eq!(deviations, [f64::INFINITY; 7]);
+    }
+
+    #[test]
+    fn test_recorded_run_json_round_trip() {
+        let reference = TrajectoryRecorder::record_run(&mut predefined_run(), 2400, DT);
+        let json = reference.to_json().unwrap();
+        let reloaded = TrajectoryRecorder::from_json(&json).unwrap();
+        assert_eq!(reloaded, reference);
+        assert_eq!(replay(&mut predefined_run(), &reloaded, DT), [0.0; 7]);
+
+        let filename = std::env::temp_dir().join("linefollower_recorded_run.json");
+        reference.save(&filename).unwrap();
+        assert_eq!(TrajectoryRecorder::load(&filename).unwrap(), reference);
+        std::fs::remove_file(filename).unwrap();
+    }
+}
@@ -1,18 +1,37 @@
-use std::sync::Arc;
+use std::collections::VecDeque;
+use std::io::{BufWriter, Write};
 
 use nalgebra::{distance_squared, Point2, Vector2};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 
-use crate::geometry::closed_path::ClosedPath;
-use crate::geometry::track::Track;
+use crate::geometry::speed_profile::SpeedProfile;
+use crate::geometry::track::SharedTrack;
 use crate::ode_solver::integrator::Rk4;
 use crate::ode_solver::ode_system::Vector;
+use crate::utils::math::wrap_angle;
+
+use super::events::{SimEvent, SimEventKind};
+use super::logging::{StepRecord, StreamLogger};
+use super::perception::line_pose_from_signals;
 /// The number of state variables
 const NUM_STATES: usize = 7;
 /// The number of control variables
 const NUM_CONTROLS: usize = 2;
-/// Robot geometry
+/// Default robot geometry
 const ROBOT_WHEEL_RADIUS: f64 = 0.04;
 const ROBOT_SIDE_LENGTH: f64 = 0.1;
+/// Sensor array geometry
+/// The sensors are evenly spread over a bar in front of the robot
+pub const NUM_SENSORS: usize = 5;
+const SENSOR_ARRAY_LENGTH: f64 = ROBOT_SIDE_LENGTH * 1.1;
+/// Default maximum distance at which a sensor still registers the line
+pub const MAX_SENSOR_DISTANCE: f64 = 0.05;
+/// Resolution of the simulated wheel encoders
+pub const ENCODER_COUNTS_PER_REVOLUTION: f64 = 360.0;
+/// How far back the lateral error is remembered, which bounds the window of `is_converged`
+pub const ERROR_HISTORY_DURATION: f64 = 10.0;
 
 // Dynamical constants
 // DC Motor constants
@@ -22,34 +41,379 @@ const ROBOT_SIDE_LENGTH: f64 = 0.1;
 // const L: f64 = 0.1;
 // const K: f64 = 0.1;
 // We'll just model using the usual 2nd order system constants for now
+// Default natural frequency and damping ratio of the motors
 const W0: f64 = 20.0;
 const XI: f64 = 0.71;
-const C0: f64 = 1.0 / (W0 * W0);
-const C1: f64 = 2.0 * XI / W0;
 const C2: f64 = 1.0;
 
 //const DESIRED_SPEED: f64 = 7.5;
 
+/// Physical constants of the robot, to simulate a chassis other than the default one.
+/// Each motor responds to its command `u` as `c0 w'' + c1 w' + w = u`, i.e. as a second order
+/// system of natural frequency `w0` and damping ratio `xi`, with unit static gain.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RobotParams {
+    pub wheel_radius: f64,
+    /// Distance between the wheels
+    pub side_length: f64,
+    /// In rad/s
+    pub w0: f64,
+    pub xi: f64,
+}
+
+impl Default for RobotParams {
+    fn default() -> Self {
+        Self {
+            wheel_radius: ROBOT_WHEEL_RADIUS,
+            side_length: ROBOT_SIDE_LENGTH,
+            w0: W0,
+            xi: XI,
+        }
+    }
+}
+
+impl RobotParams {
+    fn c0(&self) -> f64 {
+        1.0 / (self.w0 * self.w0)
+    }
+
+    fn c1(&self) -> f64 {
+        2.0 * self.xi / self.w0
+    }
+
+    /// Forward speed and yaw rate of the body when the wheels turn at `wl` and `wr`,
+    /// assuming they don't slip
+    fn body_velocity(&self, wl: f64, wr: f64) -> (f64, f64) {
+        (
+            self.wheel_radius * (wl + wr) / 2.0,
+            self.wheel_radius * (wr - wl) / self.side_length,
+        )
+    }
+}
+
+/// Controller gains and reference speed used for a run
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ControllerConfig {
+    pub kp: f64,
+    pub ki: f64,
+    pub kd: f64,
+    pub speed: f64,
+}
+
+/// Gains and speed the optimizer found for the predefined track
+pub const OPTIMIZED_CONFIG: ControllerConfig = ControllerConfig {
+    kp: 3.130480505558367,
+    ki: 73.01770822094774,
+    kd: 11.273635752474997,
+    speed: 1.6710281486754923,
+};
+
+/// Where the controller gets the lateral error from
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ReferenceMode {
+    /// Signed distance from the robot to the path, which requires knowing the whole track
+    Omniscient,
+    /// Position of the line estimated from the sensor readings only, like a real line follower.
+    /// While no sensor detects the line, the last estimate is kept.
+    SensorOnly,
+    /// Convex blend of the two, `alpha` times the signed distance plus `1 - alpha` times the
+    /// sensor estimate, with `alpha` in [0, 1]. Sweeping `alpha` from 1 down to 0 goes from the
+    /// omniscient error to the sensor-only one
+    Blended { alpha: f64 },
+}
+
+/// What the PID controller takes as its error, positive when the robot should turn left.
+/// Headings follow the same sign: the heading error is the path's tangent angle minus the
+/// robot's heading, as returned by `RobotSimulation::heading_error`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ErrorMode {
+    /// Lateral offset from the line, measured as the `ReferenceMode` says. It is the same
+    /// whichever way the robot faces, so it can't tell when the robot runs backwards
+    SignedDistance,
+    /// Angle from the robot's heading to the path's tangent at its projection, wrapped into
+    /// [-pi, pi], which ignores how far the robot is from the line
+    Heading,
+    /// Signed distance to the path plus `heading_weight` (in meters per radian) times the
+    /// heading error
+    Combined { heading_weight: f64 },
+}
+
+/// How the controller turns the error into a turn rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ControlStrategy {
+    /// PID on the error given by the `ErrorMode` and `ReferenceMode`, which mixes up how far the
+    /// robot is from the line with which way it is heading
+    Pid,
+    /// Stanley steering law, which corrects the heading error and the cross-track error
+    /// separately: it steers by `atan2(stanley_gain * e, v) + heading_error`.
+    /// It needs the path's heading, so it always measures from the whole track
+    Stanley,
+    /// Pure pursuit: turns along the arc which reaches the point of the path `lookahead` ahead
+    /// of the robot's projection. A longer lookahead cuts the corners more but weaves less.
+    /// Like Stanley, it needs the whole track
+    PurePursuit,
+}
+
+/// Default gain of the Stanley controller on the cross-track error, in 1/s
+pub const DEFAULT_STANLEY_GAIN: f64 = 2.5;
+/// Default lookahead distance of the pure pursuit controller, in meters
+pub const DEFAULT_LOOKAHEAD: f64 = 0.3;
+
+/// Turn rate of the `SearchSpin` lost-line policy, in rad/s
+pub const SEARCH_SPIN_RATE: f64 = 3.0;
+
+/// What the controller does while none of the sensors detect the line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LostLinePolicy {
+    /// Keep sending the last command computed while the line was visible
+    HoldLastCommand,
+    /// Spin in place towards the side where the line was last seen, at `SEARCH_SPIN_RATE`
+    SearchSpin,
+    /// Stop both motors
+    Stop,
+}
+
+/// Bang-bang controller which always turns towards the line at `amplitude` rad/s.
+/// It switches on `e + lead * de/dt` rather than on the error `e` alone: the lateral error
+/// is a double integral of the turn rate, so without some lead the oscillation keeps growing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Relay {
+    pub amplitude: f64,
+    /// In seconds
+    pub lead: f64,
+}
+
+/// Loss of traction when cornering hard. While the lateral acceleration demanded by the wheels
+/// exceeds `traction_limit`, the body only turns at the rate the tires can hold, so the robot
+/// runs wide of the arc the wheels describe, and the sliding scrubs off some ground speed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SlipModel {
+    /// Largest lateral acceleration the tires can sustain, in m/s^2
+    pub traction_limit: f64,
+}
+
+/// Summary of a completed lap, to tell whether the controller gets better or worse over a run
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LapStats {
+    /// Starting from 1
+    pub lap: usize,
+    /// In seconds
+    pub lap_time: f64,
+    /// Root mean square of the distance to the path during the lap
+    pub rms_error: f64,
+    /// Largest absolute distance to the path during the lap
+    pub max_abs_error: f64,
+}
+
+/// Errors accumulated since the start of the current lap
+#[derive(Debug, Clone, Copy, Default)]
+struct LapAccumulator {
+    start_time: f64,
+    squared_error: f64,
+    max_abs_error: f64,
+    steps: usize,
+}
+
+/// Everything in a `RobotSimulation` that changes while stepping, so that a run can be
+/// rewound to the moment the snapshot was taken. The gains, the path and the other
+/// settings are not part of it.
+#[derive(Debug, Clone)]
+pub struct SimSnapshot {
+    state: Vector<NUM_STATES>,
+    integrator_time: f64,
+    controls: Vector<NUM_CONTROLS>,
+    commanded_controls: Vector<NUM_CONTROLS>,
+    actuator_queue: VecDeque<Vector<NUM_CONTROLS>>,
+    prev_error: f64,
+    int_error: f64,
+    proportional_term: f64,
+    integral_term: f64,
+    derivative_term: f64,
+    time: f64,
+    arc_length: f64,
+    progress: f64,
+    completed_laps: usize,
+    distance_traveled: f64,
+    iae: f64,
+    line_detected: bool,
+    saturated: bool,
+    diverged: bool,
+    events: Vec<SimEvent>,
+    error_history: VecDeque<(f64, f64)>,
+    wheel_rotations: (f64, f64),
+    noise_rng: StdRng,
+    sensor_noise: [f64; NUM_SENSORS],
+    lap_stats: Vec<LapStats>,
+    current_lap: LapAccumulator,
+}
+
+impl SimSnapshot {
+    pub fn get_state(&self) -> Vector<NUM_STATES> {
+        self.state
+    }
+
+    pub fn get_time(&self) -> f64 {
+        self.time
+    }
+}
+
+/// Right-hand side of the robot's equations of motion, with the slip settings baked in
+type Dynamics =
+    Box<dyn Fn(f64, &Vector<NUM_STATES>, &Vector<NUM_CONTROLS>) -> Vector<NUM_STATES> + Send>;
+
 pub struct RobotSimulation {
-    integrator: Rk4<
-        fn(f64, &Vector<NUM_STATES>, &Vector<NUM_CONTROLS>) -> Vector<NUM_STATES>,
-        NUM_STATES,
-        NUM_CONTROLS,
-    >,
+    integrator: Rk4<Dynamics, NUM_STATES, NUM_CONTROLS>,
     initial_state: Vector<NUM_STATES>,
     state: Vector<NUM_STATES>,
+    /// Controls applied to the dynamics, i.e. after the actuator delay
     controls: Vector<NUM_CONTROLS>,
-    path: Arc<ClosedPath<f64>>,
+    /// Controls computed by the controller on the last step
+    commanded_controls: Vector<NUM_CONTROLS>,
+    /// Commands waiting to reach the motors, oldest first
+    actuator_queue: VecDeque<Vector<NUM_CONTROLS>>,
+    actuator_delay_steps: usize,
+    /// Steps ahead the controller predicts the error, to compensate for the actuator delay
+    prediction_steps: usize,
+    /// Largest magnitude of each motor command, like the voltage ceiling of a real motor
+    max_control: f64,
+    /// Largest change of each motor command from one step to the next
+    max_command_slew: Option<f64>,
+    /// The line the sensors see, from which the error is measured
+    path: SharedTrack,
+    /// Where the reference point moves along, by default the same path as the sensed one
+    reference_path: SharedTrack,
+    /// Arc-length along `reference_path` of the reference point at the start
+    reference_offset: f64,
+    /// When set, the reference point moves along this profile rather than at `speed`
+    reference_profile: Option<SpeedProfile<f64>>,
     prev_error: f64,
     int_error: f64,
     pub kp: f64,
     pub ki: f64,
     pub kd: f64,
     pub speed: f64,
+    /// Gain of the PID's feed-forward on the path's curvature at the robot's projection, which
+    /// adds `kff * curvature * speed` to the turn rate so that the robot turns into the arcs
+    /// before the error builds up. 1 turns at the path's rate, 0 (the default) disables it
+    pub kff: f64,
     proportional_term: f64,
     integral_term: f64,
     derivative_term: f64,
+    /// Largest magnitude of the integral term, beyond which the error stops accumulating
+    integral_limit: f64,
+    max_sensor_distance: f64,
+    /// Number of discrete values the sensor position estimate can take, if it is quantized
+    position_levels: Option<usize>,
+    reference_mode: ReferenceMode,
+    error_mode: ErrorMode,
+    control_strategy: ControlStrategy,
+    /// Gain of the Stanley controller on the cross-track error, in 1/s
+    pub stanley_gain: f64,
+    /// Distance along the path to the goal point of the pure pursuit controller, in meters
+    pub lookahead: f64,
+    /// When set, the PID is replaced by this relay
+    relay: Option<Relay>,
+    /// When set, overrides the controller while the line is lost
+    lost_line_policy: Option<LostLinePolicy>,
+    params: RobotParams,
+    /// When unset, the robot moves exactly as the wheel kinematics predict
+    slip_model: Option<SlipModel>,
     time: f64,
+    /// Arc-length position of the robot's projection on the path
+    arc_length: f64,
+    /// Unwrapped arc-length traveled along the path since the start
+    progress: f64,
+    completed_laps: usize,
+    lap_stats: Vec<LapStats>,
+    current_lap: LapAccumulator,
+    /// Length of the path actually followed by the robot since the start
+    distance_traveled: f64,
+    iae: f64,
+    line_detected: bool,
+    /// Whether the last motor commands were clamped to `max_control`
+    saturated: bool,
+    diverged: bool,
+    events: Vec<SimEvent>,
+    /// Time and signed distance to the path after each step, over the last `ERROR_HISTORY_DURATION`
+    error_history: VecDeque<(f64, f64)>,
+    /// Angle turned by the left and right wheels since the start, in radians
+    wheel_rotations: (f64, f64),
+    /// Standard deviation of the noise added to the sensor distances, disabled when 0
+    sensor_noise_std_dev: f64,
+    sensor_noise_seed: u64,
+    noise_rng: StdRng,
+    /// Noise added to each sensor distance until the next step
+    sensor_noise: [f64; NUM_SENSORS],
+    stream_logger: Option<StreamLogger>,
+}
+
+/// A copy in the same state, which then runs independently of the original,
+/// sharing only the (immutable) paths. The copy doesn't log to the original's stream
+impl Clone for RobotSimulation {
+    fn clone(&self) -> Self {
+        Self {
+            integrator: Self::make_integrator(
+                self.params,
+                self.slip_model,
+                self.integrator.get_time(),
+                self.integrator.get_state(),
+            ),
+            initial_state: self.initial_state,
+            state: self.state,
+            controls: self.controls,
+            commanded_controls: self.commanded_controls,
+            actuator_queue: self.actuator_queue.clone(),
+            actuator_delay_steps: self.actuator_delay_steps,
+            prediction_steps: self.prediction_steps,
+            max_control: self.max_control,
+            max_command_slew: self.max_command_slew,
+            path: self.path.clone(),
+            reference_path: self.reference_path.clone(),
+            reference_offset: self.reference_offset,
+            reference_profile: self.reference_profile.clone(),
+            prev_error: self.prev_error,
+            int_error: self.int_error,
+            kp: self.kp,
+            ki: self.ki,
+            kd: self.kd,
+            speed: self.speed,
+            kff: self.kff,
+            proportional_term: self.proportional_term,
+            integral_term: self.integral_term,
+            derivative_term: self.derivative_term,
+            integral_limit: self.integral_limit,
+            max_sensor_distance: self.max_sensor_distance,
+            position_levels: self.position_levels,
+            reference_mode: self.reference_mode,
+            error_mode: self.error_mode,
+            control_strategy: self.control_strategy,
+            stanley_gain: self.stanley_gain,
+            lookahead: self.lookahead,
+            relay: self.relay,
+            lost_line_policy: self.lost_line_policy,
+            params: self.params,
+            slip_model: self.slip_model,
+            time: self.time,
+            arc_length: self.arc_length,
+            progress: self.progress,
+            completed_laps: self.completed_laps,
+            lap_stats: self.lap_stats.clone(),
+            current_lap: self.current_lap,
+            distance_traveled: self.distance_traveled,
+            iae: self.iae,
+            line_detected: self.line_detected,
+            saturated: self.saturated,
+            diverged: self.diverged,
+            events: self.events.clone(),
+            error_history: self.error_history.clone(),
+            wheel_rotations: self.wheel_rotations,
+            sensor_noise_std_dev: self.sensor_noise_std_dev,
+            sensor_noise_seed: self.sensor_noise_seed,
+            noise_rng: self.noise_rng.clone(),
+            sensor_noise: self.sensor_noise,
+            stream_logger: None,
+        }
+    }
 }
 
 impl RobotSimulation {
@@ -59,22 +423,25 @@ impl RobotSimulation {
         ki: f64,
         kd: f64,
         speed: f64,
-        path: Arc<ClosedPath<f64>>,
+        path: SharedTrack,
     ) -> Self {
         let x = x0;
         let u = Vector::<NUM_CONTROLS>::zeros();
-        let integrator = Rk4::new(
-            Self::robot_dynamics
-                as fn(f64, &Vector<NUM_STATES>, &Vector<NUM_CONTROLS>) -> Vector<NUM_STATES>,
-            0.0,
-            x,
-        );
+        let integrator = Self::make_integrator(RobotParams::default(), None, 0.0, x);
 
-        Self {
+        let arc_length = path.point_projection_distance(Point2::new(x[0], x[1]));
+        let mut sim = Self {
             integrator,
             initial_state: x0,
             state: x,
             controls: u,
+            commanded_controls: u,
+            actuator_queue: VecDeque::new(),
+            actuator_delay_steps: 0,
+            prediction_steps: 0,
+            reference_path: path.clone(),
+            reference_offset: 0.0,
+            reference_profile: None,
             path,
             prev_error: 0.0,
             int_error: 0.0,
@@ -83,28 +450,316 @@ impl RobotSimulation {
             ki,
             kd,
             speed,
+            kff: 0.0,
             proportional_term: 0.0,
             integral_term: 0.0,
             derivative_term: 0.0,
+            integral_limit: f64::INFINITY,
+            max_control: f64::INFINITY,
+            max_command_slew: None,
+            max_sensor_distance: MAX_SENSOR_DISTANCE,
+            position_levels: None,
+            reference_mode: ReferenceMode::Omniscient,
+            error_mode: ErrorMode::SignedDistance,
+            control_strategy: ControlStrategy::Pid,
+            stanley_gain: DEFAULT_STANLEY_GAIN,
+            lookahead: DEFAULT_LOOKAHEAD,
+            relay: None,
+            lost_line_policy: None,
+            params: RobotParams::default(),
+            slip_model: None,
+            arc_length,
+            progress: 0.0,
+            completed_laps: 0,
+            lap_stats: Vec::new(),
+            current_lap: LapAccumulator::default(),
+            distance_traveled: 0.0,
+            iae: 0.0,
+            line_detected: false,
+            saturated: false,
+            diverged: false,
+            events: Vec::new(),
+            error_history: VecDeque::new(),
+            wheel_rotations: (0.0, 0.0),
+            sensor_noise_std_dev: 0.0,
+            sensor_noise_seed: 0,
+            noise_rng: StdRng::seed_from_u64(0),
+            sensor_noise: [0.0; NUM_SENSORS],
+            stream_logger: None,
+        };
+        sim.line_detected = sim.is_line_detected();
+        sim
+    }
+
+    pub fn from_config(
+        x0: Vector<NUM_STATES>,
+        config: &ControllerConfig,
+        path: SharedTrack,
+    ) -> Self {
+        Self::new(x0, config.kp, config.ki, config.kd, config.speed, path)
+    }
+
+    /// Checks the initial heading against the direction of the path at the robot's position.
+    /// If they differ by more than `threshold` radians, an `InitialHeadingMismatch` event is
+    /// recorded and, if `auto_align` is set, the robot starts facing along the path instead.
+    pub fn with_heading_check(mut self, threshold: f64, auto_align: bool) -> Self {
+        let error = self.heading_error();
+        if error.abs() > threshold {
+            if auto_align {
+                self.initial_state[2] += error;
+                self.reset();
+            }
+            self.push_event(SimEventKind::InitialHeadingMismatch);
         }
+        self
+    }
+
+    /// Starts the reference point at the projection of the robot's initial position on the
+    /// reference path, rather than at the start of that path, so that the reference is in phase
+    /// with the robot wherever it starts
+    pub fn with_synced_reference(mut self) -> Self {
+        let start = Point2::new(self.initial_state[0], self.initial_state[1]);
+        self.reference_offset = self.reference_path.point_projection_distance(start);
+        self
     }
 
+    /// Simulates a robot with other physical constants than the default ones
+    pub fn with_params(mut self, params: RobotParams) -> Self {
+        self.params = params;
+        self.integrator = Self::make_integrator(
+            params,
+            self.slip_model,
+            self.integrator.get_time(),
+            self.state,
+        );
+        self
+    }
+
+    pub fn get_params(&self) -> RobotParams {
+        self.params
+    }
+
+    /// Adds zero-mean gaussian noise with a standard deviation of `std_dev` to the distance
+    /// measured by each sensor, drawn anew on every step from a generator seeded with `seed`,
+    /// so that noisy runs are reproducible. The noise only reaches the controller in
+    /// `ReferenceMode::SensorOnly` and `ReferenceMode::Blended`, and through the detection of
+    /// the line.
+    pub fn with_sensor_noise(mut self, std_dev: f64, seed: u64) -> Self {
+        self.sensor_noise_std_dev = std_dev;
+        self.sensor_noise_seed = seed;
+        self.noise_rng = StdRng::seed_from_u64(seed);
+        self.resample_sensor_noise();
+        self.line_detected = self.is_line_detected();
+        self
+    }
+
+    fn resample_sensor_noise(&mut self) {
+        if self.sensor_noise_std_dev <= 0.0 {
+            return;
+        }
+        for noise in self.sensor_noise.iter_mut() {
+            // Box-Muller transform, with u1 in (0, 1] so that its logarithm is finite
+            let u1 = 1.0 - self.noise_rng.gen::<f64>();
+            let u2 = self.noise_rng.gen::<f64>();
+            let z = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+            *noise = self.sensor_noise_std_dev * z;
+        }
+    }
+
+    /// Restarts the run from the initial state. The gains and the speed keep their current
+    /// values, since they are usually being tuned live, as do the other settings.
     pub fn reset(&mut self) {
         self.state = self.initial_state;
         self.controls = Vector::<NUM_CONTROLS>::zeros();
-        self.integrator = Rk4::new(
-            Self::robot_dynamics
-                as fn(f64, &Vector<NUM_STATES>, &Vector<NUM_CONTROLS>) -> Vector<NUM_STATES>,
-            0.0,
-            self.state,
-        );
+        self.commanded_controls = Vector::<NUM_CONTROLS>::zeros();
+        self.fill_actuator_queue();
+        self.integrator = Self::make_integrator(self.params, self.slip_model, 0.0, self.state);
         self.time = 0.0;
         self.prev_error = 0.0;
         self.int_error = 0.0;
+        self.proportional_term = 0.0;
+        self.integral_term = 0.0;
+        self.derivative_term = 0.0;
+        self.arc_length = self.path.point_projection_distance(self.robot_position());
+        self.progress = 0.0;
+        self.completed_laps = 0;
+        self.lap_stats.clear();
+        self.current_lap = LapAccumulator::default();
+        self.distance_traveled = 0.0;
+        self.iae = 0.0;
+        self.saturated = false;
+        self.diverged = false;
+        self.events.clear();
+        self.error_history.clear();
+        self.wheel_rotations = (0.0, 0.0);
+        // the noise starts over too, so that the run is the same as the first time
+        self.noise_rng = StdRng::seed_from_u64(self.sensor_noise_seed);
+        self.sensor_noise = [0.0; NUM_SENSORS];
+        self.resample_sensor_noise();
+        self.line_detected = self.is_line_detected();
+    }
+
+    pub fn snapshot(&self) -> SimSnapshot {
+        SimSnapshot {
+            state: self.state,
+            integrator_time: self.integrator.get_time(),
+            controls: self.controls,
+            commanded_controls: self.commanded_controls,
+            actuator_queue: self.actuator_queue.clone(),
+            prev_error: self.prev_error,
+            int_error: self.int_error,
+            proportional_term: self.proportional_term,
+            integral_term: self.integral_term,
+            derivative_term: self.derivative_term,
+            time: self.time,
+            arc_length: self.arc_length,
+            progress: self.progress,
+            completed_laps: self.completed_laps,
+            lap_stats: self.lap_stats.clone(),
+            current_lap: self.current_lap,
+            distance_traveled: self.distance_traveled,
+            iae: self.iae,
+            line_detected: self.line_detected,
+            saturated: self.saturated,
+            diverged: self.diverged,
+            events: self.events.clone(),
+            error_history: self.error_history.clone(),
+            wheel_rotations: self.wheel_rotations,
+            noise_rng: self.noise_rng.clone(),
+            sensor_noise: self.sensor_noise,
+        }
+    }
+
+    /// Rewinds the simulation to the moment `snapshot` was taken, after which
+    /// stepping reproduces exactly the same run, as long as the settings are the same
+    pub fn restore(&mut self, snapshot: &SimSnapshot) {
+        self.state = snapshot.state;
+        self.integrator = Self::make_integrator(
+            self.params,
+            self.slip_model,
+            snapshot.integrator_time,
+            snapshot.state,
+        );
+        self.controls = snapshot.controls;
+        self.commanded_controls = snapshot.commanded_controls;
+        self.actuator_queue = snapshot.actuator_queue.clone();
+        self.prev_error = snapshot.prev_error;
+        self.int_error = snapshot.int_error;
+        self.proportional_term = snapshot.proportional_term;
+        self.integral_term = snapshot.integral_term;
+        self.derivative_term = snapshot.derivative_term;
+        self.time = snapshot.time;
+        self.arc_length = snapshot.arc_length;
+        self.progress = snapshot.progress;
+        self.completed_laps = snapshot.completed_laps;
+        self.lap_stats = snapshot.lap_stats.clone();
+        self.current_lap = snapshot.current_lap;
+        self.distance_traveled = snapshot.distance_traveled;
+        self.iae = snapshot.iae;
+        self.line_detected = snapshot.line_detected;
+        self.saturated = snapshot.saturated;
+        self.diverged = snapshot.diverged;
+        self.events = snapshot.events.clone();
+        self.error_history = snapshot.error_history.clone();
+        self.wheel_rotations = snapshot.wheel_rotations;
+        self.noise_rng = snapshot.noise_rng.clone();
+        self.sensor_noise = snapshot.sensor_noise;
     }
 
     pub fn theta_error_estimate(&self) -> f64 {
-        self.robot_sdf_to_path()
+        match (self.error_mode, self.reference_mode) {
+            (ErrorMode::SignedDistance, ReferenceMode::SensorOnly) => {
+                self.sensor_line_offset().unwrap_or(self.prev_error)
+            }
+            (ErrorMode::SignedDistance, ReferenceMode::Blended { alpha }) => {
+                self.blended_error(alpha)
+            }
+            _ => self.track_error(&self.state),
+        }
+    }
+
+    /// `alpha` times the signed distance to the path plus `1 - alpha` times the line offset
+    /// estimated by the sensors, which is the last error while they don't detect the line
+    pub fn blended_error(&self, alpha: f64) -> f64 {
+        let sensor_error = self.sensor_line_offset().unwrap_or(self.prev_error);
+        alpha * self.robot_sdf_to_path() + (1.0 - alpha) * sensor_error
+    }
+
+    /// Error of the `ErrorMode` for a robot in the state `x`, measured from the whole track
+    fn track_error(&self, x: &Vector<NUM_STATES>) -> f64 {
+        let position = Point2::new(x[0], x[1]);
+        let heading_error = || self.heading_error_at(position, x[2]);
+        match self.error_mode {
+            ErrorMode::SignedDistance => self.path.sdf(position),
+            ErrorMode::Heading => heading_error(),
+            ErrorMode::Combined { heading_weight } => {
+                self.path.sdf(position) + heading_weight * heading_error()
+            }
+        }
+    }
+
+    pub fn set_reference_mode(&mut self, reference_mode: ReferenceMode) {
+        if let ReferenceMode::Blended { alpha } = reference_mode {
+            assert!(
+                (0.0..=1.0).contains(&alpha),
+                "the blend factor must be in [0, 1], not {}",
+                alpha
+            );
+        }
+        self.reference_mode = reference_mode;
+    }
+
+    pub fn get_reference_mode(&self) -> ReferenceMode {
+        self.reference_mode
+    }
+
+    /// The heading needs the path's tangent, so only `ErrorMode::SignedDistance` can be
+    /// measured by the sensors; the other modes always measure from the whole track
+    pub fn set_error_mode(&mut self, error_mode: ErrorMode) {
+        self.error_mode = error_mode;
+    }
+
+    pub fn get_error_mode(&self) -> ErrorMode {
+        self.error_mode
+    }
+
+    pub fn set_control_strategy(&mut self, control_strategy: ControlStrategy) {
+        self.control_strategy = control_strategy;
+    }
+
+    pub fn get_control_strategy(&self) -> ControlStrategy {
+        self.control_strategy
+    }
+
+    /// Replaces the PID by a relay (bang-bang) controller, or restores the PID with `None`.
+    /// Used for relay auto-tuning.
+    pub fn set_relay(&mut self, relay: Option<Relay>) {
+        self.relay = relay;
+    }
+
+    /// Sets what to do while no sensor detects the line. With `None`, the controller keeps
+    /// running on its error estimate, which for `ReferenceMode::SensorOnly` is the last one seen.
+    pub fn set_lost_line_policy(&mut self, policy: Option<LostLinePolicy>) {
+        self.lost_line_policy = policy;
+    }
+
+    pub fn get_lost_line_policy(&self) -> Option<LostLinePolicy> {
+        self.lost_line_policy
+    }
+
+    /// Takes effect from the next step on, without changing the current state
+    pub fn set_slip_model(&mut self, slip_model: Option<SlipModel>) {
+        self.slip_model = slip_model;
+        self.integrator = Self::make_integrator(
+            self.params,
+            slip_model,
+            self.integrator.get_time(),
+            self.state,
+        );
+    }
+
+    pub fn get_slip_model(&self) -> Option<SlipModel> {
+        self.slip_model
     }
 
     pub fn get_state(&self) -> Vector<NUM_STATES> {
@@ -115,6 +770,53 @@ impl RobotSimulation {
         self.time
     }
 
+    /// Turn rate of the robot's body, in rad/s, which results from the difference
+    /// between the wheels' angular velocities
+    pub fn yaw_rate(&self) -> f64 {
+        self.params.body_velocity(self.state[3], self.state[5]).1
+    }
+
+    /// Speed of the robot's body along its heading, in m/s, which results from the average
+    /// of the wheels' angular velocities
+    pub fn forward_speed(&self) -> f64 {
+        self.params.body_velocity(self.state[3], self.state[5]).0
+    }
+
+    /// Centripetal acceleration demanded by the wheel speeds, in m/s^2,
+    /// i.e. the one the robot would have without slip
+    pub fn lateral_acceleration(&self) -> f64 {
+        self.forward_speed() * self.yaw_rate()
+    }
+
+    /// Velocity of the robot's body in m/s, as given by the dynamics
+    pub fn robot_velocity(&self) -> Vector2<f64> {
+        let dx = Self::robot_dynamics(&self.state, &self.controls, &self.params, self.slip_model);
+        Vector2::new(dx[0], dx[1])
+    }
+
+    /// Angle from the robot's heading to its velocity, in [-pi/2, pi/2], positive when the
+    /// robot moves to the left of where it faces, and 0 at a standstill. The current dynamics
+    /// always move the robot along its heading, even while it slips, so it stays 0 until
+    /// they model sideways motion
+    pub fn slip_angle(&self) -> f64 {
+        let v = self.robot_velocity();
+        let theta = self.state[2];
+        let longitudinal = v.x * theta.cos() + v.y * theta.sin();
+        let lateral = v.y * theta.cos() - v.x * theta.sin();
+        if longitudinal == 0.0 && lateral == 0.0 {
+            return 0.0;
+        }
+        (lateral / longitudinal).atan()
+    }
+
+    /// Moves the robot by `offset` without changing the rest of its state,
+    /// e.g. to model an external push
+    pub fn displace(&mut self, offset: Vector2<f64>) {
+        self.state[0] += offset.x;
+        self.state[1] += offset.y;
+        self.integrator.set_state(self.state);
+    }
+
     pub fn robot_position(&self) -> Point2<f64> {
         Point2::<f64>::new(self.state[0], self.state[1])
     }
@@ -123,6 +825,239 @@ impl RobotSimulation {
         self.path.sdf(self.robot_position())
     }
 
+    /// Limits the magnitude of both motor commands to `max_control`. Unlimited by default
+    pub fn set_max_control(&mut self, max_control: f64) {
+        assert!(max_control >= 0.0, "the control limit can't be negative");
+        self.max_control = max_control;
+    }
+
+    pub fn get_max_control(&self) -> f64 {
+        self.max_control
+    }
+
+    /// Clamps the motor commands to `max_control`. The difference between them, which sets the
+    /// turn rate, is kept when possible, giving up on the common part, which sets the speed.
+    /// Staying on the line matters more than going fast.
+    fn saturate(&self, u: Vector<NUM_CONTROLS>) -> Vector<NUM_CONTROLS> {
+        let limit = self.max_control;
+        if u[0].abs() <= limit && u[1].abs() <= limit {
+            return u;
+        }
+        let v = (u[1] - u[0]).clamp(-2.0 * limit, 2.0 * limit);
+        let max_um = 2.0 * limit - v.abs();
+        let um = (u[0] + u[1]).clamp(-max_um, max_um);
+        Vector2::<f64>::new((um - v) / 2.0, (um + v) / 2.0)
+    }
+
+    /// Limits how much each motor command can change on a single step, which smooths out the
+    /// jerks a sudden change of the error would cause. Applied after `max_control`, and
+    /// disabled with `None`, the default
+    pub fn set_max_command_slew(&mut self, max_slew: Option<f64>) {
+        if let Some(max_slew) = max_slew {
+            assert!(max_slew >= 0.0, "the slew limit can't be negative");
+        }
+        self.max_command_slew = max_slew;
+    }
+
+    pub fn get_max_command_slew(&self) -> Option<f64> {
+        self.max_command_slew
+    }
+
+    /// Moves each motor command from the one of the last step towards `u` by at most
+    /// `max_command_slew`
+    fn limit_slew(&self, u: Vector<NUM_CONTROLS>) -> Vector<NUM_CONTROLS> {
+        match self.max_command_slew {
+            Some(max_slew) => {
+                let previous = self.commanded_controls;
+                previous + (u - previous).map(|du| du.clamp(-max_slew, max_slew))
+            }
+            None => u,
+        }
+    }
+
+    /// Anti-windup: the accumulated error is clamped so that the integral term stays within
+    /// `[-limit, limit]`, which avoids a large overshoot after a long stretch far from the line.
+    /// Unlimited by default
+    pub fn set_integral_limit(&mut self, limit: f64) {
+        assert!(limit >= 0.0, "the integral limit can't be negative");
+        self.integral_limit = limit;
+    }
+
+    pub fn get_integral_limit(&self) -> f64 {
+        self.integral_limit
+    }
+
+    /// Distance from the line, in meters, beyond which a sensor stops registering it. The analog
+    /// signals fade from 1 above the line to 0 at that distance, so it must be positive
+    pub fn set_max_sensor_distance(&mut self, max_sensor_distance: f64) {
+        assert!(
+            max_sensor_distance.is_finite() && max_sensor_distance > 0.0,
+            "the maximum sensor distance must be positive and finite, not {}",
+            max_sensor_distance
+        );
+        self.max_sensor_distance = max_sensor_distance;
+    }
+
+    pub fn get_max_sensor_distance(&self) -> f64 {
+        self.max_sensor_distance
+    }
+
+    /// Quantizes the sensor position estimate to `levels` evenly spaced values spanning the
+    /// sensor array, modeling the limited resolution of the ADC and of the estimation algorithm.
+    /// `None` leaves the estimate continuous.
+    pub fn set_position_levels(&mut self, levels: Option<usize>) {
+        self.position_levels = levels;
+    }
+
+    pub fn get_position_levels(&self) -> Option<usize> {
+        self.position_levels
+    }
+
+    /// Number of steps between computing a command and applying it to the motors.
+    /// The motors receive zero until the first command gets through.
+    pub fn set_actuator_delay_steps(&mut self, steps: usize) {
+        self.actuator_delay_steps = steps;
+        self.fill_actuator_queue();
+    }
+
+    pub fn get_actuator_delay_steps(&self) -> usize {
+        self.actuator_delay_steps
+    }
+
+    /// Smith predictor: the controller acts on the error predicted `steps` steps ahead rather
+    /// than on the current one, by running an internal model of the robot through the commands
+    /// which are still on their way to the motors. Its prediction of how much the distance to
+    /// the path will change is added to the measured error, so it also works on the sensor
+    /// estimate. Compensates the actuator delay best when both are the same. Disabled when 0
+    pub fn set_prediction_steps(&mut self, steps: usize) {
+        self.prediction_steps = steps;
+    }
+
+    pub fn get_prediction_steps(&self) -> usize {
+        self.prediction_steps
+    }
+
+    /// Change of the error of the `ErrorMode`, measured from the whole track,
+    /// over the next `prediction_steps` steps of `dt`,
+    /// according to the model. Past the end of the queue, the last command is held
+    fn predicted_error_change(&self, dt: f64) -> f64 {
+        if self.prediction_steps == 0 {
+            return 0.0;
+        }
+        let mut model = Self::make_integrator(self.params, self.slip_model, 0.0, self.state);
+        let mut u = self.controls;
+        for k in 0..self.prediction_steps {
+            if let Some(&queued) = self.actuator_queue.get(k) {
+                u = queued;
+            }
+            model.step(dt, &u);
+        }
+        self.track_error(&model.get_state()) - self.track_error(&self.state)
+    }
+
+    fn fill_actuator_queue(&mut self) {
+        self.actuator_queue.clear();
+        self.actuator_queue
+            .resize(self.actuator_delay_steps, Vector::<NUM_CONTROLS>::zeros());
+    }
+
+    /// Controls applied to the dynamics on the last step
+    pub fn get_controls(&self) -> Vector<NUM_CONTROLS> {
+        self.controls
+    }
+
+    /// Controls computed by the controller on the last step, which may not have been applied yet
+    pub fn get_commanded_controls(&self) -> Vector<NUM_CONTROLS> {
+        self.commanded_controls
+    }
+
+    /// World positions of the sensors, from the rightmost to the leftmost one
+    pub fn sensor_positions(&self) -> [Point2<f64>; NUM_SENSORS] {
+        let theta = self.state[2];
+        let forward = Vector2::new(theta.cos(), theta.sin());
+        let left = Vector2::new(-theta.sin(), theta.cos());
+        let center = self.robot_position() + forward * (SENSOR_ARRAY_LENGTH / 2.0);
+        Self::sensor_offsets().map(|offset| center + left * offset)
+    }
+
+    /// Offset of each sensor from the center of the array, positive to the robot's left
+    fn sensor_offsets() -> [f64; NUM_SENSORS] {
+        let spacing = SENSOR_ARRAY_LENGTH / (NUM_SENSORS - 1) as f64;
+        std::array::from_fn(|i| -SENSOR_ARRAY_LENGTH / 2.0 + i as f64 * spacing)
+    }
+
+    /// Signed distance from each sensor to the line, or `None` when the line
+    /// is farther than the sensor's maximum range (no detection).
+    /// Includes the sensor noise, if any.
+    pub fn sensor_distances(&self) -> [Option<f64>; NUM_SENSORS] {
+        let positions = self.sensor_positions();
+        std::array::from_fn(|i| {
+            let d = self.path.sdf(positions[i]) + self.sensor_noise[i];
+            if d.abs() <= self.max_sensor_distance {
+                Some(d)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Analog reading of each sensor, in the range [0, 1]
+    /// It is 1 right above the line and fades linearly to 0 at the edge of the sensor's range
+    pub fn sensor_signals(&self) -> [f64; NUM_SENSORS] {
+        self.sensor_distances().map(|d| match d {
+            Some(d) => 1.0 - d.abs() / self.max_sensor_distance,
+            None => 0.0,
+        })
+    }
+
+    /// Lateral offset of the line relative to the center of the sensor array, positive to the
+    /// robot's left, estimated as the average of the sensor offsets weighted by their signals.
+    /// It has the same sign as the sdf of the robot, so it can be used as the controller error.
+    /// The estimate is rounded to the nearest level when position levels are set.
+    /// Returns `None` when no sensor detects the line.
+    pub fn sensor_line_offset(&self) -> Option<f64> {
+        let (weighted, total) = Self::sensor_offsets()
+            .iter()
+            .zip(self.sensor_signals())
+            .fold((0.0, 0.0), |(weighted, total), (offset, signal)| {
+                (weighted + signal * offset, total + signal)
+            });
+        if total > 0.0 {
+            Some(self.quantize_position(weighted / total))
+        } else {
+            None
+        }
+    }
+
+    /// Distance from the center of the sensor array to the line, positive to the robot's left,
+    /// and the magnitude of the angle between the line and the robot's heading, estimated from
+    /// the sensor signals only, as `line_pose_from_signals` does.
+    /// Returns `None` while fewer than three sensors detect the line.
+    pub fn sensor_line_pose(&self) -> Option<(f64, f64)> {
+        line_pose_from_signals(
+            &Self::sensor_offsets(),
+            &self.sensor_signals(),
+            self.max_sensor_distance,
+        )
+    }
+
+    fn quantize_position(&self, offset: f64) -> f64 {
+        match self.position_levels {
+            None => offset,
+            Some(0) | Some(1) => 0.0,
+            Some(levels) => {
+                let step = SENSOR_ARRAY_LENGTH / (levels - 1) as f64;
+                let level = ((offset + SENSOR_ARRAY_LENGTH / 2.0) / step).round();
+                -SENSOR_ARRAY_LENGTH / 2.0 + level * step
+            }
+        }
+    }
+
+    /// Whether at least one of the sensors detects the line
+    pub fn is_line_detected(&self) -> bool {
+        self.sensor_distances().iter().any(|d| d.is_some())
+    }
+
     /// Error relative to the trajectory defined by the reference position
     pub fn robot_error(&self) -> f64 {
         distance_squared(&self.reference_point(), &self.robot_position())
@@ -130,9 +1065,8 @@ impl RobotSimulation {
 
     /// Dot product of the robot's velocity with the tangent of reference position
     pub fn robot_velocity_reward(&self) -> f64 {
-        let (wl, wr) = (self.state[3], self.state[5]);
         let theta = self.state[2];
-        let speed = ROBOT_WHEEL_RADIUS * (wl + wr) / 2.0;
+        let speed = self.forward_speed();
         let vx = speed * theta.cos();
         let vy = speed * theta.sin();
         // let (tx, ty) = self.reference_tangent();
@@ -141,65 +1075,468 @@ impl RobotSimulation {
         vx * tx + vy * ty
     }
 
+    /// Makes the reference point move along `reference_path` rather than along the sensed path,
+    /// e.g. a racing line drawn over the physical track. The error is still measured from the
+    /// sensed path. The reference offset is kept, so `with_synced_reference` has to come after
+    pub fn set_reference_path(&mut self, reference_path: SharedTrack) {
+        self.reference_path = reference_path;
+    }
+
+    /// The path the sensors see, to start other simulations on the same one
+    pub fn get_path(&self) -> &SharedTrack {
+        &self.path
+    }
+
+    pub fn get_reference_path(&self) -> &SharedTrack {
+        &self.reference_path
+    }
+
+    /// Arc-length along the reference path at which the reference point starts,
+    /// wrapped into the length of the path
+    pub fn set_reference_offset(&mut self, offset: f64) {
+        self.reference_offset = offset.rem_euclid(self.reference_path.length());
+    }
+
+    pub fn get_reference_offset(&self) -> f64 {
+        self.reference_offset
+    }
+
+    /// Makes the reference point move along `profile` from the reference offset, e.g. a
+    /// minimum-jerk one which starts and changes speed smoothly. `None`, the default, moves it
+    /// at `speed` from the start
+    pub fn set_reference_profile(&mut self, profile: Option<SpeedProfile<f64>>) {
+        self.reference_profile = profile;
+    }
+
+    /// The profile the reference point moves along, `speed` from the start if none was set
+    pub fn get_reference_profile(&self) -> SpeedProfile<f64> {
+        self.reference_profile
+            .clone()
+            .unwrap_or(SpeedProfile::Constant(self.speed))
+    }
+
     pub fn reference_point(&self) -> Point2<f64> {
-        self.path.point_at(self.speed * self.get_time())
+        self.reference_path.point_at(self.reference_arc_length())
     }
 
     pub fn reference_tangent(&self) -> Vector2<f64> {
-        self.path.tangent_at(self.speed * self.get_time())
+        self.reference_path.tangent_at(self.reference_arc_length())
+    }
+
+    /// Arc-length along the reference path of the reference point, which moves along the
+    /// reference profile
+    fn reference_arc_length(&self) -> f64 {
+        let distance = match &self.reference_profile {
+            Some(profile) => profile.distance_at(self.get_time()),
+            None => self.speed * self.get_time(),
+        };
+        self.reference_offset + distance
     }
 
     pub fn robot_projection_tangent(&self) -> Vector2<f64> {
         self.path.point_projection_tangent(self.robot_position())
     }
 
+    /// Angle from the robot's heading to the path's tangent at its projection, wrapped into
+    /// [-pi, pi]: positive when the robot should turn left to face along the path
+    pub fn heading_error(&self) -> f64 {
+        self.heading_error_at(self.robot_position(), self.state[2])
+    }
+
+    fn heading_error_at(&self, position: Point2<f64>, heading: f64) -> f64 {
+        let t = self.path.point_projection_tangent(position);
+        wrap_angle(t.y.atan2(t.x) - heading)
+    }
+
+    pub fn get_progress(&self) -> f64 {
+        self.progress
+    }
+
+    /// Odometry: length of the robot's actual trajectory, as opposed to the progress along the path
+    pub fn distance_traveled(&self) -> f64 {
+        self.distance_traveled
+    }
+
+    /// Integral of the absolute error (IAE) since the start: the sum, over the steps, of the
+    /// distance to the path after each step times its duration, in meter-seconds
+    pub fn get_iae(&self) -> f64 {
+        self.iae
+    }
+
+    /// Angles turned by the left and right wheels since the start, in radians
+    pub fn wheel_rotations(&self) -> (f64, f64) {
+        self.wheel_rotations
+    }
+
+    /// Readings of simulated incremental encoders on the left and right wheels, with
+    /// `ENCODER_COUNTS_PER_REVOLUTION` counts per turn. Odometry built from these drifts
+    /// away from the true pose, like on a real robot.
+    pub fn encoder_counts(&self) -> (i64, i64) {
+        let counts = |rotation: f64| {
+            (rotation / (2.0 * std::f64::consts::PI) * ENCODER_COUNTS_PER_REVOLUTION).floor() as i64
+        };
+        (
+            counts(self.wheel_rotations.0),
+            counts(self.wheel_rotations.1),
+        )
+    }
+
+    pub fn get_completed_laps(&self) -> usize {
+        self.completed_laps
+    }
+
+    /// Whether the state became non-finite at some point
+    /// Once diverged, the simulation no longer advances
+    pub fn is_diverged(&self) -> bool {
+        self.diverged
+    }
+
+    pub fn get_events(&self) -> &[SimEvent] {
+        &self.events
+    }
+
+    fn push_event(&mut self, kind: SimEventKind) {
+        self.events.push(SimEvent {
+            time: self.time,
+            kind,
+        });
+    }
+
     pub fn step(&mut self, dt: f64) {
-        self.controls = self.calculate_control(dt);
+        if self.diverged {
+            return;
+        }
+        let previous_position = self.robot_position();
+        let (wl, wr) = (self.state[3], self.state[5]);
+        let controls = self.calculate_control(dt);
+        let saturated_controls = self.saturate(controls);
+        let saturated = saturated_controls != controls;
+        self.commanded_controls = self.limit_slew(saturated_controls);
+        self.actuator_queue.push_back(self.commanded_controls);
+        self.controls = self.actuator_queue.pop_front().unwrap();
         self.integrator.step(dt, &self.controls);
         self.state = self.integrator.get_state();
         self.time += dt;
+
+        if self.state.iter().any(|x| !x.is_finite()) {
+            // the path queries below can't deal with NaNs
+            self.diverged = true;
+            self.push_event(SimEventKind::NonFiniteState);
+            return;
+        }
+        self.distance_traveled += (self.robot_position() - previous_position).norm();
+        self.iae += self.robot_sdf_to_path().abs() * dt;
+        // trapezoidal rule on the wheels' angular velocities
+        self.wheel_rotations.0 += (wl + self.state[3]) / 2.0 * dt;
+        self.wheel_rotations.1 += (wr + self.state[5]) / 2.0 * dt;
+        self.resample_sensor_noise();
+        self.accumulate_lap_error();
+        self.update_progress();
+        self.update_line_detection();
+        self.update_saturation(saturated);
+        self.update_error_history();
+        if let Some(mut logger) = self.stream_logger.take() {
+            logger.log(&StepRecord::of(self));
+            self.stream_logger = Some(logger);
+        }
+    }
+
+    /// Streams a record of every following step, but the diverged one, to `logger`,
+    /// replacing the logger attached before if any
+    pub fn attach_stream_logger(&mut self, logger: StreamLogger) {
+        self.stream_logger = Some(logger);
+    }
+
+    /// Stops streaming the steps, giving back the logger to `finish` it
+    pub fn detach_stream_logger(&mut self) -> Option<StreamLogger> {
+        self.stream_logger.take()
+    }
+
+    /// Runs `steps` steps of `dt` and writes the state after each of them as a CSV table to
+    /// `writer`, with a header row, for offline analysis. The output is buffered and flushed
+    /// once at the end. Stops early, without writing the non-finite state, if the run diverges.
+    pub fn record_csv<W: Write>(
+        &mut self,
+        writer: W,
+        steps: usize,
+        dt: f64,
+    ) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(writer);
+        writeln!(
+            writer,
+            "time,x,y,theta,wl,wr,sdf_error,p_term,i_term,d_term"
+        )?;
+        for _ in 0..steps {
+            self.step(dt);
+            if self.diverged {
+                break;
+            }
+            let x = &self.state;
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{},{}",
+                self.time,
+                x[0],
+                x[1],
+                x[2],
+                x[3],
+                x[5],
+                self.robot_sdf_to_path(),
+                self.proportional_term,
+                self.integral_term,
+                self.derivative_term
+            )?;
+        }
+        writer.flush()
+    }
+
+    fn update_error_history(&mut self) {
+        self.error_history
+            .push_back((self.time, self.robot_sdf_to_path()));
+        while let Some(&(t, _)) = self.error_history.front() {
+            if t >= self.time - ERROR_HISTORY_DURATION {
+                break;
+            }
+            self.error_history.pop_front();
+        }
+    }
+
+    /// Whether the robot settled into steady tracking: over the last `window` seconds, the
+    /// distance to the path varied by at most `tolerance`. It may settle at a constant offset,
+    /// e.g. on a turn without integral action. Always false for windows longer than the
+    /// simulated time or than `ERROR_HISTORY_DURATION`.
+    pub fn is_converged(&self, window: f64, tolerance: f64) -> bool {
+        match self.error_history.front() {
+            Some(&(t, _)) if t <= self.time - window => {}
+            _ => return false,
+        }
+        let (min, max) = self
+            .error_history
+            .iter()
+            .filter(|(t, _)| *t >= self.time - window)
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &(_, e)| {
+                (min.min(e), max.max(e))
+            });
+        max - min <= tolerance
+    }
+
+    fn update_progress(&mut self) {
+        let length = self.path.length();
+        let arc_length = self.path.point_projection_distance(self.robot_position());
+        // unwrap the arc-length when crossing the start of the path
+        let mut delta = arc_length - self.arc_length;
+        if delta < -length / 2.0 {
+            delta += length;
+        } else if delta > length / 2.0 {
+            delta -= length;
+        }
+        self.arc_length = arc_length;
+        self.progress += delta;
+        if self.progress >= (self.completed_laps + 1) as f64 * length {
+            self.completed_laps += 1;
+            self.push_event(SimEventKind::LapCompleted);
+            self.finish_lap();
+        }
+    }
+
+    fn accumulate_lap_error(&mut self) {
+        let error = self.robot_sdf_to_path();
+        let lap = &mut self.current_lap;
+        lap.squared_error += error * error;
+        lap.max_abs_error = lap.max_abs_error.max(error.abs());
+        lap.steps += 1;
+    }
+
+    fn finish_lap(&mut self) {
+        let lap = self.current_lap;
+        self.lap_stats.push(LapStats {
+            lap: self.completed_laps,
+            lap_time: self.time - lap.start_time,
+            rms_error: (lap.squared_error / lap.steps.max(1) as f64).sqrt(),
+            max_abs_error: lap.max_abs_error,
+        });
+        self.current_lap = LapAccumulator {
+            start_time: self.time,
+            ..Default::default()
+        };
+    }
+
+    /// Statistics of each completed lap, in order
+    pub fn get_lap_stats(&self) -> &[LapStats] {
+        &self.lap_stats
+    }
+
+    fn update_line_detection(&mut self) {
+        let line_detected = self.is_line_detected();
+        if self.line_detected && !line_detected {
+            self.push_event(SimEventKind::OffTrack);
+        } else if !self.line_detected && line_detected {
+            self.push_event(SimEventKind::BackOnTrack);
+        }
+        self.line_detected = line_detected;
+    }
+
+    fn update_saturation(&mut self, saturated: bool) {
+        if saturated && !self.saturated {
+            self.push_event(SimEventKind::MotorSaturated);
+        }
+        self.saturated = saturated;
+    }
+
+    fn make_integrator(
+        params: RobotParams,
+        slip_model: Option<SlipModel>,
+        t: f64,
+        x: Vector<NUM_STATES>,
+    ) -> Rk4<Dynamics, NUM_STATES, NUM_CONTROLS> {
+        Rk4::new(
+            Box::new(move |_, x, u| Self::robot_dynamics(x, u, &params, slip_model)),
+            t,
+            x,
+        )
     }
 
     fn robot_dynamics(
-        _: f64,
         x: &Vector<NUM_STATES>,
         u: &Vector<NUM_CONTROLS>,
+        params: &RobotParams,
+        slip_model: Option<SlipModel>,
     ) -> Vector<NUM_STATES> {
         let (_, _, theta, wl, dwl, wr, dwr) = (x[0], x[1], x[2], x[3], x[4], x[5], x[6]);
         let ul = u[0];
         let ur = u[1];
 
-        let speed = ROBOT_WHEEL_RADIUS * (wl + wr) / 2.0;
-        let d_theta = ROBOT_WHEEL_RADIUS * (wr - wl) / ROBOT_SIDE_LENGTH;
+        let (mut speed, mut d_theta) = params.body_velocity(wl, wr);
+        if let Some(slip) = slip_model {
+            let lateral_acceleration = (speed * d_theta).abs();
+            if lateral_acceleration > slip.traction_limit {
+                // the yaw rate saturates at traction_limit / speed, which widens the turn
+                // even though sliding also slows the robot down
+                let grip = slip.traction_limit / lateral_acceleration;
+                d_theta *= grip;
+                speed *= grip.sqrt();
+            }
+        }
         let d_x = speed * theta.cos();
         let d_y = speed * theta.sin();
         let d_wl = dwl;
-        let d_dwl = (ul - C1 * dwl - C2 * wl) / C0;
+        let (c0, c1) = (params.c0(), params.c1());
+        let d_dwl = (ul - c1 * dwl - C2 * wl) / c0;
         let d_wr = dwr;
-        let d_dwr = (ur - C1 * dwr - C2 * wr) / C0;
+        let d_dwr = (ur - c1 * dwr - C2 * wr) / c0;
 
         Vector::<7>::from_column_slice(&[d_x, d_y, d_theta, d_wl, d_dwl, d_wr, d_dwr])
     }
 
     fn calculate_control(&mut self, dt: f64) -> Vector<NUM_CONTROLS> {
+        if let Some(policy) = self.lost_line_policy {
+            if !self.is_line_detected() {
+                // the PID state is left untouched until the line is found again
+                return match policy {
+                    LostLinePolicy::HoldLastCommand => self.commanded_controls,
+                    LostLinePolicy::SearchSpin => {
+                        self.wheel_commands(0.0, SEARCH_SPIN_RATE * self.prev_error.signum())
+                    }
+                    LostLinePolicy::Stop => Vector::<NUM_CONTROLS>::zeros(),
+                };
+            }
+        }
+
         // control system
+        match self.control_strategy {
+            ControlStrategy::Pid => {}
+            ControlStrategy::Stanley => return self.stanley_control(),
+            ControlStrategy::PurePursuit => {
+                let desired_dtheta = self.forward_speed() * self.pure_pursuit_curvature();
+                return self.steer_geometrically(desired_dtheta);
+            }
+        }
 
         // estimate the robot's angle relative to the track
         // (i.e. the error in theta) by using the sensor array data
-        let error_estimate = self.theta_error_estimate();
+        let error_estimate = self.theta_error_estimate() + self.predicted_error_change(dt);
         let deriv_error = (error_estimate - self.prev_error) / dt;
         self.int_error += self.prev_error * dt;
+        if self.ki != 0.0 {
+            let max_int_error = self.integral_limit / self.ki.abs();
+            self.int_error = self.int_error.clamp(-max_int_error, max_int_error);
+        }
         self.prev_error = error_estimate;
 
         // u(t) = Kp * e(t) + Ki * \int e(t) dt + Kd * \frac{de(t)}{dt}
         self.proportional_term = self.kp * error_estimate;
         self.integral_term = self.ki * self.int_error;
         self.derivative_term = self.kd * deriv_error;
-        let desired_dtheta = self.proportional_term + self.integral_term + self.derivative_term;
-        let k = ROBOT_SIDE_LENGTH * C2 / ROBOT_WHEEL_RADIUS;
+        let desired_dtheta = match self.relay {
+            Some(relay) => relay.amplitude * (error_estimate + relay.lead * deriv_error).signum(),
+            None => {
+                self.proportional_term
+                    + self.integral_term
+                    + self.derivative_term
+                    + self.curvature_feedforward()
+            }
+        };
+        self.wheel_commands(self.speed, desired_dtheta)
+    }
+
+    /// Turn rate added to the PID's, `kff * curvature * speed` with the path's curvature at the
+    /// robot's projection
+    fn curvature_feedforward(&self) -> f64 {
+        if self.kff == 0.0 {
+            return 0.0;
+        }
+        let d = self.path.point_projection_distance(self.robot_position());
+        self.kff * self.path.curvature_at(d) * self.speed
+    }
+
+    /// Steers by the Stanley law, then turns like a bicycle whose wheelbase is the side of the
+    /// robot would with that steering angle, linearized so that it doesn't blow up at right angles
+    fn stanley_control(&mut self) -> Vector<NUM_CONTROLS> {
+        let cross_track_error = self.robot_sdf_to_path();
+        let v = self.forward_speed();
+        // to the left of the path the error is negative, which calls for turning right
+        let steering = (self.stanley_gain * cross_track_error).atan2(v) + self.heading_error();
+        self.steer_geometrically(v * steering / self.params.side_length)
+    }
+
+    /// Curvature of the arc which leaves the robot along its heading and goes through the point
+    /// `lookahead` ahead of its projection on the path, `2 sin(alpha) / d` with `d` the distance
+    /// to that point and `alpha` its angle from the heading. Positive when turning left
+    pub fn pure_pursuit_curvature(&self) -> f64 {
+        let position = self.robot_position();
+        let d = self.path.point_projection_distance(position) + self.lookahead;
+        let goal = self.path.point_at(d.rem_euclid(self.path.length()));
+        let to_goal = goal - position;
+        let distance = to_goal.norm();
+        if distance == 0.0 {
+            return 0.0;
+        }
+        let alpha = wrap_angle(to_goal.y.atan2(to_goal.x) - self.state[2]);
+        2.0 * alpha.sin() / distance
+    }
+
+    /// Commands of the controllers which steer from the geometry of the path rather than from
+    /// the PID terms, which are left at zero
+    fn steer_geometrically(&mut self, desired_dtheta: f64) -> Vector<NUM_CONTROLS> {
+        self.prev_error = self.robot_sdf_to_path();
+        self.proportional_term = 0.0;
+        self.integral_term = 0.0;
+        self.derivative_term = 0.0;
+        self.wheel_commands(self.speed, desired_dtheta)
+    }
+
+    /// Motor commands which, in steady state, make the robot move at `speed`
+    /// while turning at `desired_dtheta`
+    fn wheel_commands(&self, speed: f64, desired_dtheta: f64) -> Vector<NUM_CONTROLS> {
+        let RobotParams {
+            wheel_radius,
+            side_length,
+            ..
+        } = self.params;
+        let k = side_length * C2 / wheel_radius;
 
         let v = k * desired_dtheta;
-        let um = 2.0 * self.speed * C2 / ROBOT_WHEEL_RADIUS;
+        let um = 2.0 * speed * C2 / wheel_radius;
 
         let ul = (um - v) / 2.0;
         let ur = (um + v) / 2.0;
@@ -207,6 +1544,33 @@ impl RobotSimulation {
         Vector2::<f64>::new(ul, ur)
     }
 
+    /// Proportional gain giving the linearized lateral dynamics a natural frequency of
+    /// `target_bandwidth_hz`, as a starting point for tuning.
+    /// Near the line, the lateral error is the integral of `speed` times the heading error,
+    /// which in turn is the integral of the yaw rate. Neglecting the motors' lag, the body turns
+    /// at the commanded rate, since `wheel_commands` inverts the wheels' steady state and the
+    /// kinematics whatever the robot's geometry, so a proportional controller closes the loop
+    /// `e'' = -speed * kp * e`.
+    /// The neglected lag makes the undamped loop weave, so Kd is still needed.
+    /// The loop only has that form when the robot moves forward, so the speed must be positive.
+    pub fn suggest_kp(&self, target_bandwidth_hz: f64) -> f64 {
+        assert!(
+            self.speed > 0.0,
+            "a gain can only be suggested for a positive speed, not {}",
+            self.speed
+        );
+        let w = 2.0 * std::f64::consts::PI * target_bandwidth_hz;
+        w * w / self.speed
+    }
+
+    /// Motor commands that would follow the path exactly in the absence of errors:
+    /// moving at `speed` while turning at the rate required by the path's curvature
+    /// at the robot's projection. A feedback controller then only has to correct the residuals.
+    pub fn feedforward_controls(&self) -> Vector<NUM_CONTROLS> {
+        let d = self.path.point_projection_distance(self.robot_position());
+        self.wheel_commands(self.speed, self.speed * self.path.curvature_at(d))
+    }
+
     pub fn get_proportional_term(&self) -> f64 {
         self.proportional_term
     }
@@ -218,4 +1582,1266 @@ impl RobotSimulation {
     pub fn get_derivative_term(&self) -> f64 {
         self.derivative_term
     }
+
+    /// Accumulated error before being multiplied by Ki
+    pub fn get_integral_error(&self) -> f64 {
+        self.int_error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::closed_path::{predefined_circle, predefined_closed_path, ClosedPath};
+    use crate::geometry::track::Track;
+    use std::f64::consts::PI;
+    use std::sync::Arc;
+
+    fn robot_at(x: f64, y: f64, theta: f64) -> RobotSimulation {
+        let x0 = Vector::<NUM_STATES>::from_column_slice(&[x, y, theta, 0.0, 0.0, 0.0, 0.0]);
+        RobotSimulation::new(x0, 1.0, 0.0, 0.0, 0.0, Arc::new(predefined_closed_path()))
+    }
+
+    #[test]
+    fn test_sensors_detect_line_under_robot() {
+        // robot centered on the first straight, (0, -4) -> (8, -4), facing along it
+        let robot = robot_at(4.0, -4.0, 0.0);
+        let distances = robot.sensor_distances();
+        let center = distances[NUM_SENSORS / 2].expect("center sensor is above the line");
+        assert!(center.abs() < 1e-9);
+        assert!((robot.sensor_signals()[NUM_SENSORS / 2] - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sensor_beyond_max_range_reports_no_detection() {
+        // half a meter to the left of the first straight
+        let mut robot = robot_at(4.0, -3.5, 0.0);
+        assert!(robot.sensor_distances().iter().all(|d| d.is_none()));
+        assert!(robot.sensor_signals().iter().all(|&s| s == 0.0));
+        assert!(!robot.is_line_detected());
+
+        robot.set_max_sensor_distance(1.0);
+        assert!(robot.sensor_distances().iter().all(|d| d.is_some()));
+        assert!(robot.is_line_detected());
+    }
+
+    #[test]
+    #[should_panic(expected = "maximum sensor distance")]
+    fn test_zero_max_sensor_distance_panics() {
+        // the signals would divide by zero at the line
+        robot_at(4.0, -4.0, 0.0).set_max_sensor_distance(0.0);
+    }
+
+    #[test]
+    fn test_off_track_excursion_emits_event() {
+        // no steering at all, so the robot drives straight off the first segment
+        let x0 = Vector::<NUM_STATES>::from_column_slice(&[1.0, -4.0, 0.5, 0.0, 0.0, 0.0, 0.0]);
+        let path = Arc::new(predefined_closed_path());
+        let mut robot = RobotSimulation::new(x0, 0.0, 0.0, 0.0, 0.5, path);
+        let dt = 0.01;
+        let mut lost_at = None;
+        for _ in 0..400 {
+            robot.step(dt);
+            if lost_at.is_none() && !robot.is_line_detected() {
+                lost_at = Some(robot.get_time());
+            }
+        }
+        let lost_at = lost_at.expect("the robot should have left the line");
+        let off_track = robot
+            .get_events()
+            .iter()
+            .filter(|e| e.kind == SimEventKind::OffTrack)
+            .collect::<Vec<_>>();
+        assert_eq!(off_track.len(), 1);
+        assert_eq!(off_track[0].time, lost_at);
+    }
+
+    #[test]
+    fn test_motor_saturated_event() {
+        let dt = 0.01;
+        // starting off the line, the commands peak while turning back towards it
+        let run = |max_control: f64| {
+            let mut robot = robot_at(1.0, -3.9, 0.0);
+            robot.kp = 10.0;
+            robot.speed = 0.5;
+            robot.set_max_control(max_control);
+            let mut peak = 0.0f64;
+            let mut saturated_at = Vec::new();
+            let mut was_saturated = false;
+            for _ in 0..400 {
+                robot.step(dt);
+                let u = robot.get_commanded_controls();
+                peak = peak.max(u[0].abs()).max(u[1].abs());
+                // a clamped command leaves a wheel right at the limit
+                let saturated = u[0].abs().max(u[1].abs()) >= max_control - 1e-12;
+                if saturated && !was_saturated {
+                    saturated_at.push(robot.get_time());
+                }
+                was_saturated = saturated;
+            }
+            let events = robot
+                .get_events()
+                .iter()
+                .filter(|e| e.kind == SimEventKind::MotorSaturated)
+                .map(|e| e.time)
+                .collect::<Vec<_>>();
+            (peak, saturated_at, events)
+        };
+        let (peak, _, events) = run(f64::INFINITY);
+        assert!(events.is_empty());
+
+        let (_, saturated_at, events) = run(peak / 2.0);
+        assert!(!events.is_empty());
+        assert_eq!(events, saturated_at);
+    }
+
+    #[test]
+    fn test_lap_completed_event() {
+        let x0 = Vector::<NUM_STATES>::from_column_slice(&[0.0, -4.0, 0.1, 0.0, 0.0, 0.0, 0.0]);
+        let path = Arc::new(predefined_closed_path());
+        let length = path.length();
+        let mut robot = RobotSimulation::from_config(x0, &OPTIMIZED_CONFIG, path);
+        let dt = 1.0 / 240.0;
+        while robot.get_completed_laps() == 0 && robot.get_time() < 60.0 {
+            robot.step(dt);
+        }
+        assert_eq!(robot.get_completed_laps(), 1);
+        assert!(robot.get_progress() >= length);
+        let laps = robot
+            .get_events()
+            .iter()
+            .filter(|e| e.kind == SimEventKind::LapCompleted)
+            .count();
+        assert_eq!(laps, 1);
+    }
+
+    #[test]
+    fn test_lap_stats_over_a_multi_lap_run() {
+        let mut robot = robot_at(0.0, -4.0, 0.1);
+        robot.kp = OPTIMIZED_CONFIG.kp;
+        robot.ki = OPTIMIZED_CONFIG.ki;
+        robot.kd = OPTIMIZED_CONFIG.kd;
+        robot.speed = OPTIMIZED_CONFIG.speed;
+        let dt = 1.0 / 240.0;
+        let mut max_abs_error = 0.0f64;
+        while robot.get_completed_laps() < 3 && robot.get_time() < 150.0 {
+            robot.step(dt);
+            max_abs_error = max_abs_error.max(robot.robot_sdf_to_path().abs());
+        }
+        let stats = robot.get_lap_stats();
+        assert_eq!(stats.len(), 3);
+        let lap_ends = robot
+            .get_events()
+            .iter()
+            .filter(|e| e.kind == SimEventKind::LapCompleted)
+            .map(|e| e.time)
+            .collect::<Vec<_>>();
+        let mut lap_start = 0.0;
+        for (i, (lap, end)) in stats.iter().zip(lap_ends).enumerate() {
+            assert_eq!(lap.lap, i + 1);
+            assert!((lap.lap_time - (end - lap_start)).abs() < 1e-9);
+            assert!(lap.rms_error > 0.0 && lap.rms_error <= lap.max_abs_error);
+            lap_start = end;
+        }
+        let overall_max = stats.iter().map(|l| l.max_abs_error).fold(0.0, f64::max);
+        assert_eq!(overall_max, max_abs_error);
+        // after the initial transient, the laps are about the same
+        assert!((stats[2].lap_time - stats[1].lap_time).abs() < 0.05 * stats[1].lap_time);
+    }
+
+    #[test]
+    fn test_backward_initial_heading_is_detected() {
+        let mismatches = |robot: &RobotSimulation| {
+            robot
+                .get_events()
+                .iter()
+                .filter(|e| e.kind == SimEventKind::InitialHeadingMismatch)
+                .count()
+        };
+        // facing along the first straight: nothing to report
+        let robot = robot_at(4.0, -4.0, 0.1).with_heading_check(0.5, true);
+        assert_eq!(mismatches(&robot), 0);
+        assert_eq!(robot.get_state()[2], 0.1);
+
+        // facing backwards, only warn
+        let robot = robot_at(4.0, -4.0, PI).with_heading_check(0.5, false);
+        assert_eq!(mismatches(&robot), 1);
+        assert_eq!(robot.get_state()[2], PI);
+
+        // facing backwards, turned around to face along the path
+        let mut robot = robot_at(4.0, -4.0, PI).with_heading_check(0.5, true);
+        assert_eq!(mismatches(&robot), 1);
+        assert!(robot.heading_error().abs() < 1e-9);
+        robot.reset();
+        assert!(robot.heading_error().abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_heading_error_mode() {
+        // beside the first straight, facing along it
+        let mut robot = robot_at(4.0, -3.9, 0.0);
+        let offset = robot.theta_error_estimate();
+        assert!((offset + 0.1).abs() < 1e-12);
+        robot.set_error_mode(ErrorMode::Heading);
+        assert_eq!(robot.get_error_mode(), ErrorMode::Heading);
+        assert!(robot.theta_error_estimate().abs() < 1e-12);
+        robot.set_error_mode(ErrorMode::Combined {
+            heading_weight: 0.5,
+        });
+        assert!((robot.theta_error_estimate() - offset).abs() < 1e-12);
+
+        // turned to the left of the straight, the robot should turn right, and facing backwards
+        // the error is as large as it gets, where the distance alone doesn't change at all
+        for (heading, expected) in [(0.2, -0.2), (-0.3, 0.3), (PI, PI)] {
+            let mut robot = robot_at(4.0, -3.9, heading);
+            assert!((robot.theta_error_estimate() - offset).abs() < 1e-12);
+            robot.set_error_mode(ErrorMode::Heading);
+            let error = robot.theta_error_estimate();
+            assert_eq!(error, robot.heading_error());
+            assert!((error.abs() - f64::abs(expected)).abs() < 1e-12);
+            if heading != PI {
+                assert!((error - expected).abs() < 1e-12);
+            }
+            robot.set_error_mode(ErrorMode::Combined {
+                heading_weight: 0.5,
+            });
+            assert!((robot.theta_error_estimate() - (offset + 0.5 * error)).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_integral_error_accumulates_constant_error() {
+        // standing still beside the line keeps the error constant
+        let mut robot = robot_at(4.0, -3.9, 0.0);
+        robot.kp = 0.0;
+        let error = robot.theta_error_estimate();
+        let dt = 0.01;
+        for _ in 0..200 {
+            robot.step(dt);
+        }
+        assert_eq!(robot.theta_error_estimate(), error);
+        // the integral uses the error from the previous step,
+        // so the very first step doesn't contribute
+        let expected = error * (robot.get_time() - dt);
+        assert!((robot.get_integral_error() - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_integral_term_saturates_at_the_limit() {
+        // only the integral acts, so the robot turns in place and the error stays the same
+        let mut robot = robot_at(4.0, -3.0, 0.0);
+        robot.kp = 0.0;
+        robot.ki = 10.0;
+        robot.set_integral_limit(2.0);
+        let error = robot.theta_error_estimate();
+        assert!(error.abs() > 0.5);
+        for _ in 0..500 {
+            robot.step(0.01);
+        }
+        assert_eq!(robot.theta_error_estimate(), error);
+        // unbounded, it would have reached 10 * error * 4.99 by now
+        assert!((robot.get_integral_term() - 2.0 * error.signum()).abs() < 1e-12);
+        assert!((robot.get_integral_error().abs() - 0.2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_controls_never_exceed_the_limit() {
+        let x0 = Vector::<NUM_STATES>::from_column_slice(&[0.0, -4.0, 0.1, 0.0, 0.0, 0.0, 0.0]);
+        let path = Arc::new(predefined_closed_path());
+        let mut robot = RobotSimulation::new(x0, 50.0, 0.0, 5.0, 2.0, path);
+        let limit = 60.0;
+        robot.set_max_control(limit);
+        let mut saturated_steps = 0;
+        for _ in 0..(20 * 240) {
+            robot.step(1.0 / 240.0);
+            let u = robot.get_commanded_controls();
+            assert!(u[0].abs() <= limit && u[1].abs() <= limit, "{:?}", u);
+            if u[0].abs().max(u[1].abs()) == limit {
+                saturated_steps += 1;
+            }
+        }
+        // the speed alone takes 2 * 2.0 / 0.04 = 100 on each wheel
+        assert!(saturated_steps > 0);
+        assert!(!robot.is_diverged());
+        // the turn is kept at the expense of the speed, as long as it fits
+        robot.set_max_control(50.0);
+        assert_eq!(
+            robot.saturate(Vector2::new(90.0, 110.0)),
+            Vector2::new(30.0, 50.0)
+        );
+        assert_eq!(
+            robot.saturate(Vector2::new(-80.0, 40.0)),
+            Vector2::new(-50.0, 50.0)
+        );
+        assert_eq!(
+            robot.saturate(Vector2::new(10.0, -20.0)),
+            Vector2::new(10.0, -20.0)
+        );
+    }
+
+    #[test]
+    fn test_command_changes_never_exceed_the_slew_limit() {
+        // starting well off the line makes for a large error step on the first sensor reading
+        let x0 = Vector::<NUM_STATES>::from_column_slice(&[0.0, -3.8, 0.1, 0.0, 0.0, 0.0, 0.0]);
+        let path = Arc::new(predefined_closed_path());
+        let mut robot = RobotSimulation::new(x0, 50.0, 0.0, 5.0, 1.0, path);
+        let max_slew = 0.5;
+        robot.set_max_command_slew(Some(max_slew));
+        let mut previous = robot.get_commanded_controls();
+        let mut limited_steps = 0;
+        for _ in 0..(10 * 240) {
+            robot.step(1.0 / 240.0);
+            let u = robot.get_commanded_controls();
+            let change = (u[0] - previous[0]).abs().max((u[1] - previous[1]).abs());
+            assert!(
+                change <= max_slew + 1e-12,
+                "{} at {}",
+                change,
+                robot.get_time()
+            );
+            if change >= max_slew - 1e-12 {
+                limited_steps += 1;
+            }
+            previous = u;
+        }
+        assert!(limited_steps > 0);
+        assert!(!robot.is_diverged());
+        robot.set_max_command_slew(None);
+        assert_eq!(
+            robot.limit_slew(Vector2::new(1e3, -1e3)),
+            Vector2::new(1e3, -1e3)
+        );
+    }
+
+    #[test]
+    fn test_distance_traveled_on_straight_and_weaving_runs() {
+        let dt = 1.0 / 240.0;
+        // on the line and facing along it, so the robot never turns
+        let mut straight = robot_at(1.0, -4.0, 0.0);
+        straight.kp = 0.0;
+        straight.speed = 0.5;
+        for _ in 0..960 {
+            straight.step(dt);
+        }
+        let displacement = (straight.robot_position() - Point2::new(1.0, -4.0)).norm();
+        assert!(displacement > 1.0);
+        assert!((straight.distance_traveled() - displacement).abs() < 1e-9);
+
+        // starting off the line, a P-only controller oscillates around it
+        let mut weaving = robot_at(1.0, -3.9, 0.0);
+        weaving.kp = 10.0;
+        weaving.speed = 0.5;
+        for _ in 0..960 {
+            weaving.step(dt);
+        }
+        let displacement = (weaving.robot_position() - Point2::new(1.0, -3.9)).norm();
+        assert!(weaving.distance_traveled() > displacement + 1e-3);
+    }
+
+    #[test]
+    fn test_iae_of_perfect_and_offset_runs() {
+        let dt = 1.0 / 240.0;
+        // on the line and facing along it, so the robot never leaves it
+        let mut on_line = robot_at(1.0, -4.0, 0.0);
+        on_line.kp = 0.0;
+        on_line.speed = 0.5;
+        for _ in 0..960 {
+            on_line.step(dt);
+        }
+        assert!(on_line.get_iae() < 1e-12, "{}", on_line.get_iae());
+
+        // parallel to the line at a constant offset, which accumulates as fast all along
+        let offset = 0.1;
+        let mut parallel = robot_at(1.0, -4.0 + offset, 0.0);
+        parallel.kp = 0.0;
+        parallel.speed = 0.5;
+        for k in 1..=960 {
+            parallel.step(dt);
+            if k % 240 == 0 {
+                let expected = offset * parallel.get_time();
+                assert!((parallel.get_iae() - expected).abs() < 1e-9, "{}", k);
+            }
+        }
+
+        parallel.reset();
+        assert_eq!(parallel.get_iae(), 0.0);
+    }
+
+    #[test]
+    fn test_actuator_delay_shifts_applied_controls() {
+        let dt = 1.0 / 240.0;
+        let delay = 3;
+        let mut undelayed = robot_at(1.0, -3.95, 0.0);
+        undelayed.speed = 0.5;
+        let mut delayed = robot_at(1.0, -3.95, 0.0);
+        delayed.speed = 0.5;
+        delayed.set_actuator_delay_steps(delay);
+
+        let mut commanded = Vec::new();
+        for k in 0..50 {
+            undelayed.step(dt);
+            assert_eq!(undelayed.get_controls(), undelayed.get_commanded_controls());
+
+            delayed.step(dt);
+            commanded.push(delayed.get_commanded_controls());
+            if k < delay {
+                assert_eq!(delayed.get_controls(), Vector::<NUM_CONTROLS>::zeros());
+            } else {
+                assert_eq!(delayed.get_controls(), commanded[k - delay]);
+            }
+        }
+        assert!(commanded[0].norm() > 0.0);
+    }
+
+    #[test]
+    fn test_prediction_compensates_the_actuator_delay() {
+        let run = |delay: usize, prediction: usize| {
+            let x0 = Vector::<NUM_STATES>::from_column_slice(&[0.0, -4.0, 0.1, 0.0, 0.0, 0.0, 0.0]);
+            let path = Arc::new(predefined_closed_path());
+            let mut robot = RobotSimulation::new(x0, 50.0, 0.0, 5.0, 0.5, path);
+            robot.set_actuator_delay_steps(delay);
+            robot.set_prediction_steps(prediction);
+            let mut squared_error = 0.0;
+            let steps = 40 * 240;
+            for _ in 0..steps {
+                robot.step(1.0 / 240.0);
+                squared_error += robot.robot_sdf_to_path().powi(2);
+            }
+            (squared_error / steps as f64).sqrt()
+        };
+        // a tenth of a second is enough to make the robot weave around the line
+        let undelayed = run(0, 0);
+        let delayed = run(24, 0);
+        let predicted = run(24, 24);
+        assert!(delayed > 10.0 * undelayed, "{} vs {}", delayed, undelayed);
+        // with an exact model, it is the same loop as without any delay, only later
+        assert!(
+            predicted < 1.5 * undelayed,
+            "{} vs {}",
+            predicted,
+            undelayed
+        );
+    }
+
+    #[test]
+    fn test_sensor_line_offset_matches_sdf_sign() {
+        // 2 cm to the left of the first straight, so the line is to the right of the sensors
+        let robot = robot_at(4.0, -3.98, 0.0);
+        let offset = robot.sensor_line_offset().unwrap();
+        assert!((offset + 0.02).abs() < 5e-3, "{}", offset);
+        assert!(robot.robot_sdf_to_path() < 0.0);
+        assert!(robot_at(4.0, -3.5, 0.0).sensor_line_offset().is_none());
+    }
+
+    #[test]
+    fn test_sensor_line_pose_matches_the_track() {
+        // beside the first straight and turned either way, or right above it and along it
+        for (y, heading) in [(-3.99, 0.3), (-4.01, -0.2), (-4.0, 0.0)] {
+            let robot = robot_at(4.0, y, heading);
+            let (offset, heading_error) = robot.sensor_line_pose().unwrap();
+            let center =
+                nalgebra::center(&robot.sensor_positions()[0], &robot.sensor_positions()[4]);
+            assert!((offset - robot.path.sdf(center)).abs() < 1e-9, "{}", offset);
+            // acos is steep near 0, where a rounding error of cos(phi) shows the most
+            assert!((heading_error - robot.heading_error().abs()).abs() < 1e-6);
+        }
+        assert!(robot_at(4.0, -3.5, 0.0).sensor_line_pose().is_none());
+    }
+
+    #[test]
+    fn test_quantized_sensor_position_takes_only_discrete_values() {
+        let levels = 8;
+        let step = SENSOR_ARRAY_LENGTH / (levels - 1) as f64;
+        let allowed = (0..levels)
+            .map(|i| -SENSOR_ARRAY_LENGTH / 2.0 + i as f64 * step)
+            .collect::<Vec<_>>();
+        let mut seen = Vec::new();
+        // sweep the robot across the first straight, from left to right of the line
+        for i in 0..=100 {
+            let mut robot = robot_at(4.0, -4.0 + 0.05 - i as f64 * 0.001, 0.0);
+            let continuous = robot.sensor_line_offset();
+            robot.set_position_levels(Some(levels));
+            let offset = match robot.sensor_line_offset() {
+                Some(offset) => offset,
+                None => {
+                    assert!(continuous.is_none());
+                    continue;
+                }
+            };
+            let level = allowed
+                .iter()
+                .position(|a| (a - offset).abs() < 1e-12)
+                .unwrap_or_else(|| panic!("{} is not one of the levels", offset));
+            assert!((offset - continuous.unwrap()).abs() <= step / 2.0 + 1e-12);
+            if !seen.contains(&level) {
+                seen.push(level);
+            }
+        }
+        assert!(seen.len() > 2);
+    }
+
+    #[test]
+    fn test_sensor_only_mode_completes_a_lap_like_omniscient_mode() {
+        let run = |reference_mode: ReferenceMode| {
+            let x0 = Vector::<NUM_STATES>::from_column_slice(&[0.0, -4.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+            let path = Arc::new(predefined_closed_path());
+            let mut robot = RobotSimulation::new(x0, 50.0, 0.0, 5.0, 0.5, path);
+            robot.set_reference_mode(reference_mode);
+            let dt = 1.0 / 240.0;
+            let (mut squared_error, mut steps) = (0.0, 0);
+            while robot.get_completed_laps() == 0 && robot.get_time() < 150.0 {
+                robot.step(dt);
+                squared_error += robot.robot_sdf_to_path().powi(2);
+                steps += 1;
+            }
+            assert_eq!(robot.get_completed_laps(), 1, "{:?}", reference_mode);
+            (robot.get_time(), (squared_error / steps as f64).sqrt())
+        };
+        let (omniscient_time, omniscient_rms) = run(ReferenceMode::Omniscient);
+        let (sensor_time, sensor_rms) = run(ReferenceMode::SensorOnly);
+        assert!((sensor_time - omniscient_time).abs() < 0.05 * omniscient_time);
+        // both stay within reach of the sensors on average
+        assert!(omniscient_rms < MAX_SENSOR_DISTANCE, "{}", omniscient_rms);
+        assert!(sensor_rms < MAX_SENSOR_DISTANCE, "{}", sensor_rms);
+    }
+
+    #[test]
+    fn test_blended_error_combines_the_sdf_and_sensor_errors() {
+        // off the line and at an angle to it, so that the sensors see a different error
+        let mut robot = robot_at(1.0, -3.97, 0.3);
+        let sdf_error = robot.robot_sdf_to_path();
+        let sensor_error = robot.sensor_line_offset().unwrap();
+        assert!((sdf_error - sensor_error).abs() > 1e-3);
+
+        robot.set_reference_mode(ReferenceMode::Omniscient);
+        assert_eq!(robot.theta_error_estimate(), sdf_error);
+        robot.set_reference_mode(ReferenceMode::SensorOnly);
+        assert_eq!(robot.theta_error_estimate(), sensor_error);
+        for alpha in [0.0, 0.25, 0.5, 0.8, 1.0] {
+            robot.set_reference_mode(ReferenceMode::Blended { alpha });
+            let expected = alpha * sdf_error + (1.0 - alpha) * sensor_error;
+            assert!((robot.theta_error_estimate() - expected).abs() < 1e-12);
+            assert_eq!(robot.theta_error_estimate(), robot.blended_error(alpha));
+        }
+        // the ends of the blend are the omniscient and sensor-only errors
+        assert_eq!(robot.blended_error(1.0), sdf_error);
+        assert_eq!(robot.blended_error(0.0), sensor_error);
+    }
+
+    #[test]
+    #[should_panic(expected = "blend factor")]
+    fn test_blend_factor_out_of_range_panics() {
+        let mut robot = robot_at(0.0, -4.0, 0.0);
+        robot.set_reference_mode(ReferenceMode::Blended { alpha: 1.5 });
+    }
+
+    #[test]
+    fn test_clone_continues_like_the_original() {
+        let x0 = Vector::<NUM_STATES>::from_column_slice(&[0.0, -4.0, 0.1, 0.0, 0.0, 0.0, 0.0]);
+        let path = Arc::new(predefined_closed_path());
+        let mut robot = RobotSimulation::new(x0, 50.0, 0.0, 5.0, 0.5, path)
+            .with_sensor_noise(0.005, 3)
+            .with_synced_reference();
+        robot.set_actuator_delay_steps(2);
+        for _ in 0..300 {
+            robot.step(1.0 / 240.0);
+        }
+        let mut copy = robot.clone();
+        // the paths are shared; their data pointers tell, unlike the vtables `ptr_eq` compares too
+        assert!(std::ptr::eq(
+            Arc::as_ptr(copy.get_path()).cast::<u8>(),
+            Arc::as_ptr(robot.get_path()).cast::<u8>()
+        ));
+        for _ in 0..600 {
+            robot.step(1.0 / 240.0);
+            copy.step(1.0 / 240.0);
+            assert_eq!(copy.get_state(), robot.get_state());
+        }
+        assert_eq!(copy.get_time(), robot.get_time());
+        assert_eq!(copy.get_progress(), robot.get_progress());
+        // then each one goes its own way
+        copy.kp = 10.0;
+        for _ in 0..240 {
+            robot.step(1.0 / 240.0);
+            copy.step(1.0 / 240.0);
+        }
+        assert_ne!(copy.get_state(), robot.get_state());
+        assert_eq!(robot.kp, 50.0);
+    }
+
+    #[test]
+    fn test_sensor_noise_is_reproducible() {
+        let run = |seed: Option<u64>| {
+            let x0 = Vector::<NUM_STATES>::from_column_slice(&[0.0, -4.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+            let path = Arc::new(predefined_closed_path());
+            let mut robot = RobotSimulation::new(x0, 50.0, 0.0, 5.0, 0.5, path);
+            if let Some(seed) = seed {
+                robot = robot.with_sensor_noise(0.005, seed);
+            }
+            robot.set_reference_mode(ReferenceMode::SensorOnly);
+            (0..2400)
+                .map(|_| {
+                    robot.step(1.0 / 240.0);
+                    robot.robot_position()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(run(Some(7)), run(Some(7)));
+        let (noisy, other_seed, noiseless) = (run(Some(7)), run(Some(8)), run(None));
+        let deviation = |a: &[Point2<f64>], b: &[Point2<f64>]| {
+            a.iter()
+                .zip(b)
+                .map(|(p, q)| (p - q).norm())
+                .fold(0.0, f64::max)
+        };
+        assert!(deviation(&noisy, &other_seed) > 1e-4);
+        assert!(deviation(&noisy, &noiseless) > 1e-4);
+        // a standard deviation of 0 disables the noise
+        let x0 = Vector::<NUM_STATES>::from_column_slice(&[0.0, -4.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        let path = Arc::new(predefined_closed_path());
+        let robot = RobotSimulation::new(x0, 50.0, 0.0, 5.0, 0.5, path).with_sensor_noise(0.0, 7);
+        assert_eq!(
+            robot.sensor_distances(),
+            robot_at(0.0, -4.0, 0.0).sensor_distances()
+        );
+    }
+
+    #[test]
+    fn test_lost_line_policies() {
+        // no steering, so the robot drives straight off the first segment, towards its left
+        let lost_robot = |policy: LostLinePolicy| {
+            let x0 = Vector::<NUM_STATES>::from_column_slice(&[1.0, -4.0, 0.5, 0.0, 0.0, 0.0, 0.0]);
+            let path = Arc::new(predefined_closed_path());
+            let mut robot = RobotSimulation::new(x0, 0.0, 0.0, 0.0, 0.5, path);
+            robot.set_reference_mode(ReferenceMode::SensorOnly);
+            robot.set_lost_line_policy(Some(policy));
+            let dt = 0.01;
+            let mut last_seen_command = None;
+            while robot.is_line_detected() {
+                assert!(
+                    robot.get_time() < 4.0,
+                    "the robot should have left the line"
+                );
+                robot.step(dt);
+                last_seen_command = Some(robot.get_commanded_controls());
+            }
+            robot.step(dt);
+            (robot, last_seen_command.unwrap())
+        };
+
+        let (robot, _) = lost_robot(LostLinePolicy::Stop);
+        assert_eq!(
+            robot.get_commanded_controls(),
+            Vector::<NUM_CONTROLS>::zeros()
+        );
+
+        let (mut robot, last_seen_command) = lost_robot(LostLinePolicy::HoldLastCommand);
+        for _ in 0..10 {
+            assert_eq!(robot.get_commanded_controls(), last_seen_command);
+            robot.step(0.01);
+        }
+
+        // the line was last seen to the right, so the robot spins clockwise without moving forward
+        let (robot, _) = lost_robot(LostLinePolicy::SearchSpin);
+        let u = robot.get_commanded_controls();
+        assert!((u[0] + u[1]).abs() < 1e-12);
+        assert!(u[0] > u[1]);
+    }
+
+    #[test]
+    fn test_restoring_a_snapshot_rewinds_the_run() {
+        let x0 = Vector::<NUM_STATES>::from_column_slice(&[0.0, -4.0, 0.1, 0.0, 0.0, 0.0, 0.0]);
+        let path = Arc::new(predefined_closed_path());
+        let mut robot = RobotSimulation::new(x0, 5.0, 1.0, 2.0, 0.5, path);
+        robot.set_actuator_delay_steps(3);
+        let dt = 1.0 / 240.0;
+        for _ in 0..500 {
+            robot.step(dt);
+        }
+        let snapshot = robot.snapshot();
+        let mut continuation = Vec::new();
+        for _ in 0..500 {
+            robot.step(dt);
+            continuation.push(robot.get_state());
+        }
+
+        robot.restore(&snapshot);
+        assert_eq!(robot.get_state(), snapshot.get_state());
+        assert_eq!(robot.get_time(), snapshot.get_time());
+        assert_eq!(robot.get_progress(), snapshot.progress);
+        assert_eq!(robot.get_integral_error(), snapshot.int_error);
+        assert_eq!(robot.get_events(), &snapshot.events[..]);
+        // stepping again from the snapshot is bit for bit the same run
+        for state in continuation {
+            robot.step(dt);
+            assert_eq!(robot.get_state(), state);
+        }
+    }
+
+    #[test]
+    fn test_convergence_after_transient_but_not_while_weaving() {
+        let run = |kd: f64| {
+            let x0 = Vector::<NUM_STATES>::from_column_slice(&[0.0, -2.0, 0.1, 0.0, 0.0, 0.0, 0.0]);
+            let path = Arc::new(predefined_circle(2.0));
+            let mut robot = RobotSimulation::new(x0, 50.0, 0.0, kd, 0.5, path);
+            let dt = 1.0 / 240.0;
+            // time at which it is first reported as converged
+            let mut converged_at = None;
+            for _ in 0..(30 * 240) {
+                robot.step(dt);
+                if converged_at.is_none() && robot.is_converged(2.0, 1e-3) {
+                    converged_at = Some(robot.get_time());
+                }
+            }
+            (robot, converged_at)
+        };
+
+        let (settled, converged_at) = run(5.0);
+        let converged_at = converged_at.expect("the damped run settles");
+        // not during the transient, nor before there is a whole window of history
+        assert!(converged_at > 2.0);
+        assert!(settled.is_converged(2.0, 1e-3));
+        assert!(!settled.is_converged(ERROR_HISTORY_DURATION + 1.0, 1e-3));
+
+        // proportional control alone keeps weaving around the line
+        let (weaving, converged_at) = run(0.0);
+        assert_eq!(converged_at, None);
+        assert!(!weaving.is_converged(2.0, 1e-3));
+    }
+
+    #[test]
+    fn test_pid_terms_add_up_to_the_commanded_turn_rate() {
+        let x0 = Vector::<NUM_STATES>::from_column_slice(&[0.0, -4.0, 0.1, 0.0, 0.0, 0.0, 0.0]);
+        let path = Arc::new(predefined_closed_path());
+        let mut robot = RobotSimulation::new(x0, 5.0, 2.0, 1.5, 0.5, path);
+        let dt = 1.0 / 240.0;
+        let k = ROBOT_SIDE_LENGTH * C2 / ROBOT_WHEEL_RADIUS;
+        for _ in 0..50 {
+            robot.step(dt);
+            let u = robot.get_commanded_controls();
+            let desired_dtheta = (u[1] - u[0]) / k;
+            let sum = robot.get_proportional_term()
+                + robot.get_integral_term()
+                + robot.get_derivative_term();
+            assert!((sum - desired_dtheta).abs() < 1e-9);
+        }
+        assert!(robot.get_proportional_term() != 0.0);
+        assert!(robot.get_integral_term() != 0.0);
+        assert!(robot.get_derivative_term() != 0.0);
+
+        robot.reset();
+        assert_eq!(robot.get_proportional_term(), 0.0);
+        assert_eq!(robot.get_integral_term(), 0.0);
+        assert_eq!(robot.get_derivative_term(), 0.0);
+    }
+
+    #[test]
+    fn test_reset_restarts_the_run_with_the_current_gains() {
+        let x0 = Vector::<NUM_STATES>::from_column_slice(&[0.0, -4.0, 0.1, 0.0, 0.0, 0.0, 0.0]);
+        let path = Arc::new(predefined_closed_path());
+        let mut robot = RobotSimulation::new(x0, 5.0, 1.0, 2.0, 0.5, path.clone());
+        let dt = 1.0 / 240.0;
+        for _ in 0..500 {
+            robot.step(dt);
+        }
+        // like the sliders of the GUI
+        robot.kp = 8.0;
+        robot.speed = 0.7;
+        robot.reset();
+        assert_eq!(robot.get_state(), x0);
+        assert_eq!(robot.get_time(), 0.0);
+        assert_eq!(robot.get_integral_error(), 0.0);
+        assert_eq!(robot.get_progress(), 0.0);
+        assert!(robot.get_events().is_empty());
+        assert_eq!((robot.kp, robot.speed), (8.0, 0.7));
+
+        // the same run as a new simulation with the edited gains
+        let mut fresh = RobotSimulation::new(x0, 8.0, 1.0, 2.0, 0.7, path);
+        for _ in 0..500 {
+            robot.step(dt);
+            fresh.step(dt);
+            assert_eq!(robot.get_state(), fresh.get_state());
+        }
+    }
+
+    #[test]
+    fn test_encoders_on_a_straight_run() {
+        // on the first straight, facing along it, and without steering
+        let x0 = Vector::<NUM_STATES>::from_column_slice(&[0.5, -4.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        let path = Arc::new(predefined_closed_path());
+        let mut robot = RobotSimulation::new(x0, 0.0, 0.0, 0.0, 0.5, path);
+        let dt = 1.0 / 240.0;
+        for _ in 0..(5 * 240) {
+            robot.step(dt);
+        }
+        let (left, right) = robot.encoder_counts();
+        assert_eq!(left, right);
+        let revolutions = robot.distance_traveled() / (2.0 * PI * ROBOT_WHEEL_RADIUS);
+        let expected = revolutions * ENCODER_COUNTS_PER_REVOLUTION;
+        assert!(expected > 1000.0);
+        assert!(
+            (left as f64 - expected).abs() <= 1.0,
+            "{} vs {}",
+            left,
+            expected
+        );
+    }
+
+    /// Turn radius of the robot after driving for a second with the wheels held
+    /// at `wl` and `wr`, measured from the trajectory
+    fn turn_radius(wl: f64, wr: f64, slip_model: Option<SlipModel>) -> f64 {
+        let x0 = Vector::<NUM_STATES>::from_column_slice(&[0.0, 0.0, 0.0, wl, 0.0, wr, 0.0]);
+        let u = Vector2::new(C2 * wl, C2 * wr);
+        let mut integrator =
+            RobotSimulation::make_integrator(RobotParams::default(), slip_model, 0.0, x0);
+        let mut length = 0.0;
+        for _ in 0..1000 {
+            let previous = integrator.get_state();
+            integrator.step(1e-3, &u);
+            let x = integrator.get_state();
+            length += ((x[0] - previous[0]).powi(2) + (x[1] - previous[1]).powi(2)).sqrt();
+        }
+        length / integrator.get_state()[2]
+    }
+
+    #[test]
+    fn test_slip_widens_turns_above_the_traction_limit() {
+        // 1 m/s while turning at 4 rad/s, i.e. a radius of 0.25 m and 4 m/s^2 laterally
+        let (wl, wr) = (20.0, 30.0);
+        let commanded_radius = 0.25;
+        assert!((turn_radius(wl, wr, None) - commanded_radius).abs() < 1e-6);
+        let below_limit = Some(SlipModel {
+            traction_limit: 5.0,
+        });
+        assert!((turn_radius(wl, wr, below_limit) - commanded_radius).abs() < 1e-6);
+        let above_limit = Some(SlipModel {
+            traction_limit: 2.0,
+        });
+        let radius = turn_radius(wl, wr, above_limit);
+        assert!(radius > 1.2 * commanded_radius, "{}", radius);
+
+        let mut robot = robot_at(0.0, 0.0, 0.0);
+        robot.set_slip_model(above_limit);
+        assert_eq!(robot.get_slip_model(), above_limit);
+        robot.state[3] = wl;
+        robot.state[5] = wr;
+        assert!((robot.lateral_acceleration() - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_slip_angle_is_zero_without_slip() {
+        assert_eq!(robot_at(0.0, -4.0, 0.3).slip_angle(), 0.0);
+
+        let x0 = Vector::<NUM_STATES>::from_column_slice(&[0.0, -4.0, 0.1, 0.0, 0.0, 0.0, 0.0]);
+        let mut robot =
+            RobotSimulation::from_config(x0, &OPTIMIZED_CONFIG, Arc::new(predefined_closed_path()));
+        for _ in 0..2400 {
+            robot.step(1.0 / 240.0);
+            let slip_angle = robot.slip_angle();
+            assert!(slip_angle.abs() < 1e-12, "{}", slip_angle);
+            let velocity = robot.robot_velocity();
+            assert!((velocity.norm() - robot.forward_speed().abs()).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_reference_path_is_separate_from_the_sensed_path() {
+        let mut robot = robot_at(1.0, -3.8, 0.0);
+        let same_track = |a: &SharedTrack, b: &SharedTrack| {
+            std::ptr::eq(Arc::as_ptr(a).cast::<u8>(), Arc::as_ptr(b).cast::<u8>())
+        };
+        assert!(same_track(robot.get_reference_path(), &robot.path));
+        for _ in 0..100 {
+            robot.step(0.01);
+        }
+        robot.speed = 0.5;
+        let (point, sdf) = (robot.reference_point(), robot.robot_sdf_to_path());
+        assert_eq!(point, robot.path.point_at(0.5 * robot.get_time()));
+
+        let circle = Arc::new(predefined_circle(2.0));
+        robot.set_reference_path(circle.clone());
+        assert_ne!(robot.reference_point(), point);
+        assert_eq!(
+            robot.reference_point(),
+            circle.point_at(0.5 * robot.get_time())
+        );
+        assert_eq!(
+            robot.reference_tangent(),
+            circle.tangent_at(0.5 * robot.get_time())
+        );
+        assert_eq!(robot.robot_sdf_to_path(), sdf);
+    }
+
+    #[test]
+    fn test_synced_reference_starts_at_the_robot() {
+        let path = predefined_closed_path();
+        let offset = 0.05;
+        for k in 0..20 {
+            let d = k as f64 * path.length() / 20.0;
+            let t = path.tangent_at(d);
+            let p = path.point_at(d) + Vector2::new(t.y, -t.x) * offset;
+            let theta = t.y.atan2(t.x);
+            let unsynced = robot_at(p.x, p.y, theta);
+            assert_eq!(
+                unsynced.robot_error(),
+                distance_squared(&path.first_point(), &p)
+            );
+            let robot = robot_at(p.x, p.y, theta).with_synced_reference();
+            assert!((robot.get_reference_offset() - d).abs() < 1e-9);
+            assert!(
+                robot.robot_error() < offset * offset + 1e-9,
+                "start at {}",
+                d
+            );
+        }
+        let mut robot = robot_at(0.0, -4.0, 0.0);
+        robot.set_reference_offset(-1.0);
+        assert_eq!(robot.get_reference_offset(), path.length() - 1.0);
+    }
+
+    #[test]
+    fn test_reference_follows_the_reference_profile() {
+        let (max_speed, ramp_time, dt) = (0.5, 2.0, 1.0 / 240.0);
+        // largest change, from one step to the next, of the reference's acceleration by finite
+        // differences of its arc-length, until the ramp is well over
+        let largest_acceleration_jump = |profile: SpeedProfile<f64>| {
+            let mut robot = robot_at(0.5, -4.0, 0.0);
+            robot.speed = max_speed;
+            robot.set_reference_profile(Some(profile));
+            let mut distances = vec![robot.reference_arc_length()];
+            while robot.get_time() < 2.0 * ramp_time {
+                robot.step(dt);
+                distances.push(robot.reference_arc_length());
+            }
+            let accelerations = distances
+                .windows(3)
+                .map(|d| (d[2] - 2.0 * d[1] + d[0]) / (dt * dt))
+                .collect::<Vec<_>>();
+            accelerations
+                .windows(2)
+                .map(|a| (a[1] - a[0]).abs())
+                .fold(0.0, f64::max)
+        };
+        let minimum_jerk = SpeedProfile::MinimumJerk {
+            max_speed,
+            ramp_time,
+        };
+        let trapezoidal = SpeedProfile::Trapezoidal {
+            max_speed,
+            acceleration: max_speed / ramp_time,
+        };
+        // the acceleration only changes by the jerk over a step, at most about 5.8 times
+        // `max_speed / ramp_time^2`, while the trapezoid's jumps by `max_speed / ramp_time` at the
+        // ends of the ramp, spread over at most two steps
+        let smooth = largest_acceleration_jump(minimum_jerk.clone());
+        assert!(
+            smooth < 6.0 * max_speed / (ramp_time * ramp_time) * dt,
+            "{}",
+            smooth
+        );
+        let jump = largest_acceleration_jump(trapezoidal);
+        assert!(jump > 0.5 * max_speed / ramp_time, "{}", jump);
+
+        // without a profile, the reference moves at the robot's speed
+        let mut robot = robot_at(0.5, -4.0, 0.0);
+        robot.speed = max_speed;
+        assert_eq!(
+            robot.get_reference_profile(),
+            SpeedProfile::Constant(max_speed)
+        );
+        robot.set_reference_profile(Some(minimum_jerk.clone()));
+        assert_eq!(robot.get_reference_profile(), minimum_jerk);
+        for _ in 0..240 {
+            robot.step(dt);
+        }
+        let expected = robot
+            .get_reference_path()
+            .point_at_time(robot.get_time(), &minimum_jerk);
+        assert!((robot.reference_point() - expected).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_record_csv_writes_a_row_per_step() {
+        let mut robot = robot_at(0.5, -3.95, 0.0);
+        robot.speed = 0.5;
+        let mut csv = Vec::new();
+        robot.record_csv(&mut csv, 120, 0.01).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("time,x,y,theta,wl,wr,sdf_error,p_term,i_term,d_term")
+        );
+        let rows = lines
+            .map(|line| {
+                line.split(',')
+                    .map(|value| value.parse::<f64>().unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), 120);
+        assert!(rows.iter().all(|row| row.len() == 10));
+        let last = rows.last().unwrap();
+        assert_eq!(last[0], robot.get_time());
+        assert_eq!(last[1], robot.robot_position().x);
+        assert_eq!(last[6], robot.robot_sdf_to_path());
+        assert_eq!(last[7], robot.get_proportional_term());
+        // recording again continues the run, with a header of its own
+        let mut more = Vec::new();
+        robot.record_csv(&mut more, 10, 0.01).unwrap();
+        assert_eq!(String::from_utf8(more).unwrap().lines().count(), 11);
+    }
+
+    #[test]
+    fn test_yaw_rate_from_wheel_speeds() {
+        let with_wheels = |wl: f64, wr: f64| {
+            let x0 = Vector::<NUM_STATES>::from_column_slice(&[4.0, -4.0, 0.0, wl, 0.0, wr, 0.0]);
+            RobotSimulation::new(x0, 1.0, 0.0, 0.0, 0.0, Arc::new(predefined_closed_path()))
+        };
+        assert_eq!(with_wheels(5.0, 5.0).yaw_rate(), 0.0);
+        let robot = with_wheels(4.0, 6.0);
+        let expected = ROBOT_WHEEL_RADIUS * 2.0 / ROBOT_SIDE_LENGTH;
+        assert!((robot.yaw_rate() - expected).abs() < 1e-12);
+        assert!((with_wheels(6.0, 4.0).yaw_rate() + expected).abs() < 1e-12);
+        // it matches the turn rate used by the dynamics
+        let d_theta = RobotSimulation::robot_dynamics(
+            &robot.get_state(),
+            &Vector::<NUM_CONTROLS>::zeros(),
+            &RobotParams::default(),
+            None,
+        )[2];
+        assert_eq!(robot.yaw_rate(), d_theta);
+    }
+
+    #[test]
+    fn test_wheel_radius_scales_the_forward_speed() {
+        let with_radius = |wheel_radius: f64| {
+            let params = RobotParams {
+                wheel_radius,
+                ..Default::default()
+            };
+            let mut robot = robot_at(0.0, -4.0, 0.0).with_params(params);
+            robot.state[3] = 10.0;
+            robot.state[5] = 10.0;
+            let dx = RobotSimulation::robot_dynamics(
+                &robot.state,
+                &Vector::<NUM_CONTROLS>::zeros(),
+                &robot.get_params(),
+                None,
+            )[0];
+            (robot.forward_speed(), dx)
+        };
+        let (speed, dx) = with_radius(ROBOT_WHEEL_RADIUS);
+        assert!((speed - 10.0 * ROBOT_WHEEL_RADIUS).abs() < 1e-12);
+        assert_eq!(dx, speed);
+        let (doubled_speed, doubled_dx) = with_radius(2.0 * ROBOT_WHEEL_RADIUS);
+        assert!((doubled_speed - 2.0 * speed).abs() < 1e-12);
+        assert!((doubled_dx - 2.0 * dx).abs() < 1e-12);
+
+        // the controller accounts for the bigger wheels, so the robot still goes at `speed`
+        let params = RobotParams {
+            wheel_radius: 2.0 * ROBOT_WHEEL_RADIUS,
+            ..Default::default()
+        };
+        let x0 = Vector::<NUM_STATES>::from_column_slice(&[0.5, -4.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        let path = Arc::new(predefined_closed_path());
+        let mut robot = RobotSimulation::new(x0, 0.0, 0.0, 0.0, 0.5, path).with_params(params);
+        for _ in 0..(2 * 240) {
+            robot.step(1.0 / 240.0);
+        }
+        assert!((robot.forward_speed() - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_suggested_kp_grows_with_bandwidth() {
+        let x0 = Vector::<NUM_STATES>::from_column_slice(&[0.0, -4.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        let robot =
+            RobotSimulation::new(x0, 0.0, 0.0, 0.0, 0.5, Arc::new(predefined_closed_path()));
+        let bandwidths = [0.1, 0.5, 1.0, 2.0];
+        let gains = bandwidths.map(|f| robot.suggest_kp(f));
+        for pair in gains.windows(2) {
+            assert!(pair[1] > pair[0]);
+        }
+        // e'' = -speed * kp * e oscillates at the requested frequency
+        let w = (robot.speed * gains[2]).sqrt();
+        assert!((w / (2.0 * PI) - bandwidths[2]).abs() < 1e-12);
+
+        // in steady state, the wheels of any robot turn it at the commanded rate, so the
+        // suggestion doesn't depend on the geometry
+        let params = RobotParams {
+            wheel_radius: 2.0 * ROBOT_WHEEL_RADIUS,
+            side_length: 0.5 * ROBOT_SIDE_LENGTH,
+            ..RobotParams::default()
+        };
+        let other = robot.clone().with_params(params);
+        assert_eq!(other.suggest_kp(bandwidths[2]), gains[2]);
+        for robot in [&robot, &other] {
+            let u = robot.wheel_commands(robot.speed, 0.8);
+            // the motors settle at u / C2, where their acceleration in `robot_dynamics` vanishes
+            let (speed, yaw_rate) = robot.params.body_velocity(u[0] / C2, u[1] / C2);
+            assert!((speed - robot.speed).abs() < 1e-12);
+            assert!((yaw_rate - 0.8).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "positive speed")]
+    fn test_suggested_kp_needs_a_positive_speed() {
+        // at rest the gain would be infinite, and backwards it would be negative
+        robot_at(0.0, -4.0, 0.0).suggest_kp(1.0);
+    }
+
+    #[test]
+    fn test_feedforward_controls_follow_curvature() {
+        let mut robot = robot_at(4.0, -4.0, 0.0);
+        robot.speed = 0.5;
+        let straight = robot.feedforward_controls();
+        assert!(straight[0] > 0.0);
+        assert_eq!(straight[0], straight[1]);
+
+        // halfway along the counterclockwise arc of radius 2 around (8, -2)
+        let offset = 2.0 * (PI / 4.0).cos();
+        let mut robot = robot_at(8.0 + offset, -2.0 + offset, 3.0 * PI / 4.0);
+        robot.speed = 0.5;
+        let arc = robot.feedforward_controls();
+        let k = ROBOT_SIDE_LENGTH * C2 / ROBOT_WHEEL_RADIUS;
+        let expected = k * robot.speed / 2.0;
+        assert!((arc[1] - arc[0] - expected).abs() < 1e-9, "{:?}", arc);
+        assert!((arc[0] + arc[1] - straight[0] - straight[1]).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_curvature_feedforward_reduces_the_error_on_the_arcs() {
+        use crate::geometry::closed_path::SubPath;
+        let path = predefined_closed_path();
+        let mut arcs = Vec::new();
+        let mut start = 0.0;
+        for subpath in path.subpaths() {
+            if let SubPath::Arc(_) = subpath {
+                arcs.push((start, start + subpath.length()));
+            }
+            start += subpath.length();
+        }
+        let peak_error_on_arcs = |kff: f64| {
+            let mut robot = robot_at(0.0, -4.0, 0.0);
+            robot.kp = 3.0;
+            robot.kd = 1.0;
+            robot.speed = 1.0;
+            robot.kff = kff;
+            let mut peak_error = 0.0f64;
+            while robot.get_completed_laps() < 1 {
+                robot.step(1.0 / 240.0);
+                assert!(robot.get_time() < 100.0, "kff = {}", kff);
+                let d = path.point_projection_distance(robot.robot_position());
+                if arcs.iter().any(|&(start, end)| start <= d && d <= end) {
+                    peak_error = peak_error.max(robot.robot_sdf_to_path().abs());
+                }
+            }
+            peak_error
+        };
+        let feedback_only = peak_error_on_arcs(0.0);
+        let with_feedforward = peak_error_on_arcs(1.0);
+        assert!(
+            with_feedforward < 0.5 * feedback_only,
+            "{} vs {}",
+            with_feedforward,
+            feedback_only
+        );
+    }
+
+    #[test]
+    fn test_stanley_and_pid_settle_on_a_straight_line() {
+        use crate::geometry::closed_path::SubPath;
+        use crate::geometry::line_path::LinePath;
+        // a long and narrow rectangle, starting on the bottom side
+        let corners = [(0.0, 0.0), (20.0, 0.0), (20.0, 2.0), (0.0, 2.0)];
+        let sides = (0..4)
+            .map(|i| {
+                let ((x0, y0), (x1, y1)) = (corners[i], corners[(i + 1) % 4]);
+                SubPath::Line(LinePath::new(Point2::new(x0, y0), Point2::new(x1, y1)))
+            })
+            .collect();
+        let path = Arc::new(ClosedPath::new(sides));
+        let x0 = Vector::<NUM_STATES>::from_column_slice(&[1.0, -0.05, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        let dt = 1.0 / 240.0;
+        // time after which the robot stays within 5 mm, and the largest error over the last 3 s
+        let settle = |strategy| {
+            let mut robot = RobotSimulation::new(x0, 20.0, 0.0, 10.0, 0.5, path.clone());
+            robot.set_control_strategy(strategy);
+            let (mut settling_time, mut steady_error) = (0.0, 0.0f64);
+            for k in 0..(20 * 240) {
+                robot.step(dt);
+                let error = robot.robot_sdf_to_path().abs();
+                if error > 0.005 {
+                    settling_time = robot.get_time();
+                }
+                if k >= 17 * 240 {
+                    steady_error = steady_error.max(error);
+                }
+            }
+            (robot, settling_time, steady_error)
+        };
+        let (_, pid_settling_time, pid_error) = settle(ControlStrategy::Pid);
+        let (stanley, stanley_settling_time, stanley_error) = settle(ControlStrategy::Stanley);
+        // neither leaves an offset on a straight line
+        assert!(
+            pid_error < 1e-9 && stanley_error < 1e-9,
+            "{} {}",
+            pid_error,
+            stanley_error
+        );
+        assert!(stanley.heading_error().abs() < 1e-9);
+        assert!(stanley.robot_position().x > 10.0);
+        // with these gains, Stanley's separate heading term brings it in sooner
+        assert!(
+            stanley_settling_time < pid_settling_time && pid_settling_time < 2.0,
+            "{} {}",
+            stanley_settling_time,
+            pid_settling_time
+        );
+        assert_eq!(stanley.get_proportional_term(), 0.0);
+    }
+
+    #[test]
+    fn test_pure_pursuit_steers_steadily_on_the_arcs() {
+        use crate::geometry::closed_path::SubPath;
+        let path = Arc::new(predefined_closed_path());
+        let x0 = Vector::<NUM_STATES>::from_column_slice(&[0.0, -4.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        let mut robot = RobotSimulation::new(x0, 0.0, 0.0, 0.0, 1.0, path.clone());
+        robot.set_control_strategy(ControlStrategy::PurePursuit);
+        // arc-length span and signed curvature of each arc, with the curvatures steered on it
+        let mut arcs = Vec::new();
+        let mut start = 0.0;
+        for subpath in path.subpaths() {
+            if let SubPath::Arc(arc) = subpath {
+                let curvature = subpath.curvature_at(0.0);
+                assert_eq!(curvature.abs(), 1.0 / arc.r);
+                arcs.push((start, start + subpath.length(), curvature, Vec::new()));
+            }
+            start += subpath.length();
+        }
+        let mut max_abs_error = 0.0f64;
+        while robot.get_completed_laps() < 1 {
+            robot.step(1.0 / 240.0);
+            assert!(robot.get_time() < 100.0);
+            max_abs_error = max_abs_error.max(robot.robot_sdf_to_path().abs());
+            let d = path.point_projection_distance(robot.robot_position());
+            // once the robot has settled on the arc, and while the goal point is still on it
+            for (start, end, _, steered) in arcs.iter_mut() {
+                if d > *start + 0.5 && d < *end - robot.lookahead - 0.1 {
+                    steered.push(robot.pure_pursuit_curvature());
+                }
+            }
+        }
+        assert!(max_abs_error < 0.05, "{}", max_abs_error);
+        for (start, _, curvature, steered) in arcs {
+            assert!(!steered.is_empty());
+            // on a circle, the goal point is reached by following the circle itself
+            for k in steered {
+                let relative_error = (k - curvature) / curvature;
+                assert!(relative_error.abs() < 0.03, "arc at {}: {}", start, k);
+            }
+        }
+        assert_eq!(robot.get_proportional_term(), 0.0);
+    }
 }
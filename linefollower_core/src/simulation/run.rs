@@ -0,0 +1,457 @@
+use nalgebra::Point2;
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::track::SharedTrack;
+use crate::ode_solver::ode_system::Vector;
+use crate::utils::math::menger_curvature;
+
+use super::events::{SimEvent, SimEventKind};
+use super::robot::{ControllerConfig, RobotSimulation};
+
+/// Time between the trajectory samples stored in a `RunResult`, in seconds
+pub const TRAJECTORY_SAMPLE_PERIOD: f64 = 0.05;
+
+/// State of the robot at some instant of a run
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TrajectorySample {
+    pub time: f64,
+    pub x: f64,
+    pub y: f64,
+    /// Unwrapped arc-length traveled along the path
+    pub progress: f64,
+    /// Signed distance to the path
+    pub error: f64,
+}
+
+impl TrajectorySample {
+    fn of(robot_sim: &RobotSimulation) -> Self {
+        let position = robot_sim.robot_position();
+        Self {
+            time: robot_sim.get_time(),
+            x: position.x,
+            y: position.y,
+            progress: robot_sim.get_progress(),
+            error: robot_sim.robot_sdf_to_path(),
+        }
+    }
+}
+
+/// Summary statistics of a run
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RunStats {
+    /// Simulated time, in seconds
+    pub duration: f64,
+    /// Unwrapped arc-length traveled along the path
+    pub progress: f64,
+    /// Length of the robot's actual trajectory
+    pub distance_traveled: f64,
+    pub completed_laps: usize,
+    /// Root mean square of the distance to the path
+    pub rms_error: f64,
+    /// Largest absolute distance to the path
+    pub max_abs_error: f64,
+    /// Average absolute curvature of the trajectory, to compare with the path's.
+    /// Zero for results saved before it was recorded
+    #[serde(default)]
+    pub mean_abs_trajectory_curvature: f64,
+    /// How many times the trajectory samples cross from one side of the path to the other.
+    /// Zero for results saved before it was recorded
+    #[serde(default)]
+    pub line_crossings: usize,
+    /// Whether the integration stopped because the state became non-finite
+    pub diverged: bool,
+}
+
+/// Everything about a run needed to archive it or compare it against other runs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunResult {
+    pub config: ControllerConfig,
+    pub stats: RunStats,
+    pub events: Vec<SimEvent>,
+    /// Duration of each completed lap, in seconds
+    pub lap_times: Vec<f64>,
+    /// Sampled every `TRAJECTORY_SAMPLE_PERIOD`, starting at the initial state.
+    /// Empty for results saved before it was recorded
+    #[serde(default)]
+    pub trajectory: Vec<TrajectorySample>,
+}
+
+impl RunResult {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    /// Where the trajectory crossed the path, see `line_crossings`
+    pub fn line_crossings(&self) -> Vec<LineCrossing> {
+        line_crossings(&self.trajectory)
+    }
+
+    /// Number of line crossings during each completed lap, which quantifies how much the robot
+    /// weaves around the path. The crossings after the last completed lap aren't counted
+    pub fn crossings_per_lap(&self) -> Vec<usize> {
+        let crossings = self.line_crossings();
+        let mut lap_start = 0.0;
+        self.lap_times
+            .iter()
+            .map(|lap_time| {
+                let lap_end = lap_start + lap_time;
+                let count = crossings
+                    .iter()
+                    .filter(|c| lap_start <= c.time && c.time < lap_end)
+                    .count();
+                lap_start = lap_end;
+                count
+            })
+            .collect()
+    }
+}
+
+/// Runs a simulation without any rendering for `duration` seconds with a step of `dt`,
+/// stopping early if the state diverges.
+pub fn run_headless(
+    x0: Vector<7>,
+    config: &ControllerConfig,
+    path: SharedTrack,
+    dt: f64,
+    duration: f64,
+) -> RunResult {
+    let mut robot_sim = RobotSimulation::from_config(x0, config, path);
+    let steps = (duration / dt).round() as usize;
+    let (mut squared_error, mut max_abs_error) = (0.0, 0.0f64);
+    let mut samples = 0;
+    let sample_every = ((TRAJECTORY_SAMPLE_PERIOD / dt).round() as usize).max(1);
+    let mut trajectory = vec![TrajectorySample::of(&robot_sim)];
+    for k in 0..steps {
+        robot_sim.step(dt);
+        if robot_sim.is_diverged() {
+            break;
+        }
+        let error = robot_sim.robot_sdf_to_path();
+        squared_error += error * error;
+        max_abs_error = max_abs_error.max(error.abs());
+        samples += 1;
+        if (k + 1) % sample_every == 0 {
+            trajectory.push(TrajectorySample::of(&robot_sim));
+        }
+    }
+
+    let events = robot_sim.get_events().to_vec();
+    let lap_times = events
+        .iter()
+        .filter(|e| e.kind == SimEventKind::LapCompleted)
+        .scan(0.0, |lap_start, e| {
+            let lap_time = e.time - *lap_start;
+            *lap_start = e.time;
+            Some(lap_time)
+        })
+        .collect();
+    let curvatures = trajectory_curvature(&trajectory);
+    let mean_abs_trajectory_curvature =
+        curvatures.iter().map(|k| k.abs()).sum::<f64>() / curvatures.len().max(1) as f64;
+    let line_crossings = line_crossings(&trajectory).len();
+    RunResult {
+        config: *config,
+        stats: RunStats {
+            duration: robot_sim.get_time(),
+            progress: robot_sim.get_progress(),
+            distance_traveled: robot_sim.distance_traveled(),
+            completed_laps: robot_sim.get_completed_laps(),
+            rms_error: (squared_error / samples.max(1) as f64).sqrt(),
+            max_abs_error,
+            mean_abs_trajectory_curvature,
+            line_crossings,
+            diverged: robot_sim.is_diverged(),
+        },
+        events,
+        lap_times,
+        trajectory,
+    }
+}
+
+/// Signed curvature of the trajectory actually traced by the robot, positive when turning left,
+/// at each sample but the first and the last. It is estimated from the circle through each
+/// sample and its neighbours; where the robot stood still, it is taken as zero.
+pub fn trajectory_curvature(trajectory: &[TrajectorySample]) -> Vec<f64> {
+    trajectory
+        .windows(3)
+        .map(|w| {
+            let [a, b, c] = [&w[0], &w[1], &w[2]].map(|s| Point2::new(s.x, s.y));
+            let curvature = menger_curvature(a, b, c);
+            if curvature.is_finite() {
+                curvature
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+/// Instant and spot of the path where the robot crossed it, going from one side to the other
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LineCrossing {
+    pub time: f64,
+    /// Unwrapped arc-length along the path
+    pub progress: f64,
+}
+
+/// Every point where the signed distance to the path changes sign between two samples of the
+/// trajectory, linearly interpolated to where it is zero. Samples right on the path don't count
+/// as a side, so touching the path without crossing it isn't a crossing. Crossings back and
+/// forth between two samples are missed.
+pub fn line_crossings(trajectory: &[TrajectorySample]) -> Vec<LineCrossing> {
+    let mut crossings = Vec::new();
+    // last sample off the path
+    let mut previous: Option<&TrajectorySample> = None;
+    for sample in trajectory.iter().filter(|s| s.error != 0.0) {
+        if let Some(p) = previous {
+            if p.error.signum() != sample.error.signum() {
+                let t = p.error / (p.error - sample.error);
+                crossings.push(LineCrossing {
+                    time: p.time + t * (sample.time - p.time),
+                    progress: p.progress + t * (sample.progress - p.progress),
+                });
+            }
+        }
+        previous = Some(sample);
+    }
+    crossings
+}
+
+/// Lateral errors of two runs at the same progress along the path
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlignedErrors {
+    pub progress: f64,
+    pub error_a: f64,
+    pub error_b: f64,
+}
+
+impl AlignedErrors {
+    /// How much farther from the path run `b` is than run `a`, with sign
+    pub fn difference(&self) -> f64 {
+        self.error_b - self.error_a
+    }
+}
+
+/// Aligns two trajectories by their arc-length progress rather than by time, so that runs at
+/// different speeds can be compared at the same spot of the track. The errors are linearly
+/// interpolated every `spacing` meters over the progress covered by both runs.
+/// Returns an empty vector if either trajectory has less than two samples.
+pub fn align_by_progress(
+    a: &[TrajectorySample],
+    b: &[TrajectorySample],
+    spacing: f64,
+) -> Vec<AlignedErrors> {
+    if a.len() < 2 || b.len() < 2 {
+        return Vec::new();
+    }
+    let max_progress =
+        |t: &[TrajectorySample]| t.iter().map(|s| s.progress).fold(f64::MIN, f64::max);
+    let start = a[0].progress.max(b[0].progress);
+    let end = max_progress(a).min(max_progress(b));
+    if end < start {
+        return Vec::new();
+    }
+    let n = ((end - start) / spacing).floor() as usize;
+    let progresses = (0..=n)
+        .map(|i| start + i as f64 * spacing)
+        .collect::<Vec<_>>();
+    let errors_a = errors_at_progress(a, &progresses);
+    let errors_b = errors_at_progress(b, &progresses);
+    progresses
+        .into_iter()
+        .zip(errors_a.into_iter().zip(errors_b))
+        .map(|(progress, (error_a, error_b))| AlignedErrors {
+            progress,
+            error_a,
+            error_b,
+        })
+        .collect()
+}
+
+/// Interpolates the error where the trajectory first reaches each of the increasing `progresses`
+fn errors_at_progress(trajectory: &[TrajectorySample], progresses: &[f64]) -> Vec<f64> {
+    let mut j = 0;
+    progresses
+        .iter()
+        .map(|&s| {
+            // if the robot backs up a little, only the first time it passes s counts
+            while j + 2 < trajectory.len() && trajectory[j + 1].progress < s {
+                j += 1;
+            }
+            let (p, q) = (&trajectory[j], &trajectory[j + 1]);
+            let span = q.progress - p.progress;
+            let t = if span > 0.0 {
+                ((s - p.progress) / span).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            p.error + t * (q.error - p.error)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::closed_path::{predefined_circle, predefined_closed_path};
+    use crate::geometry::track::Track;
+    use crate::simulation::robot::OPTIMIZED_CONFIG;
+    use std::f64::consts::PI;
+    use std::sync::Arc;
+
+    fn optimized_run(duration: f64) -> RunResult {
+        run_with(&OPTIMIZED_CONFIG, duration)
+    }
+
+    fn run_with(config: &ControllerConfig, duration: f64) -> RunResult {
+        let x0 = Vector::<7>::from_column_slice(&[0.0, -4.0, 0.1, 0.0, 0.0, 0.0, 0.0]);
+        let path = Arc::new(predefined_closed_path());
+        run_headless(x0, config, path, 1.0 / 240.0, duration)
+    }
+
+    #[test]
+    fn test_completed_run_populates_result() {
+        let length = predefined_closed_path().length();
+        let result = optimized_run(75.0);
+        let stats = result.stats;
+        assert!(!stats.diverged);
+        assert!((stats.duration - 75.0).abs() < 1e-6);
+        assert!(stats.completed_laps >= 2);
+        assert_eq!(result.lap_times.len(), stats.completed_laps);
+        assert!(stats.progress >= stats.completed_laps as f64 * length);
+        assert!(stats.distance_traveled > 0.9 * stats.progress);
+        let total_lap_time: f64 = result.lap_times.iter().sum();
+        assert!(total_lap_time > 0.0 && total_lap_time <= stats.duration);
+        assert!(stats.rms_error > 0.0 && stats.rms_error <= stats.max_abs_error);
+        assert_eq!(result.config, OPTIMIZED_CONFIG);
+        let expected_samples = (75.0 / TRAJECTORY_SAMPLE_PERIOD).round() as usize + 1;
+        assert_eq!(result.trajectory.len(), expected_samples);
+        assert_eq!(result.trajectory[0].time, 0.0);
+        assert_eq!(result.trajectory.last().unwrap().progress, stats.progress);
+        assert_eq!(result.line_crossings().len(), stats.line_crossings);
+        assert_eq!(result.crossings_per_lap().len(), stats.completed_laps);
+        assert!(result.crossings_per_lap().iter().sum::<usize>() <= stats.line_crossings);
+    }
+
+    #[test]
+    fn test_line_crossings() {
+        let sample = |time: f64, error: f64| TrajectorySample {
+            time,
+            x: time,
+            y: error,
+            progress: 2.0 * time,
+            error,
+        };
+        // weaving around the path with a period of 2 s
+        let weaving = (0..=100)
+            .map(|k| {
+                let time = k as f64 * 0.05;
+                sample(time, 0.1 * f64::sin(PI * (time - 0.25)))
+            })
+            .collect::<Vec<_>>();
+        let crossings = line_crossings(&weaving);
+        assert_eq!(crossings.len(), 5);
+        for (k, crossing) in crossings.iter().enumerate() {
+            let time = 0.25 + k as f64;
+            assert!((crossing.time - time).abs() < 1e-3, "{:?}", crossing);
+            assert!(
+                (crossing.progress - 2.0 * time).abs() < 2e-3,
+                "{:?}",
+                crossing
+            );
+        }
+
+        // cleanly offset to one side, even touching the path, is never a crossing
+        let offset = (0..=100)
+            .map(|k| sample(k as f64 * 0.05, 0.05 + 0.05 * f64::cos(k as f64 * 0.1)))
+            .collect::<Vec<_>>();
+        assert!(offset.iter().any(|s| s.error.abs() < 1e-3));
+        assert!(line_crossings(&offset).is_empty());
+        let touching = [sample(0.0, -0.1), sample(0.1, 0.0), sample(0.2, -0.1)];
+        assert!(line_crossings(&touching).is_empty());
+        let through = [sample(0.0, -0.1), sample(0.1, 0.0), sample(0.2, 0.1)];
+        assert_eq!(
+            line_crossings(&through),
+            vec![LineCrossing {
+                time: 0.1,
+                progress: 0.2
+            }]
+        );
+    }
+
+    #[test]
+    fn test_align_runs_by_progress() {
+        let a = optimized_run(30.0);
+        let itself = align_by_progress(&a.trajectory, &a.trajectory, 0.1);
+        assert!(!itself.is_empty());
+        assert!(itself.iter().all(|e| e.difference().abs() < 1e-12));
+
+        // a slower run covers less of the track, so only the common stretch is compared
+        let slower = ControllerConfig {
+            speed: OPTIMIZED_CONFIG.speed / 2.0,
+            ..OPTIMIZED_CONFIG
+        };
+        let b = run_with(&slower, 30.0);
+        let aligned = align_by_progress(&a.trajectory, &b.trajectory, 0.1);
+        let last = aligned.last().unwrap();
+        let b_max_progress = b.trajectory.iter().map(|s| s.progress).fold(0.0, f64::max);
+        assert!(b_max_progress < a.stats.progress);
+        assert!(last.progress <= b_max_progress);
+        assert!(b_max_progress - last.progress < 0.1);
+        assert!(aligned.windows(2).all(|w| w[1].progress > w[0].progress));
+        assert!(aligned.iter().any(|e| e.difference().abs() > 1e-6));
+        for e in aligned.iter() {
+            assert!(e.error_a.abs() <= a.stats.max_abs_error + 1e-12);
+            assert!(e.error_b.abs() <= b.stats.max_abs_error + 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_trajectory_curvature_on_a_circle() {
+        let radius = 2.0;
+        let path = Arc::new(predefined_circle(radius));
+        let p0 = path.first_point();
+        let x0 = Vector::<7>::from_column_slice(&[p0.x, p0.y, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        let config = ControllerConfig {
+            kp: 20.0,
+            ki: 0.0,
+            kd: 10.0,
+            speed: 0.5,
+        };
+        let result = run_headless(x0, &config, path, 1.0 / 240.0, 30.0);
+        assert!(!result.stats.diverged);
+        let curvatures = trajectory_curvature(&result.trajectory);
+        assert_eq!(curvatures.len(), result.trajectory.len() - 2);
+        // once the transient from the straight start is over
+        let steady = &curvatures[curvatures.len() / 2..];
+        let mean = steady.iter().sum::<f64>() / steady.len() as f64;
+        assert!((mean - 1.0 / radius).abs() < 0.02 / radius, "{}", mean);
+        assert!(steady
+            .iter()
+            .all(|k| (k - 1.0 / radius).abs() < 0.1 / radius));
+        assert!((result.stats.mean_abs_trajectory_curvature - 1.0 / radius).abs() < 0.1 / radius);
+
+        // a straight run has no curvature, and neither does standing still
+        let straight = |x: f64| TrajectorySample {
+            time: x,
+            x,
+            y: 1.0,
+            progress: x,
+            error: 0.0,
+        };
+        let line = [straight(0.0), straight(0.5), straight(1.0), straight(1.0)];
+        assert_eq!(trajectory_curvature(&line), vec![0.0, 0.0]);
+        assert!(trajectory_curvature(&line[..2]).is_empty());
+    }
+
+    #[test]
+    fn test_run_result_json_round_trip() {
+        let result = optimized_run(40.0);
+        let json = result.to_json().unwrap();
+        assert_eq!(RunResult::from_json(&json).unwrap(), result);
+    }
+}
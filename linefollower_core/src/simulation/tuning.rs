@@ -0,0 +1,100 @@
+use super::robot::{ControllerConfig, Relay, RobotSimulation};
+
+/// Integration time step of the tuning runs
+const DT: f64 = 1.0 / 240.0;
+
+/// Relay feedback auto-tuning (Åström–Hägglund).
+///
+/// Runs `robot_sim` for `duration` seconds with `relay` in place of the PID, which makes
+/// the relay's input `e + lead * de/dt` oscillate at the frequency where the loop has a phase
+/// lag of 180 degrees. The second half of the run is used to measure the period `tu` and
+/// amplitude `a` of the oscillation, giving the ultimate gain `ku = 4 * amplitude / (pi * a)`.
+/// The classic Ziegler–Nichols rules then give `kp = 0.6 ku`, `ki = 1.2 ku / tu`, and a
+/// derivative time of `tu / 8` which adds up with the relay's lead: `kd = kp (lead + tu / 8)`.
+///
+/// The PID of `robot_sim` is restored afterwards, and the simulation reset to its initial
+/// state, so that the tuned controller can run from the start. Returns `None` if no sustained
+/// oscillation was observed.
+pub fn relay_autotune(
+    robot_sim: &mut RobotSimulation,
+    relay: Relay,
+    duration: f64,
+) -> Option<ControllerConfig> {
+    robot_sim.set_relay(Some(relay));
+    let steps = (duration / DT).round() as usize;
+    let mut upward_crossings = Vec::new();
+    let (mut min_input, mut max_input) = (f64::INFINITY, f64::NEG_INFINITY);
+    let mut prev_error = robot_sim.robot_sdf_to_path();
+    let mut prev_input = prev_error;
+    for i in 0..steps {
+        robot_sim.step(DT);
+        let error = robot_sim.robot_sdf_to_path();
+        let input = error + relay.lead * (error - prev_error) / DT;
+        // let the robot settle into the limit cycle first
+        if i >= steps / 2 {
+            min_input = min_input.min(input);
+            max_input = max_input.max(input);
+            if prev_input < 0.0 && input >= 0.0 {
+                // interpolate the time at which the input crossed zero
+                let fraction = prev_input / (prev_input - input);
+                upward_crossings.push(robot_sim.get_time() - DT * (1.0 - fraction));
+            }
+        }
+        prev_error = error;
+        prev_input = input;
+    }
+    robot_sim.set_relay(None);
+    let diverged = robot_sim.is_diverged();
+    robot_sim.reset();
+
+    if upward_crossings.len() < 3 || diverged {
+        return None;
+    }
+    let cycles = (upward_crossings.len() - 1) as f64;
+    let tu = (upward_crossings.last().unwrap() - upward_crossings[0]) / cycles;
+    let a = (max_input - min_input) / 2.0;
+    let ku = 4.0 * relay.amplitude / (std::f64::consts::PI * a);
+    let kp = 0.6 * ku;
+    Some(ControllerConfig {
+        kp,
+        ki: 1.2 * ku / tu,
+        kd: kp * (relay.lead + tu / 8.0),
+        speed: robot_sim.speed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::closed_path::predefined_closed_path;
+    use crate::geometry::track::Track;
+    use crate::ode_solver::ode_system::Vector;
+    use std::sync::Arc;
+
+    const RELAY: Relay = Relay {
+        amplitude: 2.0,
+        lead: 0.5,
+    };
+
+    #[test]
+    fn test_relay_autotune_gives_positive_gains() {
+        let path = Arc::new(predefined_closed_path());
+        let p0 = path.first_point();
+        let x0 = Vector::<7>::from_column_slice(&[p0.x, p0.y, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        let mut robot_sim = RobotSimulation::new(x0, 0.0, 0.0, 0.0, 0.5, path.clone());
+        let config = relay_autotune(&mut robot_sim, RELAY, 10.0).expect("the relay oscillates");
+        for gain in [config.kp, config.ki, config.kd] {
+            assert!(gain.is_finite() && gain > 0.0, "{:?}", config);
+        }
+        assert_eq!(config.speed, 0.5);
+        assert_eq!(robot_sim.get_time(), 0.0);
+        assert_eq!(robot_sim.get_state(), x0);
+
+        // the tuned gains are good enough to go around the track
+        let mut robot_sim = RobotSimulation::from_config(x0, &config, path);
+        while robot_sim.get_completed_laps() == 0 && robot_sim.get_time() < 150.0 {
+            robot_sim.step(DT);
+        }
+        assert_eq!(robot_sim.get_completed_laps(), 1);
+    }
+}
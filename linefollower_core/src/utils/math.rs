@@ -1,12 +1,18 @@
-use nalgebra::Vector2;
+use nalgebra::{Point2, Vector2};
 
 use crate::utils::traits::Float;
 
+/// Relative tolerance on `end`, so that an endpoint which is a whole number of steps
+/// away from `start` isn't dropped because of rounding errors
+const FLOAT_RANGE_TOLERANCE: f64 = 1e-9;
+
+/// Values `start`, `start + step`, ... up to and including `end`
 pub struct FloatRange<F: Float> {
     pub start: F,
     pub end: F,
     pub step: F,
-    curr: F,
+    /// Index of the next value, which is computed from `start` rather than accumulated
+    index: usize,
 }
 
 impl<F> FloatRange<F>
@@ -18,7 +24,7 @@ where
             start,
             end,
             step,
-            curr: start,
+            index: 0,
         }
     }
 
@@ -33,15 +39,18 @@ where
 {
     type Item = F;
     fn next(&mut self) -> Option<Self::Item> {
-        self.curr += self.step;
-        if self.curr > self.end {
+        let curr = self.start + F::from_usize(self.index).unwrap() * self.step;
+        let tolerance = self.step * F::from(FLOAT_RANGE_TOLERANCE).unwrap();
+        if curr > self.end + tolerance {
             None
         } else {
-            Some(self.curr)
+            self.index += 1;
+            Some(curr)
         }
     }
 }
 
+/// Points `x_0 + k dx` covering [`x_min`, `x_max`], including the closest ones on either side
 #[inline(always)]
 pub fn lattice_points<F: Float>(x_0: F, x_min: F, x_max: F, dx: F) -> FloatRange<F> {
     let y_min = x_0 + num::Float::floor((x_min - x_0) / dx) * dx;
@@ -54,6 +63,21 @@ pub fn distance<F: Float>(x_0: F, y_0: F, x_1: F, y_1: F) -> F {
     num::Float::sqrt(num::Float::powi(x_0 - x_1, 2) + num::Float::powi(y_0 - y_1, 2))
 }
 
+/// Distance between two points, which unlike `nalgebra::distance` doesn't overflow when the
+/// coordinates are beyond the square root of the largest float. Distances which aren't
+/// representable at all saturate at the largest float
+#[inline(always)]
+pub fn point_distance<F: Float>(a: &Point2<F>, b: &Point2<F>) -> F {
+    let d = a - b;
+    let distance = num::Float::hypot(d.x, d.y);
+    // unlike min, this keeps a NaN from going unnoticed
+    if distance > F::max_value() {
+        F::max_value()
+    } else {
+        distance
+    }
+}
+
 #[inline(always)]
 pub fn dot_product<F: Float>(x_0: F, y_0: F, x_1: F, y_1: F) -> F {
     x_0 * x_1 + y_0 * y_1
@@ -71,6 +95,14 @@ pub fn sigmoid<F: Float>(x: F) -> F {
     F::one() / (F::one() + num::Float::exp(-x))
 }
 
+/// Wraps an angle (in radians) into the range [-pi, pi]
+#[inline(always)]
+pub fn wrap_angle<F: Float>(theta: F) -> F {
+    let pi = F::from(std::f64::consts::PI).unwrap();
+    let two_pi = pi + pi;
+    theta - two_pi * num::Float::floor((theta + pi) / two_pi)
+}
+
 #[inline(always)]
 pub fn cross<F>(a: &Vector2<F>, b: &Vector2<F>) -> F
 where
@@ -78,3 +110,51 @@ where
 {
     a.x * b.y - a.y * b.x
 }
+
+/// Signed curvature of the circle through three points, positive when turning left
+#[inline(always)]
+pub fn menger_curvature<F>(a: Point2<F>, b: Point2<F>, c: Point2<F>) -> F
+where
+    F: Float,
+{
+    let two = F::from(2.0).unwrap();
+    let (ab, bc) = (b - a, c - b);
+    two * cross(&ab, &bc) / (ab.norm() * bc.norm() * (c - a).norm())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_float_range_includes_both_endpoints() {
+        let values: Vec<f64> = FloatRange::new(0.0, 1.0, 0.25).collect();
+        assert_eq!(values, vec![0.0, 0.25, 0.5, 0.75, 1.0]);
+        // an end which isn't a whole number of steps away isn't reached
+        let values: Vec<f64> = FloatRange::new(-1.0, 0.5, 0.5).collect();
+        assert_eq!(values, vec![-1.0, -0.5, 0.0, 0.5]);
+        let values: Vec<f64> = FloatRange::new(0.0, 0.9, 0.5).collect();
+        assert_eq!(values, vec![0.0, 0.5]);
+        // 0.1 isn't exact in binary, but the last value isn't lost to rounding
+        let values: Vec<f64> = FloatRange::new(0.0, 0.3, 0.1).collect();
+        assert_eq!(values.len(), 4);
+        assert_eq!(values[0], 0.0);
+        assert!((values[3] - 0.3).abs() < 1e-12);
+        assert_eq!(
+            FloatRange::new(2.0, 2.0, 1.0).collect::<Vec<f64>>(),
+            vec![2.0]
+        );
+        assert!(FloatRange::new(1.0, 0.0, 1.0).next().is_none());
+    }
+
+    #[test]
+    fn test_lattice_points_cover_the_bounds() {
+        let values: Vec<f64> = lattice_points(0.0, -0.25, 0.3, 0.1).collect();
+        assert_eq!(values.len(), 7);
+        assert!((values[0] + 0.3).abs() < 1e-12);
+        assert!((values[6] - 0.3).abs() < 1e-12);
+        // the lattice goes through `x_0`, here off the origin by half a step, not through 0
+        let values: Vec<f64> = lattice_points(0.25, 0.0, 1.0, 0.5).collect();
+        assert_eq!(values, vec![-0.25, 0.25, 0.75, 1.25]);
+    }
+}
@@ -0,0 +1,157 @@
+use macroquad::prelude::*;
+
+use linefollower_core::utils::math::sigmoid;
+
+pub const MIN_ZOOM: f32 = 0.01;
+pub const MAX_ZOOM: f32 = 15.0;
+
+/// A view of the world, centered on a point and zoomed in by a factor which maps world units
+/// to half the screen width. The screen's aspect ratio is kept, so a world unit is as tall
+/// as it is wide
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    pub center: Vec2,
+    zoom: f32,
+}
+
+impl Camera {
+    pub fn new(center: Vec2, zoom: f32) -> Self {
+        Self {
+            center,
+            zoom: zoom.clamp(MIN_ZOOM, MAX_ZOOM),
+        }
+    }
+
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    /// Clamped to `[MIN_ZOOM, MAX_ZOOM]`
+    pub fn set_zoom(&mut self, zoom: f32) {
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+
+    pub fn zoom_by(&mut self, factor: f32) {
+        self.set_zoom(self.zoom * factor);
+    }
+
+    /// Zooms in or out by a little for each notch the mouse wheel turned in this frame
+    pub fn zoom_with_mouse_wheel(&mut self) {
+        let mw = sigmoid(mouse_wheel().1) - 0.5;
+        self.zoom_by((mw * 0.1).exp());
+    }
+
+    /// Moves the center along `direction` by `speed` divided by the zoom, so that the view
+    /// scrolls as fast on screen whatever the zoom
+    pub fn pan(&mut self, direction: Vec2, speed: f32) {
+        if direction != Vec2::ZERO {
+            self.center += direction.normalize() * speed / self.zoom;
+        }
+    }
+
+    /// Pans along the WASD keys held down in this frame
+    pub fn pan_with_keys(&mut self, speed: f32) {
+        let mut direction = Vec2::ZERO;
+        if is_key_down(KeyCode::W) {
+            direction.y += 1.0;
+        }
+        if is_key_down(KeyCode::S) {
+            direction.y -= 1.0;
+        }
+        if is_key_down(KeyCode::A) {
+            direction.x -= 1.0;
+        }
+        if is_key_down(KeyCode::D) {
+            direction.x += 1.0;
+        }
+        self.pan(direction, speed);
+    }
+
+    pub fn follow(&mut self, target: Vec2) {
+        self.center = target;
+    }
+
+    /// The macroquad camera to draw the world through on a screen of `screen_size` pixels
+    pub fn camera_2d(&self, screen_size: Vec2) -> Camera2D {
+        Camera2D {
+            zoom: self.scale(screen_size),
+            target: self.center,
+            ..Default::default()
+        }
+    }
+
+    /// Point of the world under the pixel at `point`, counted from the top left of the screen,
+    /// the same as `Camera2D::screen_to_world` of `camera_2d`
+    pub fn screen_to_world(&self, point: Vec2, screen_size: Vec2) -> Vec2 {
+        let ndc = vec2(
+            point.x / screen_size.x * 2.0 - 1.0,
+            1.0 - point.y / screen_size.y * 2.0,
+        );
+        self.center + ndc / self.scale(screen_size)
+    }
+
+    pub fn world_to_screen(&self, point: Vec2, screen_size: Vec2) -> Vec2 {
+        let ndc = (point - self.center) * self.scale(screen_size);
+        vec2(
+            (ndc.x + 1.0) * 0.5 * screen_size.x,
+            (1.0 - ndc.y) * 0.5 * screen_size.y,
+        )
+    }
+
+    /// From world units to normalized device coordinates, which span [-1, 1] across the screen
+    fn scale(&self, screen_size: Vec2) -> Vec2 {
+        vec2(self.zoom, self.zoom * screen_size.x / screen_size.y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zoom_is_clamped() {
+        let mut camera = Camera::new(Vec2::ZERO, 100.0);
+        assert_eq!(camera.zoom(), MAX_ZOOM);
+        camera.set_zoom(0.0);
+        assert_eq!(camera.zoom(), MIN_ZOOM);
+        camera.set_zoom(0.3);
+        camera.zoom_by(2.0);
+        assert!((camera.zoom() - 0.6).abs() < 1e-6);
+        camera.zoom_by(1e-6);
+        assert_eq!(camera.zoom(), MIN_ZOOM);
+        camera.zoom_by(1e6);
+        assert_eq!(camera.zoom(), MAX_ZOOM);
+    }
+
+    #[test]
+    fn test_screen_to_world_round_trip() {
+        let screen_size = vec2(1600.0, 900.0);
+        let camera = Camera::new(vec2(3.0, -4.0), 0.25);
+        // the middle of the screen shows the center, and the screen is 2 / zoom wide
+        assert_eq!(
+            camera.screen_to_world(screen_size / 2.0, screen_size),
+            camera.center
+        );
+        let bottom_left = camera.screen_to_world(vec2(0.0, screen_size.y), screen_size);
+        let top_right = camera.screen_to_world(vec2(screen_size.x, 0.0), screen_size);
+        let extent = top_right - bottom_left;
+        assert!((extent.x - 2.0 / 0.25).abs() < 1e-4);
+        assert!((extent.x / extent.y - screen_size.x / screen_size.y).abs() < 1e-4);
+
+        for point in [vec2(0.0, 0.0), vec2(123.0, 456.0), vec2(1600.0, 900.0)] {
+            let world = camera.screen_to_world(point, screen_size);
+            let back = camera.world_to_screen(world, screen_size);
+            assert!((back - point).length() < 1e-3, "{} vs {}", back, point);
+        }
+
+        // panning scrolls the view by the same number of pixels at any zoom
+        for zoom in [0.1, 1.0, 4.0] {
+            let mut camera = Camera::new(vec2(3.0, -4.0), zoom);
+            let origin = vec2(3.0, -4.0);
+            let before = camera.world_to_screen(origin, screen_size);
+            camera.pan(vec2(2.0, 0.0), 0.03);
+            let scrolled = before - camera.world_to_screen(origin, screen_size);
+            assert!((scrolled - vec2(0.03 * screen_size.x / 2.0, 0.0)).length() < 1e-3);
+        }
+    }
+}
@@ -9,7 +9,7 @@ use linefollower_core::utils::traits::Float;
 pub const ROBOT_SIDE_LENGTH: f32 = 0.1;
 pub const SENSOR_ARRAY_LENGTH: f32 = ROBOT_SIDE_LENGTH * 1.1;
 
-pub fn draw_closed_curve<F>(points: &[Point2<F>], color: Color, stroke_width: f32)
+pub fn draw_curve<F>(points: &[Point2<F>], color: Color, stroke_width: f32)
 where
     F: Float,
 {
@@ -23,6 +23,13 @@ where
             color,
         );
     }
+}
+
+pub fn draw_closed_curve<F>(points: &[Point2<F>], color: Color, stroke_width: f32)
+where
+    F: Float,
+{
+    draw_curve(points, color, stroke_width);
     draw_line(
         points[points.len() - 1].x.to_f32().unwrap(),
         points[points.len() - 1].y.to_f32().unwrap(),
@@ -33,6 +40,90 @@ where
     );
 }
 
+/// Number of triangles of the fans which round the joins of thick polylines
+pub const ROUND_JOIN_SEGMENTS: usize = 8;
+
+/// Triangulates a polyline stroked with `stroke_width`: each segment becomes a quad
+/// (two triangles) and each point gets a disk (a fan of `ROUND_JOIN_SEGMENTS` triangles)
+/// which fills the gaps between consecutive quads, giving rounded joins and caps.
+/// Segments of zero length are skipped.
+pub fn thick_polyline_triangles(
+    points: &[Vec2],
+    stroke_width: f32,
+    closed: bool,
+) -> Vec<[Vec2; 3]> {
+    let half_width = stroke_width / 2.0;
+    let num_segments = match (closed, points.len()) {
+        (_, 0) | (_, 1) => 0,
+        (true, n) => n,
+        (false, n) => n - 1,
+    };
+    let mut triangles = Vec::with_capacity(2 * num_segments + ROUND_JOIN_SEGMENTS * points.len());
+    for i in 0..num_segments {
+        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+        let direction = b - a;
+        if direction.length_squared() == 0.0 {
+            continue;
+        }
+        let normal = direction.perp().normalize() * half_width;
+        triangles.push([a + normal, a - normal, b + normal]);
+        triangles.push([b + normal, a - normal, b - normal]);
+    }
+    for &p in points {
+        for k in 0..ROUND_JOIN_SEGMENTS {
+            let angle = |k: usize| 2.0 * PI * k as f32 / ROUND_JOIN_SEGMENTS as f32;
+            let (a0, a1) = (angle(k), angle(k + 1));
+            triangles.push([
+                p,
+                p + vec2(a0.cos(), a0.sin()) * half_width,
+                p + vec2(a1.cos(), a1.sin()) * half_width,
+            ]);
+        }
+    }
+    triangles
+}
+
+/// Like `draw_closed_curve`, but with a proper stroke and rounded joins instead of separate lines
+pub fn draw_thick_closed_curve<F>(points: &[Point2<F>], color: Color, stroke_width: f32)
+where
+    F: Float,
+{
+    let points: Vec<Vec2> = points
+        .iter()
+        .map(|p| vec2(p.x.to_f32().unwrap(), p.y.to_f32().unwrap()))
+        .collect();
+    for [v1, v2, v3] in thick_polyline_triangles(&points, stroke_width, true) {
+        draw_triangle(v1, v2, v3, color);
+    }
+}
+
+/// Segments shorter than this on screen are merged when decimating a curve
+pub const MIN_SEGMENT_PIXELS: f32 = 2.0;
+
+/// How many samples to advance per drawn point so that consecutive points of a curve sampled
+/// every `sample_spacing` world units are at least `MIN_SEGMENT_PIXELS` apart on screen.
+/// `zoom` is the horizontal camera zoom, mapping world units to half the screen width.
+pub fn decimation_step(sample_spacing: f32, zoom: f32, screen_width: f32) -> usize {
+    let pixels_per_sample = sample_spacing * zoom * screen_width * 0.5;
+    if pixels_per_sample <= 0.0 || !pixels_per_sample.is_finite() {
+        return 1;
+    }
+    ((MIN_SEGMENT_PIXELS / pixels_per_sample).floor() as usize).max(1)
+}
+
+/// Keeps every `step`-th point, always including the first and last ones
+pub fn decimate<T: Copy>(points: &[T], step: usize) -> Vec<T> {
+    if points.is_empty() {
+        return Vec::new();
+    }
+    let step = step.max(1);
+    let mut decimated: Vec<T> = points.iter().step_by(step).copied().collect();
+    if (points.len() - 1) % step != 0 {
+        decimated.push(points[points.len() - 1]);
+    }
+    decimated
+}
+
 pub fn draw_vector(x: f32, y: f32, dx: f32, dy: f32, color: Color) {
     draw_line(x, y, x + dx, y + dy, 0.01, color);
 }
@@ -71,6 +162,8 @@ pub fn draw_grid_from_bounds(origin: Vec2, min_bounds: Vec2, max_bounds: Vec2, d
     let (x_0, y_0) = (origin[0], origin[1]);
     let (x_min, y_min) = (min_bounds[0], min_bounds[1]);
     let (x_max, y_max) = (max_bounds[0], max_bounds[1]);
+    // the lattice extends to the first line past each bound, so the lines on the edges of
+    // the screen are drawn and the grid stays aligned with the origin
     let range_x = lattice_points(x_0, x_min, x_max, dx);
     let range_y = lattice_points(y_0, y_min, y_max, dy);
     const THICKNESS: f32 = 0.005;
@@ -112,3 +205,54 @@ pub fn draw_grid_from_bounds(origin: Vec2, min_bounds: Vec2, max_bounds: Vec2, d
         Color::new(0., 0., 0., ALPHA),
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thick_polyline_vertex_count() {
+        let points = [
+            vec2(0.0, 0.0),
+            vec2(1.0, 0.0),
+            vec2(1.0, 1.0),
+            vec2(0.0, 1.0),
+        ];
+        let joins = ROUND_JOIN_SEGMENTS * points.len();
+        let open = thick_polyline_triangles(&points, 0.1, false);
+        assert_eq!(open.len() * 3, (2 * 3 + joins) * 3);
+        let closed = thick_polyline_triangles(&points, 0.1, true);
+        assert_eq!(closed.len() * 3, (2 * 4 + joins) * 3);
+        // the quads are as wide as the stroke
+        let [a, b, _] = closed[0];
+        assert!(((a - b).length() - 0.1).abs() < 1e-6);
+        // repeated points don't produce degenerate quads
+        let repeated = [vec2(0.0, 0.0), vec2(0.0, 0.0), vec2(1.0, 0.0)];
+        let triangles = thick_polyline_triangles(&repeated, 0.1, false);
+        assert_eq!(triangles.len(), 2 + ROUND_JOIN_SEGMENTS * 3);
+        assert!(triangles.iter().flatten().all(|v| v.is_finite()));
+    }
+
+    #[test]
+    fn test_decimate_preserves_endpoints() {
+        let points: Vec<usize> = (0..1000).collect();
+        for step in [1, 3, 7, 10, 999, 2000] {
+            let decimated = decimate(&points, step);
+            assert_eq!(decimated[0], 0);
+            assert_eq!(*decimated.last().unwrap(), 999);
+            assert!(decimated.len() <= points.len() / step + 2);
+        }
+        assert_eq!(decimate(&points, 1), points);
+        assert_eq!(decimate(&[5], 4), vec![5]);
+    }
+
+    #[test]
+    fn test_decimation_step_reduces_count_when_zoomed_out() {
+        let points: Vec<usize> = (0..10000).collect();
+        let close = decimation_step(0.01, 10.0, 1000.0);
+        let far = decimation_step(0.01, 0.1, 1000.0);
+        assert_eq!(close, 1);
+        assert!(far > 1);
+        assert!(decimate(&points, far).len() < decimate(&points, close).len());
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::VecDeque;
+
+use egui::plot::PlotPoints;
+
+/// The last values of a signal sampled once per frame, up to a fixed capacity,
+/// the oldest ones being dropped to make room for the new ones
+#[derive(Debug, Clone, PartialEq)]
+pub struct History {
+    values: VecDeque<f32>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "a history needs room for at least one value");
+        Self {
+            values: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, value: f32) {
+        if self.values.len() == self.capacity {
+            self.values.pop_front();
+        }
+        self.values.push_back(value);
+    }
+
+    /// From the oldest value to the latest one
+    pub fn iter_chronological(&self) -> impl Iterator<Item = f32> + '_ {
+        self.values.iter().copied()
+    }
+
+    /// The values from the oldest to the latest one, against their index
+    pub fn plot_points(&self) -> PlotPoints {
+        self.iter_chronological()
+            .enumerate()
+            .map(|(i, value)| [i as f64, value as f64])
+            .collect()
+    }
+
+    pub fn latest(&self) -> Option<f32> {
+        self.values.back().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Keeps only the latest `capacity` values when shrinking
+    pub fn set_capacity(&mut self, capacity: usize) {
+        assert!(capacity > 0, "a history needs room for at least one value");
+        while self.values.len() > capacity {
+            self.values.pop_front();
+        }
+        self.capacity = capacity;
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_keeps_the_latest_values_in_order() {
+        let mut history = History::new(4);
+        assert!(history.is_empty());
+        assert_eq!(history.latest(), None);
+        for value in 0..6 {
+            history.push(value as f32);
+        }
+        assert_eq!(history.len(), 4);
+        assert_eq!(
+            history.iter_chronological().collect::<Vec<_>>(),
+            vec![2.0, 3.0, 4.0, 5.0]
+        );
+        assert_eq!(history.latest(), Some(5.0));
+        let points = history.plot_points();
+        let points = points.points();
+        assert_eq!(points.len(), 4);
+        assert!(points.windows(2).all(|w| w[1].x == w[0].x + 1.0));
+        assert_eq!((points[0].y, points[3].y), (2.0, 5.0));
+
+        history.set_capacity(2);
+        assert_eq!(
+            history.iter_chronological().collect::<Vec<_>>(),
+            vec![4.0, 5.0]
+        );
+        history.set_capacity(3);
+        history.push(6.0);
+        history.push(7.0);
+        assert_eq!(
+            history.iter_chronological().collect::<Vec<_>>(),
+            vec![5.0, 6.0, 7.0]
+        );
+        history.clear();
+        assert!(history.is_empty());
+        assert_eq!(history.capacity(), 3);
+    }
+}
@@ -1 +1,3 @@
 pub mod graphics;
+pub mod history;
+pub mod plots;
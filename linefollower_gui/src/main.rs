@@ -1,32 +1,42 @@
 #![windows_subsystem = "windows"]
-use egui::plot::{Legend, Line, PlotPoints, Points};
+use egui::plot::{Legend, Line, PlotPoints};
 use egui::{RichText, TextStyle};
 use itertools::Itertools;
-use linefollower_core::geometry::closed_path::predefined_closed_path;
+use linefollower_core::geometry::closed_path::{predefined_closed_path, ClosedPath, SubPath};
 use linefollower_core::geometry::track::{sample_points, Track};
+use linefollower_core::geometry::track_file::load_track_auto;
 use linefollower_core::ode_solver::ode_system::Vector;
-use linefollower_core::simulation::robot::RobotSimulation;
-use linefollower_core::utils::math::sigmoid;
-use linefollower_gui::graphics::draw::{draw_closed_curve, ROBOT_SIDE_LENGTH, SENSOR_ARRAY_LENGTH};
+use linefollower_core::simulation::robot::{ControllerConfig, RobotSimulation};
+use linefollower_core::simulation::run::{align_by_progress, AlignedErrors, RunResult};
+use linefollower_gui::graphics::camera::Camera;
+use linefollower_gui::graphics::draw::{
+    decimate, decimation_step, draw_closed_curve, draw_curve, draw_thick_closed_curve,
+    ROBOT_SIDE_LENGTH, SENSOR_ARRAY_LENGTH,
+};
+use linefollower_gui::history::History;
+use linefollower_gui::plots::{colored_list, plot_window, sensor_window, Text, ValueFormat};
 use macroquad::color::Color;
 use macroquad::miniquad::conf::Icon;
-use macroquad::prelude::{
-    is_key_down, mouse_wheel, vec2, Camera2D, KeyCode, Vec2, GREEN, PURPLE, RED, SKYBLUE, YELLOW,
-};
+use macroquad::prelude::{mouse_wheel, vec2, Vec2, GREEN, PURPLE, SKYBLUE, YELLOW};
 use macroquad::shapes::draw_circle;
+use macroquad::time::get_frame_time;
 use macroquad::window::{next_frame, screen_height, screen_width, Conf};
+use std::collections::VecDeque;
 use std::f32::consts::PI;
+use std::path::PathBuf;
 use std::sync::Arc;
 
-const MAX_ZOOM: f32 = 15.0;
-const MIN_ZOOM: f32 = 0.01;
-
 // PID Constants
 const KP: f64 = 25.908317542875754;
 const KI: f64 = 81.02522946834891;
 const KD: f64 = 40.95824622164516;
 const SPEED: f64 = 0.3599426035093697;
 
+// Colors of the PID terms, shared by their plot and their readout
+const P_TERM_COLOR: egui::Color32 = egui::Color32::from_rgb(229, 75, 75);
+const I_TERM_COLOR: egui::Color32 = egui::Color32::from_rgb(92, 200, 255);
+const D_TERM_COLOR: egui::Color32 = egui::Color32::from_rgb(158, 217, 161);
+
 struct ColorScheme {
     pub darkmode: bool,
 }
@@ -106,6 +116,202 @@ impl ColorScheme {
     }
 }
 
+// distance along the path between the points of the run comparison plot
+const RUN_COMPARISON_SPACING: f64 = 0.05;
+
+// half-width of the lane the racing line may use, and number of points it is relaxed on
+const RACING_LINE_HALF_WIDTH: f64 = 0.15;
+const RACING_LINE_POINTS: usize = 200;
+
+/// Simulation with the given gains which follows `path`, starting at its first point
+fn new_simulation(config: &ControllerConfig, path: Arc<ClosedPath<f64>>) -> RobotSimulation {
+    let p0 = path.first_point();
+    let initial_condition = Vector::<7>::from_column_slice(&[p0.x, p0.y, 0.1, 0.0, 0.0, 0.0, 0.0]);
+    RobotSimulation::from_config(initial_condition, config, path)
+}
+
+/// Which kind of subpath `subpath` is, as shown in the info panel
+fn subpath_kind(subpath: &SubPath<f64>) -> &'static str {
+    match subpath {
+        SubPath::Arc(_) => "Arc",
+        SubPath::Line(_) => "Line",
+        SubPath::Spline(_) => "Spline",
+    }
+}
+
+/// Color the subpath closest to the selected robot is drawn over the track with
+const CURRENT_SUBPATH_COLOR: Color = Color::new(1.0, 0.6, 0.1, 0.9);
+
+/// Gains and reference speed `sim` currently runs with
+fn controller_config(sim: &RobotSimulation) -> ControllerConfig {
+    ControllerConfig {
+        kp: sim.kp,
+        ki: sim.ki,
+        kd: sim.kd,
+        speed: sim.speed,
+    }
+}
+
+/// Colors the robots are drawn and plotted in, in the order they are added
+const ROBOT_COLORS: [egui::Color32; 6] = [
+    egui::Color32::from_rgb(230, 41, 56),
+    egui::Color32::from_rgb(92, 200, 255),
+    egui::Color32::from_rgb(255, 180, 20),
+    egui::Color32::from_rgb(158, 217, 161),
+    egui::Color32::from_rgb(200, 20, 255),
+    egui::Color32::from_rgb(255, 130, 200),
+];
+
+fn macroquad_color(color: egui::Color32, alpha: f32) -> Color {
+    let mut color = Color::from_rgba(color.r(), color.g(), color.b(), 255);
+    color.a = alpha;
+    color
+}
+
+/// Halfway between `color` and white, to tell apart two lines of the same robot
+fn lighter(color: egui::Color32) -> egui::Color32 {
+    let lighten = |c: u8| ((c as u16 + 255) / 2) as u8;
+    egui::Color32::from_rgb(lighten(color.r()), lighten(color.g()), lighten(color.b()))
+}
+
+/// One of the robots on the track, along with the values its plots show and where it has been,
+/// sampled once per frame
+#[derive(Clone)]
+struct Robot {
+    name: String,
+    color: egui::Color32,
+    sim: RobotSimulation,
+    sdf_history: History,
+    wl_history: History,
+    wr_history: History,
+    yaw_rate_history: History,
+    p_term_history: History,
+    i_term_history: History,
+    d_term_history: History,
+    int_error_history: History,
+    reference_trail: VecDeque<nalgebra::Point2<f64>>,
+    robot_trail: VecDeque<nalgebra::Point2<f64>>,
+}
+
+impl Robot {
+    /// The `number`th robot added, which picks its color, plotting the last `history_length`
+    /// frames
+    fn new(number: usize, sim: RobotSimulation, history_length: usize) -> Self {
+        let history = History::new(history_length);
+        Self {
+            name: format!("Robot {}", number),
+            color: ROBOT_COLORS[(number - 1) % ROBOT_COLORS.len()],
+            sim,
+            sdf_history: history.clone(),
+            wl_history: history.clone(),
+            wr_history: history.clone(),
+            yaw_rate_history: history.clone(),
+            p_term_history: history.clone(),
+            i_term_history: history.clone(),
+            d_term_history: history.clone(),
+            int_error_history: history,
+            reference_trail: VecDeque::with_capacity(MAX_TRAIL_POINTS),
+            robot_trail: VecDeque::with_capacity(MAX_TRAIL_POINTS),
+        }
+    }
+
+    fn histories_mut(&mut self) -> [&mut History; 8] {
+        [
+            &mut self.sdf_history,
+            &mut self.wl_history,
+            &mut self.wr_history,
+            &mut self.yaw_rate_history,
+            &mut self.p_term_history,
+            &mut self.i_term_history,
+            &mut self.d_term_history,
+            &mut self.int_error_history,
+        ]
+    }
+
+    /// Samples the plotted values and the trails, after a frame of simulation
+    fn record(&mut self) {
+        let sim = &self.sim;
+        self.wl_history.push(sim.get_state()[3] as f32);
+        self.wr_history.push(sim.get_state()[5] as f32);
+        self.yaw_rate_history.push(sim.yaw_rate() as f32);
+        self.sdf_history.push(sim.robot_sdf_to_path() as f32);
+        self.p_term_history.push(sim.get_proportional_term() as f32);
+        self.i_term_history.push(sim.get_integral_term() as f32);
+        self.d_term_history.push(sim.get_derivative_term() as f32);
+        self.int_error_history.push(sim.get_integral_error() as f32);
+
+        if self.robot_trail.len() == MAX_TRAIL_POINTS {
+            self.reference_trail.pop_front();
+            self.robot_trail.pop_front();
+        }
+        self.reference_trail.push_back(sim.reference_point());
+        self.robot_trail.push_back(sim.robot_position());
+    }
+
+    fn clear_trails(&mut self) {
+        self.reference_trail.clear();
+        self.robot_trail.clear();
+    }
+}
+
+/// Most points kept in each trail of where the reference point and the robot have been
+const MAX_TRAIL_POINTS: usize = 10000;
+/// Frames shown by the plots over time, until changed from the UI
+const DEFAULT_HISTORY_LENGTH: usize = 600;
+
+/// Two recorded runs, overlaid on the track and compared at the same progress along the path
+struct RunComparison {
+    names: [String; 2],
+    trajectories: [Vec<nalgebra::Point2<f64>>; 2],
+    aligned: Vec<AlignedErrors>,
+}
+
+impl RunComparison {
+    fn load(filenames: &[PathBuf]) -> Result<Self, String> {
+        if filenames.len() != 2 {
+            return Err(format!(
+                "Select exactly two run files ({} selected)",
+                filenames.len()
+            ));
+        }
+        let load_run = |filename: &PathBuf| -> Result<RunResult, String> {
+            let json = std::fs::read_to_string(filename)
+                .map_err(|e| format!("{}: {}", filename.display(), e))?;
+            let run = RunResult::from_json(&json)
+                .map_err(|e| format!("{}: {}", filename.display(), e))?;
+            if run.trajectory.is_empty() {
+                return Err(format!(
+                    "{} doesn't contain a trajectory",
+                    filename.display()
+                ));
+            }
+            Ok(run)
+        };
+        let runs = [load_run(&filenames[0])?, load_run(&filenames[1])?];
+        let names = [0, 1].map(|i| {
+            let name = filenames[i].file_name().unwrap_or_default();
+            name.to_string_lossy().into_owned()
+        });
+        let trajectories = [0, 1].map(|i| {
+            runs[i]
+                .trajectory
+                .iter()
+                .map(|s| nalgebra::Point2::new(s.x, s.y))
+                .collect_vec()
+        });
+        let aligned = align_by_progress(
+            &runs[0].trajectory,
+            &runs[1].trajectory,
+            RUN_COMPARISON_SPACING,
+        );
+        Ok(Self {
+            names,
+            trajectories,
+            aligned,
+        })
+    }
+}
+
 fn window_conf() -> Conf {
     let file_bytes = include_bytes!("../assets/logo.ico");
     let icon_dir = ico::IconDir::read(std::io::Cursor::new(file_bytes.as_slice())).unwrap();
@@ -136,6 +342,8 @@ fn window_conf() -> Conf {
         window_title: "Line Follower Simulation".to_owned(),
         high_dpi: true,
         icon: Some(Icon { small, medium, big }),
+        // multisampling, so that the track and the robot are anti-aliased
+        sample_count: 4,
         ..Default::default()
     }
 }
@@ -143,17 +351,31 @@ fn window_conf() -> Conf {
 #[macroquad::main(window_conf)]
 async fn main() {
     const DT: f64 = 1.0 / 60.0;
+    // integration steps per frame
+    // the controller is recomputed on every one of them
+    const STEPS: usize = 4;
+    const STEP_SIZE: f64 = DT / STEPS as f64;
+    // most real time, per unit of simulation speed, the simulation catches up on in one frame.
+    // Past it, the simulation falls behind rather than taking ever longer frames to catch up
+    const MAX_CATCH_UP_TIME: f64 = 0.25;
+    // control rate of the real robot the gains are being tuned for
+    let mut target_control_rate: f64 = 1.0 / STEP_SIZE;
     let mut should_draw_grid = false;
+    let mut smooth_track = true;
     let mut pixels_per_point: Option<f32> = Some(1.5);
-    let mut zoom: f32 = 0.3;
+    let mut camera = Camera::new(vec2(0.0, -4.0), 0.3);
     const CAMERA_SPEED: f32 = 3.0e-2;
-    let mut camera_center: Vec2 = [0.0, -4.0].into();
     let mut follow_robot = true;
     let mut color_scheme = ColorScheme::new(true);
 
     let mut show_omega_plot = false;
+    let mut show_yaw_rate_plot = false;
     let mut show_robot_distance_plot = false;
     let mut show_pid_terms_plot = false;
+    let mut show_integral_error_plot = false;
+    let mut show_sensor_plot = false;
+    let mut show_trails = false;
+    let mut highlight_subpath = true;
 
     // control simulation speed
     let mut speed_multiplier = 1;
@@ -161,33 +383,19 @@ async fn main() {
     // pause simulation
     let mut paused = false;
 
-    // sample once per frame
-    let mut robot_sdf_history = [0.0f32; 600];
-    let mut i = 0;
-
-    let mut wl_history = [0.0f32; 600];
-    let mut wl_i = 0;
-
-    let mut wr_history = [0.0f32; 600];
-    let mut wr_i = 0;
+    // advance the simulation by the real time elapsed, in steps of DT, rather than by DT per frame
+    let mut real_time = true;
+    // real time elapsed, scaled by the simulation speed, which is yet to be simulated
+    let mut unsimulated_time = 0.0;
 
-    // PID terms
-    // MUSTFIX: SEGFAULTS IF THIS IS TOO BIG (400 is fine)
-    // for example, with 600 points for each, it segfaults when you try to draw the plot with lines
-    // I suspect this is a bug in macroquad or egui
-    let mut p_term_history = [0.0f32; 400];
-    let mut kpn = 0;
-
-    let mut i_term_history = [0.0f32; 400];
-    let mut kin = 0;
-
-    let mut d_term_history = [0.0f32; 400];
-    let mut kdn = 0;
+    let mut run_comparison: Option<RunComparison> = None;
+    let mut run_comparison_error: Option<String> = None;
 
     // whether the user has selected a path
     let mut path_selected = false;
     // default path
     let mut main_path = predefined_closed_path();
+    let mut path_error: Option<String> = None;
 
     // initial config of egui context
     egui_macroquad::ui(|egui_ctx| {
@@ -207,18 +415,27 @@ async fn main() {
                 if ui.button("Default Path").clicked() {
                     path_selected = true;
                 }
-                // choose a path from a given filename in json
-                // using serde_json to deserialize the path
+                // choose a path from a given filename in json or svg
                 if ui.button("Choose Path").clicked() {
                     let filename = rfd::FileDialog::new()
-                        .add_filter("JSON", &["json"])
+                        .add_filter("Track", &["json", "svg"])
                         .pick_file();
                     if let Some(filename) = filename {
-                        let path = std::fs::read_to_string(filename).unwrap();
-                        main_path = serde_json::from_str(&path).unwrap();
-                        path_selected = true;
+                        match load_track_auto(filename) {
+                            Ok(track) => {
+                                main_path = track;
+                                path_selected = true;
+                            }
+                            Err(e) => path_error = Some(e.to_string()),
+                        }
                     }
                 }
+                if let Some(error) = &path_error {
+                    ui.colored_label(
+                        egui::Color32::from_rgb(229, 75, 75),
+                        format!("Couldn't load the track: {}", error),
+                    );
+                }
             });
         });
 
@@ -227,96 +444,97 @@ async fn main() {
         next_frame().await;
     }
 
-    let path_points = sample_points(&main_path, 0.1).collect_vec();
-    let p0 = main_path.first_point();
-
-    let initial_condition = Vector::<7>::from_column_slice(&[p0.x, p0.y, 0.1, 0.0, 0.0, 0.0, 0.0]);
-    let mut robot_sim = RobotSimulation::new(
-        initial_condition,
-        KP,
-        KI,
-        KD,
-        SPEED,
-        Arc::new(main_path.clone()),
-    );
+    const PATH_SAMPLE_SPACING: f64 = 0.1;
+    let path_points = sample_points(&main_path, PATH_SAMPLE_SPACING).collect_vec();
+    // the points of each subpath along with its ends, which sample_points leaves out, so that a
+    // highlighted subpath has no gaps
+    let subpath_points = main_path
+        .subpaths()
+        .iter()
+        .map(|subpath| {
+            std::iter::once(subpath.point_at(0.0))
+                .chain(sample_points(subpath, PATH_SAMPLE_SPACING))
+                .chain(std::iter::once(subpath.point_at(subpath.length())))
+                .collect_vec()
+        })
+        .collect_vec();
+    // the track is always drawn, but the robot may follow the racing line instead
+    let centerline = Arc::new(main_path.clone());
+    let mut follow_racing_line = false;
+    let mut racing_line: Option<(Arc<ClosedPath<f64>>, Vec<nalgebra::Point2<f64>>)> = None;
+
+    let config = ControllerConfig {
+        kp: KP,
+        ki: KI,
+        kd: KD,
+        speed: SPEED,
+    };
+    // the robots race together, `selected` being the one whose gains and plots are shown
+    let mut history_length = DEFAULT_HISTORY_LENGTH;
+    let mut robots = vec![Robot::new(
+        1,
+        new_simulation(&config, centerline.clone()),
+        history_length,
+    )];
+    let mut robots_added = 1;
+    let mut selected = 0;
+    let mut followed = 0;
 
     loop {
         macroquad::window::clear_background(color_scheme.background());
 
-        // WASD camera movement
-        let mut camera_velocity: Vec2 = Vec2::ZERO;
-
-        if is_key_down(KeyCode::W) {
-            camera_velocity.y += 1.0;
-        }
-        if is_key_down(KeyCode::S) {
-            camera_velocity.y -= 1.0;
-        }
-        if is_key_down(KeyCode::A) {
-            camera_velocity.x -= 1.0;
-        }
-        if is_key_down(KeyCode::D) {
-            camera_velocity.x += 1.0;
-        }
-        // normalize camera velocity
-        if camera_velocity != Vec2::ZERO {
-            camera_velocity = camera_velocity.normalize() * CAMERA_SPEED / zoom;
-        }
-
-        if follow_robot {
-            let robot_state = robot_sim.get_state();
-            let robot_pos = vec2(robot_state[0] as f32, robot_state[1] as f32);
-            camera_center = robot_pos;
+        // the robot's position is meaningless once the integration diverged
+        let followed_sim = &robots[followed].sim;
+        if follow_robot && !followed_sim.is_diverged() {
+            let robot_state = followed_sim.get_state();
+            camera.follow(vec2(robot_state[0] as f32, robot_state[1] as f32));
         } else {
-            camera_center += camera_velocity;
+            camera.pan_with_keys(CAMERA_SPEED);
         }
 
-        let camera = Camera2D {
-            zoom: vec2(zoom, zoom * screen_width() / screen_height()),
-            target: camera_center,
-            ..Default::default()
+        let screen_size = vec2(screen_width(), screen_height());
+        let camera_2d = camera.camera_2d(screen_size);
+        let mouse_world_pos =
+            camera.screen_to_world(macroquad::input::mouse_position().into(), screen_size);
+
+        macroquad::prelude::set_camera(&camera_2d);
+
+        let frames_to_simulate = if paused {
+            unsimulated_time = 0.0;
+            0
+        } else if real_time {
+            unsimulated_time += get_frame_time() as f64 * speed_multiplier as f64;
+            unsimulated_time = unsimulated_time.min(MAX_CATCH_UP_TIME * speed_multiplier as f64);
+            let frames = (unsimulated_time / DT).floor();
+            unsimulated_time -= frames * DT;
+            frames as usize
+        } else {
+            speed_multiplier
         };
-
-        let mouse_world_pos = camera.screen_to_world(macroquad::input::mouse_position().into());
-
-        macroquad::prelude::set_camera(&camera);
-
-        if !paused {
-            const STEPS: usize = 4;
-            const STEP_SIZE: f64 = DT / STEPS as f64;
-            for _ in 0..speed_multiplier {
+        for _ in 0..frames_to_simulate {
+            for robot in robots.iter_mut() {
                 for _ in 0..STEPS {
-                    robot_sim.step(STEP_SIZE);
+                    robot.sim.step(STEP_SIZE);
                 }
-                wl_history[wl_i] = robot_sim.get_state()[3] as f32;
-                wl_i = (wl_i + 1) % wl_history.len();
-
-                wr_history[wr_i] = robot_sim.get_state()[5] as f32;
-                wr_i = (wr_i + 1) % wl_history.len();
-
-                robot_sdf_history[i] = robot_sim.robot_sdf_to_path() as f32;
-                i = (i + 1) % robot_sdf_history.len();
-
-                p_term_history[kpn] = robot_sim.get_proportional_term() as f32;
-                kpn = (kpn + 1) % p_term_history.len();
-
-                i_term_history[kin] = robot_sim.get_integral_term() as f32;
-                kin = (kin + 1) % i_term_history.len();
-
-                d_term_history[kdn] = robot_sim.get_derivative_term() as f32;
-                kdn = (kdn + 1) % d_term_history.len();
+            }
+            if robots.iter().any(|robot| robot.sim.is_diverged()) {
+                // keep the plots and trails free of NaNs, and wait for a reset
+                paused = true;
+                break;
+            }
+            for robot in robots.iter_mut() {
+                robot.record();
             }
         }
-        // calculate zoom from mouse scroll
-        let mw = sigmoid(mouse_wheel().1) - 0.5;
-        let new_zoom = zoom * (mw * 0.1).exp();
-        if new_zoom <= MIN_ZOOM {
-            zoom = MIN_ZOOM;
-        } else if new_zoom >= MAX_ZOOM {
-            zoom = MAX_ZOOM;
+        // of the centerline, whose geometry the track is made of even when following the racing line
+        let selected_sim = &robots[selected].sim;
+        let current_subpath = if selected_sim.is_diverged() {
+            None
         } else {
-            zoom = new_zoom;
-        }
+            Some(main_path.closest_subpath_index(selected_sim.robot_position()))
+        };
+
+        camera.zoom_with_mouse_wheel();
 
         egui_macroquad::ui(|egui_ctx| {
             if pixels_per_point.is_none() {
@@ -330,16 +548,111 @@ async fn main() {
                     ui.separator();
                     color_scheme.global_dark_light_mode_switch(ui);
                     ui.checkbox(&mut should_draw_grid, "Draw grid");
-                    ui.checkbox(&mut follow_robot, "Follow robot with camera");
+                    ui.checkbox(&mut smooth_track, "Smooth track rendering")
+                        .on_hover_text("Draw the track as a single stroke with rounded joins");
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut follow_robot, "Follow with camera:");
+                        let name = |robot: &Robot| RichText::new(&robot.name).color(robot.color);
+                        egui::ComboBox::from_id_source("followed_robot")
+                            .selected_text(name(&robots[followed]))
+                            .show_ui(ui, |ui| {
+                                for (k, robot) in robots.iter().enumerate() {
+                                    ui.selectable_value(&mut followed, k, name(robot));
+                                }
+                            });
+                    });
                     ui.checkbox(&mut paused, "Pause simulation");
+                    ui.checkbox(&mut real_time, "Run in real time").on_hover_text(
+                        "Simulate as much time as has really elapsed since the last frame. \
+                        Otherwise, every frame simulates 1/60 s, faster or slower than real time \
+                        depending on the frame rate",
+                    );
+                    ui.checkbox(&mut show_trails, "Draw reference and robot trails")
+                        .on_hover_text(
+                            "Where each robot has been, in its color. Purple: where the selected robot's reference point has been",
+                        );
+                    ui.checkbox(&mut highlight_subpath, "Highlight current subpath")
+                        .on_hover_text(
+                            "Draw the subpath of the track closest to the selected robot in orange",
+                        );
+                    if ui
+                        .checkbox(&mut follow_racing_line, "Follow racing line")
+                        .on_hover_text(
+                            "Follow a line which cuts the corners within the lane instead of the center of the track, \
+                            which is still drawn. Restarts every robot",
+                        )
+                        .changed()
+                    {
+                        let path = if follow_racing_line {
+                            let (path, _) = racing_line.get_or_insert_with(|| {
+                                let spline = main_path
+                                    .racing_line(RACING_LINE_HALF_WIDTH, RACING_LINE_POINTS);
+                                let path = Arc::new(ClosedPath::from(spline));
+                                let points =
+                                    sample_points(path.as_ref(), PATH_SAMPLE_SPACING).collect_vec();
+                                (path, points)
+                            });
+                            path.clone()
+                        } else {
+                            centerline.clone()
+                        };
+                        for robot in robots.iter_mut() {
+                            robot.sim = new_simulation(&controller_config(&robot.sim), path.clone());
+                            robot.clear_trails();
+                        }
+                        paused = false;
+                    }
+                    if ui
+                        .button("Compare two runs")
+                        .on_hover_text(
+                            "Load two saved run results, overlay their trajectories and plot their errors along the path",
+                        )
+                        .clicked()
+                    {
+                        let filenames = rfd::FileDialog::new()
+                            .add_filter("JSON", &["json"])
+                            .pick_files();
+                        if let Some(filenames) = filenames {
+                            match RunComparison::load(&filenames) {
+                                Ok(comparison) => {
+                                    run_comparison = Some(comparison);
+                                    run_comparison_error = None;
+                                }
+                                Err(error) => run_comparison_error = Some(error),
+                            }
+                        }
+                    }
+                    if let Some(error) = &run_comparison_error {
+                        ui.colored_label(egui::Color32::from_rgb(229, 75, 75), error.as_str());
+                    }
                     // reset simulation button
                     if ui.button("Reset simulation").clicked() {
-                        robot_sim.reset();
+                        for robot in robots.iter_mut() {
+                            robot.sim.reset();
+                            robot.clear_trails();
+                        }
                     }
                     // simulation speed label
                     let sim_speed_label = ui.label("Simulation speed: ");
                     ui.add(egui::Slider::new(&mut speed_multiplier, 1..=3).clamp_to_range(true))
                         .labelled_by(sim_speed_label.id);
+                    // edit how far back the plots go
+                    let history_label = ui.label("Plot history length: ");
+                    if ui
+                        .add(
+                            egui::Slider::new(&mut history_length, 100..=10000)
+                                .logarithmic(true)
+                                .suffix(" frames"),
+                        )
+                        .labelled_by(history_label.id)
+                        .changed()
+                    {
+                        for robot in robots.iter_mut() {
+                            for history in robot.histories_mut() {
+                                history.set_capacity(history_length);
+                            }
+                        }
+                    }
                     // edit egui's pixels per point
                     let ppp_label = ui.label("Pixels per point: ");
                     let response = ui
@@ -350,8 +663,14 @@ async fn main() {
                         .labelled_by(ppp_label.id);
                     // edit zoom
                     let zoom_label = ui.label("Zoom: ");
-                    ui.add(egui::Slider::new(&mut zoom, 0.1..=10.0).logarithmic(true))
-                        .labelled_by(zoom_label.id);
+                    let mut zoom = camera.zoom();
+                    if ui
+                        .add(egui::Slider::new(&mut zoom, 0.1..=10.0).logarithmic(true))
+                        .labelled_by(zoom_label.id)
+                        .changed()
+                    {
+                        camera.set_zoom(zoom);
+                    }
 
                     ui.label(RichText::new("ℹ Info").heading());
                     ui.separator();
@@ -359,8 +678,12 @@ async fn main() {
                     let (mouse_x, mouse_y) = (mouse_world_pos.x, mouse_world_pos.y);
                     ui.label(format!("Mouse position: ({:.3}, {:.3})", mouse_x, mouse_y));
 
-                    // show distance to path
-                    ui.label(format!("Distance to path: {:.3}", robot_sdf_history[i]));
+                    // show the selected robot's distance to path
+                    let selected_sim = &robots[selected].sim;
+                    ui.label(format!(
+                        "Distance to path: {:.3}",
+                        selected_sim.robot_sdf_to_path()
+                    ));
 
                     let (mouse_wheel_x, mouse_wheel_y) = mouse_wheel();
                     ui.label(format!(
@@ -368,7 +691,40 @@ async fn main() {
                         mouse_wheel_x, mouse_wheel_y
                     ));
 
-                    ui.label(format!("Total time: {:.3} s", robot_sim.get_time()));
+                    ui.label(format!("Total time: {:.3} s", selected_sim.get_time()));
+                    ui.label(format!("IAE: {:.4} m·s", selected_sim.get_iae()));
+
+                    // numbered like the track's subpaths, from 0
+                    if let Some(index) = current_subpath {
+                        ui.label(format!(
+                            "Current subpath: {} #{}",
+                            subpath_kind(&main_path.subpaths()[index]),
+                            index
+                        ));
+                    }
+
+                    // the PID's integral and derivative use the integration step as their dt
+                    let control_rate = 1.0 / STEP_SIZE;
+                    ui.label(format!(
+                        "Control period: {:.4} s ({:.0} Hz)",
+                        STEP_SIZE, control_rate
+                    ));
+                    ui.horizontal(|ui| {
+                        let rate_label = ui.label("Target control rate: ");
+                        ui.add(
+                            egui::DragValue::new(&mut target_control_rate)
+                                .clamp_range(1.0..=10000.0)
+                                .suffix(" Hz"),
+                        )
+                        .labelled_by(rate_label.id);
+                    });
+                    if (control_rate - target_control_rate).abs() > 1e-6 * target_control_rate {
+                        ui.colored_label(
+                            egui::Color32::from_rgb(229, 180, 75),
+                            "⚠ The controller runs at a different rate than the target, \
+                            so the tuned gains may not transfer to the real robot",
+                        );
+                    }
 
                     // Don't change scale while dragging the slider
                     if response.drag_released() {
@@ -401,215 +757,417 @@ async fn main() {
                             .on_hover_text(
                                 "Plot the left and right wheel angular velocities over time",
                             );
+                        ui.toggle_value(&mut show_yaw_rate_plot, "Plot yaw rate (dθ/dt)")
+                            .on_hover_text(
+                                "Plot the turn rate of the robot, which results from the difference between the wheel velocities",
+                            );
                         ui.toggle_value(&mut show_robot_distance_plot, "Plot robot distance")
                             .on_hover_text("Plot the distance of the robot to the path over time");
                         ui.toggle_value(&mut show_pid_terms_plot, "Plot PID terms")
                             .on_hover_text(
                                 "Plot the P, I and D terms of the PID controller over time",
                             );
+                        ui.toggle_value(&mut show_integral_error_plot, "Plot integral error")
+                            .on_hover_text(
+                                "Plot the accumulated error used by the integral term over time",
+                            );
+                        ui.toggle_value(&mut show_sensor_plot, "Plot sensor readings")
+                            .on_hover_text(
+                                "Plot the current reading of each sensor, colored by whether it detects the line",
+                            );
 
+                        ui.label(RichText::new("🤖 Robots").heading());
+                        ui.separator();
+                        for (k, robot) in robots.iter().enumerate() {
+                            let name = RichText::new(&robot.name).color(robot.color);
+                            ui.selectable_value(&mut selected, k, name);
+                        }
+                        ui.horizontal(|ui| {
+                            if ui
+                                .button("Add robot")
+                                .on_hover_text(
+                                    "Add a copy of the selected robot, in the same state, whose gains are then set on their own",
+                                )
+                                .clicked()
+                            {
+                                robots_added += 1;
+                                let robot = Robot {
+                                    name: format!("Robot {}", robots_added),
+                                    color: ROBOT_COLORS[(robots_added - 1) % ROBOT_COLORS.len()],
+                                    ..robots[selected].clone()
+                                };
+                                robots.push(robot);
+                                selected = robots.len() - 1;
+                            }
+                            if ui
+                                .add_enabled(robots.len() > 1, egui::Button::new("Remove robot"))
+                                .on_hover_text("Remove the selected robot")
+                                .clicked()
+                            {
+                                let removed = selected;
+                                robots.remove(removed);
+                                selected = removed.min(robots.len() - 1);
+                                if followed == removed {
+                                    followed = selected;
+                                } else if followed > removed {
+                                    followed -= 1;
+                                }
+                            }
+                        });
+
+                        let robot = &mut robots[selected];
                         ui.label(RichText::new("🔧 Parameters").heading());
                         ui.separator();
+                        ui.colored_label(robot.color, robot.name.as_str());
                         ui.label(format!("Robot side length: {:.3}", ROBOT_SIDE_LENGTH));
                         ui.label(format!("Sensor array length: {:.3}", SENSOR_ARRAY_LENGTH));
                         // KP, KI, KD, SPEED
                         ui.add(
-                            egui::Slider::new(&mut robot_sim.kp, 0.0..=100.0)
+                            egui::Slider::new(&mut robot.sim.kp, 0.0..=100.0)
                                 .clamp_to_range(true)
                                 .smart_aim(true)
                                 .text("Kp"),
                         );
                         ui.add(
-                            egui::Slider::new(&mut robot_sim.ki, 0.0..=100.0)
+                            egui::Slider::new(&mut robot.sim.ki, 0.0..=100.0)
                                 .clamp_to_range(true)
                                 .smart_aim(true)
                                 .text("Ki"),
                         );
                         ui.add(
-                            egui::Slider::new(&mut robot_sim.kd, 0.0..=100.0)
+                            egui::Slider::new(&mut robot.sim.kd, 0.0..=100.0)
                                 .clamp_to_range(true)
                                 .smart_aim(true)
                                 .text("Kd"),
                         );
                         ui.add(
-                            egui::Slider::new(&mut robot_sim.speed, 0.0..=20.0)
+                            egui::Slider::new(&mut robot.sim.speed, 0.0..=20.0)
                                 .clamp_to_range(true)
                                 .smart_aim(true)
                                 .text("Speed"),
-                        )
+                        );
+
+                        // exact values of the terms, which the plot is too coarse to read
+                        let (p_term, i_term, d_term) = (
+                            robot.sim.get_proportional_term(),
+                            robot.sim.get_integral_term(),
+                            robot.sim.get_derivative_term(),
+                        );
+                        ui.label(RichText::new("PID terms").strong());
+                        ui.colored_label(P_TERM_COLOR, format!("P: {:+.4}", p_term));
+                        ui.colored_label(I_TERM_COLOR, format!("I: {:+.4}", i_term));
+                        ui.colored_label(D_TERM_COLOR, format!("D: {:+.4}", d_term));
+                        ui.label(format!("P + I + D: {:+.4}", p_term + i_term + d_term));
                     });
                 });
 
-            if show_omega_plot {
-                egui::Window::new("Angular velocities").show(egui_ctx, |ui| {
-                    let wl_color = egui::Color32::from_rgb(20, 200, 255);
-                    let wr_color = egui::Color32::from_rgb(200, 20, 255);
+            let diverged = robots
+                .iter()
+                .find(|robot| robot.sim.is_diverged())
+                .map(|robot| (robot.name.clone(), robot.sim.get_time()));
+            if let Some((name, time)) = diverged {
+                egui::Window::new("⚠ Simulation diverged")
+                    .collapsible(false)
+                    .resizable(false)
+                    .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                    .show(egui_ctx, |ui| {
+                        ui.colored_label(
+                            egui::Color32::from_rgb(229, 75, 75),
+                            format!(
+                                "The state of {} stopped being finite at t = {:.3} s.",
+                                name, time
+                            ),
+                        );
+                        ui.label(
+                            "This usually means the gains are too high for the integration step. \
+                            Lower them and reset the simulation.",
+                        );
+                        if ui.button("Reset simulation").clicked() {
+                            for robot in robots.iter_mut() {
+                                robot.sim.reset();
+                                robot.clear_trails();
+                            }
+                            paused = false;
+                        }
+                    });
+            }
 
+            let mut close_run_comparison = false;
+            if let Some(comparison) = &run_comparison {
+                egui::Window::new("Run comparison").show(egui_ctx, |ui| {
+                    let a_color = egui::Color32::from_rgb(92, 200, 255);
+                    let b_color = egui::Color32::from_rgb(255, 180, 20);
+                    let difference_color = egui::Color32::from_rgb(229, 75, 75);
                     ui.horizontal_wrapped(|ui| {
                         // Trick so we don't have to add spaces in the text below:
                         let width =
                             ui.fonts(|f| f.glyph_width(&TextStyle::Body.resolve(ui.style()), ' '));
                         ui.spacing_mut().item_spacing.x = width;
-                        ui.label("This plot shows the angular velocities of the ");
-                        ui.colored_label(wl_color, "left (ωl)");
+                        ui.label("This plot shows the distance to the path of ");
+                        ui.colored_label(a_color, format!("A ({})", comparison.names[0]));
                         ui.label(" and ");
-                        ui.colored_label(wr_color, "right (ωr)");
-                        ui.label(" wheels over time, in rad/s.");
+                        ui.colored_label(b_color, format!("B ({})", comparison.names[1]));
+                        ui.label(", and their ");
+                        ui.colored_label(difference_color, "difference (B - A)");
+                        ui.label(", over the arc-length traveled along the path, in meters.");
                     });
-                    let plot = egui::plot::Plot::new("plot_omegas")
+                    if comparison.aligned.is_empty() {
+                        ui.label("The runs don't cover any common stretch of the path.");
+                    }
+                    let plot = egui::plot::Plot::new("plot_run_comparison")
                         .label_formatter(|name, value| {
                             if !name.is_empty() {
-                                format!("{}: {:.*} rad/s", name, 1, value.y)
+                                format!("{}: {:.3} m at s = {:.2} m", name, value.y, value.x)
                             } else {
                                 "".to_owned()
                             }
                         })
                         .view_aspect(2.0)
-                        .allow_zoom(false)
-                        .allow_drag(false)
-                        .allow_scroll(false)
                         .legend(Legend::default())
-                        .show_background(false);
-
+                        .show_background(false)
+                        .include_y(0.0);
                     plot.show(ui, |plot_ui| {
+                        let line = |f: fn(&AlignedErrors) -> f64| {
+                            let points = comparison
+                                .aligned
+                                .iter()
+                                .map(|e| [e.progress, f(e)])
+                                .collect::<Vec<_>>();
+                            Line::new(PlotPoints::new(points))
+                        };
+                        plot_ui.line(line(|e| e.error_a).color(a_color).name("A"));
+                        plot_ui.line(line(|e| e.error_b).color(b_color).name("B"));
                         plot_ui.line(
-                            Line::new(PlotPoints::from_ys_f32(&wl_history))
-                                .color(wl_color)
-                                .name("ωl(t)"),
-                        );
-                        plot_ui.line(
-                            Line::new(PlotPoints::from_ys_f32(&wr_history))
-                                .color(wr_color)
-                                .name("ωr(t)"),
+                            line(AlignedErrors::difference)
+                                .color(difference_color)
+                                .name("B - A"),
                         );
                     });
+                    if ui.button("Close comparison").clicked() {
+                        close_run_comparison = true;
+                    }
                 });
             }
+            if close_run_comparison {
+                run_comparison = None;
+            }
 
-            if show_robot_distance_plot {
-                egui::Window::new("Distance to track").show(egui_ctx, |ui| {
-                    let positive_color = egui::Color32::from_rgb(229, 75, 75);
-                    let negative_color = egui::Color32::from_rgb(92, 200, 255);
-                    ui.horizontal_wrapped(|ui| {
-                        // Trick so we don't have to add spaces in the text below:
-                        let width = ui.fonts(|f|f.glyph_width(&TextStyle::Body.resolve(ui.style()), ' '));
-                        ui.spacing_mut().item_spacing.x = width;
-                        ui.label("This plot shows the distance of the robot to the path over time, in meters.");
-                        ui.label("The distance can be either ");
-                        ui.colored_label(positive_color, "positive");
-                        ui.label(" which means it is outside the track, or ");
-                        ui.colored_label(negative_color, "negative");
-                        ui.label(" which means it is inside the track.");
-                    });
-                    let plot = egui::plot::Plot::new("plot_robot_distance")
-                        .label_formatter(|name, value| {
-                            if !name.is_empty() {
-                                format!("{}: {:.3} m", name, value.y)
-                            } else {
-                                "".to_owned()
-                            }
-                        })
-                        .view_aspect(2.0)
-                        .allow_zoom(false)
-                        .allow_drag(false)
-                        .allow_scroll(false)
-                        .show_background(false)
-                        .include_y(0.0);
-                    // .include_y(1.0)
-                    // .include_y(-1.0);
-                    plot.show(ui, |plot_ui| {
-                        let positive_points = robot_sdf_history
-                        .iter()
-                        .enumerate()
-                        .filter(|(_, &d)| d >= 0.0)
-                        .map(|(i, &d)| [i as f64, d as f64])
-                        .collect::<Vec<_>>();
-
-                        plot_ui.points(
-                            Points::new(PlotPoints::new(positive_points))
-                                .color(positive_color)
-                                .stems(0.0)
-                                .name("d(t)"),
-                        );
+            if show_omega_plot {
+                let names = robots
+                    .iter()
+                    .map(|robot| {
+                        (
+                            format!("ωl(t), {}", robot.name),
+                            format!("ωr(t), {}", robot.name),
+                        )
+                    })
+                    .collect_vec();
+                let series = robots
+                    .iter()
+                    .zip(&names)
+                    .flat_map(|(robot, (wl_name, wr_name))| {
+                        [
+                            (wl_name.as_str(), robot.color, &robot.wl_history),
+                            (wr_name.as_str(), lighter(robot.color), &robot.wr_history),
+                        ]
+                    })
+                    .collect_vec();
+                let robot_names = robots
+                    .iter()
+                    .map(|robot| (robot.name.as_str(), robot.color))
+                    .collect_vec();
+                let description = [
+                    vec![Text::Plain(
+                        "This plot shows the angular velocities of the left (ωl) and right (ωr) wheels of ",
+                    )],
+                    colored_list(&robot_names),
+                    vec![Text::Plain(
+                        " over time, in rad/s. ωr is drawn in a lighter shade of the robot's color.",
+                    )],
+                ]
+                .concat();
+                plot_window(
+                    egui_ctx,
+                    "Angular velocities",
+                    &description,
+                    ValueFormat {
+                        decimals: 1,
+                        unit: "rad/s",
+                    },
+                    &series,
+                );
+            }
 
-                        let negative_points = robot_sdf_history
-                        .iter()
-                        .enumerate()
-                        .filter(|(_, &d)| d < 0.0)
-                        .map(|(i, &d)| [i as f64, d as f64])
-                        .collect::<Vec<_>>();
-
-                        plot_ui.points(
-                            Points::new(PlotPoints::new(negative_points))
-                                .color(negative_color)
-                                .stems(0.0)
-                                .name("d(t)"),
-                        );
-                    });
-                });
+            if show_yaw_rate_plot {
+                let yaw_rate_color = egui::Color32::from_rgb(255, 180, 20);
+                plot_window(
+                    egui_ctx,
+                    "Yaw rate",
+                    &[
+                        Text::Plain("This plot shows the "),
+                        Text::Colored("turn rate", yaw_rate_color),
+                        Text::Plain(" of the selected robot over time, in rad/s."),
+                    ],
+                    ValueFormat {
+                        decimals: 2,
+                        unit: "rad/s",
+                    },
+                    &[("dθ/dt", yaw_rate_color, &robots[selected].yaw_rate_history)],
+                );
+            }
+
+            if show_robot_distance_plot {
+                let series = robots
+                    .iter()
+                    .map(|robot| (robot.name.as_str(), robot.color, &robot.sdf_history))
+                    .collect_vec();
+                let robot_names = robots
+                    .iter()
+                    .map(|robot| (robot.name.as_str(), robot.color))
+                    .collect_vec();
+                let description = [
+                    vec![Text::Plain("This plot shows the distance of ")],
+                    colored_list(&robot_names),
+                    vec![Text::Plain(
+                        " to the path over time, in meters. \
+                        It is positive when the robot is outside the track, and negative when it is inside.",
+                    )],
+                ]
+                .concat();
+                plot_window(
+                    egui_ctx,
+                    "Distance to track",
+                    &description,
+                    ValueFormat {
+                        decimals: 3,
+                        unit: "m",
+                    },
+                    &series,
+                );
             }
 
             if show_pid_terms_plot {
-                egui::Window::new("PID terms").show(egui_ctx, |ui| {
-                    let kp_color = egui::Color32::from_rgb(229, 75, 75);
-                    let ki_color = egui::Color32::from_rgb(92, 200, 255);
-                    let kd_color = egui::Color32::from_rgb(158, 217, 161);
-                    ui.horizontal_wrapped(|ui| {
-                        // Trick so we don't have to add spaces in the text below:
-                        let width =
-                            ui.fonts(|f| f.glyph_width(&TextStyle::Body.resolve(ui.style()), ' '));
-                        ui.spacing_mut().item_spacing.x = width;
-                        ui.label("This plot shows the PID terms over time.");
-                    });
-                    let plot = egui::plot::Plot::new("plot_pid_terms")
-                        .label_formatter(|name, value| {
-                            if !name.is_empty() {
-                                format!("{}: {:.*}", name, 1, value.y)
-                            } else {
-                                "".to_owned()
-                            }
-                        })
-                        .view_aspect(2.0)
-                        .allow_zoom(false)
-                        .allow_drag(false)
-                        .allow_scroll(false)
-                        .legend(Legend::default())
-                        .show_background(false);
+                plot_window(
+                    egui_ctx,
+                    "PID terms",
+                    &[
+                        Text::Plain("This plot shows the "),
+                        Text::Colored("proportional", P_TERM_COLOR),
+                        Text::Plain(", "),
+                        Text::Colored("integral", I_TERM_COLOR),
+                        Text::Plain(" and "),
+                        Text::Colored("derivative", D_TERM_COLOR),
+                        Text::Plain(" terms of the selected robot over time."),
+                    ],
+                    ValueFormat {
+                        decimals: 1,
+                        unit: "",
+                    },
+                    &[
+                        ("P(t)", P_TERM_COLOR, &robots[selected].p_term_history),
+                        ("I(t)", I_TERM_COLOR, &robots[selected].i_term_history),
+                        ("D(t)", D_TERM_COLOR, &robots[selected].d_term_history),
+                    ],
+                );
+            }
 
-                    plot.show(ui, |plot_ui| {
-                        plot_ui.line(
-                            Line::new(PlotPoints::from_ys_f32(&p_term_history))
-                                .color(kp_color)
-                                .name("P(t)"),
-                        );
-                        plot_ui.line(
-                            Line::new(PlotPoints::from_ys_f32(&i_term_history))
-                                .color(ki_color)
-                                .name("I(t)"),
-                        );
-                        plot_ui.line(
-                            Line::new(PlotPoints::from_ys_f32(&d_term_history))
-                                .color(kd_color)
-                                .name("D(t)"),
-                        );
-                    });
-                });
+            if show_integral_error_plot {
+                let integral_error_color = egui::Color32::from_rgb(92, 200, 255);
+                plot_window(
+                    egui_ctx,
+                    "Integral error",
+                    &[
+                        Text::Plain("This plot shows the "),
+                        Text::Colored("accumulated error", integral_error_color),
+                        Text::Plain(" (before scaling by Ki) of the selected robot over time."),
+                    ],
+                    ValueFormat {
+                        decimals: 3,
+                        unit: "",
+                    },
+                    &[(
+                        "∫e(t)dt",
+                        integral_error_color,
+                        &robots[selected].int_error_history,
+                    )],
+                );
+            }
+
+            if show_sensor_plot {
+                let selected_sim = &robots[selected].sim;
+                let detected = selected_sim.sensor_distances().map(|d| d.is_some());
+                sensor_window(egui_ctx, &selected_sim.sensor_signals(), &detected);
             }
         });
 
         if should_draw_grid {
-            linefollower_gui::graphics::draw::draw_grid(Vec2::ZERO, &camera, 0.1, 0.1);
+            linefollower_gui::graphics::draw::draw_grid(Vec2::ZERO, &camera_2d, 0.1, 0.1);
+        }
+
+        // draw fewer points when zoomed out
+        let step = decimation_step(PATH_SAMPLE_SPACING as f32, camera.zoom(), screen_width());
+        let draw_track: fn(&[nalgebra::Point2<f64>], Color, f32) = if smooth_track {
+            draw_thick_closed_curve
+        } else {
+            draw_closed_curve
+        };
+        if step > 1 {
+            draw_track(&decimate(&path_points, step), color_scheme.path(), 0.03);
+        } else {
+            draw_track(&path_points, color_scheme.path(), 0.03);
+        }
+        if highlight_subpath {
+            if let Some(index) = current_subpath {
+                draw_curve(&subpath_points[index], CURRENT_SUBPATH_COLOR, 0.03);
+            }
         }
 
-        draw_closed_curve(&path_points, color_scheme.path(), 0.03);
+        if follow_racing_line {
+            if let Some((_, points)) = &racing_line {
+                draw_closed_curve(points, Color::new(0.3, 0.85, 0.45, 0.8), 0.01);
+            }
+        }
 
-        linefollower_gui::graphics::draw::draw_robot(
-            robot_sim.get_state()[0] as f32,
-            robot_sim.get_state()[1] as f32,
-            robot_sim.get_state()[2] as f32 * 180.0 / PI,
-            RED,
-        );
-        let pr = robot_sim.reference_point();
+        if show_trails {
+            draw_curve(
+                robots[selected].reference_trail.make_contiguous(),
+                Color::new(0.5, 0.2, 0.8, 0.7),
+                0.01,
+            );
+            for robot in &mut robots {
+                let color = macroquad_color(robot.color, 0.7);
+                draw_curve(robot.robot_trail.make_contiguous(), color, 0.01);
+            }
+        }
+
+        if let Some(comparison) = &run_comparison {
+            draw_curve(
+                &comparison.trajectories[0],
+                Color::new(0.36, 0.78, 1.0, 0.8),
+                0.01,
+            );
+            draw_curve(
+                &comparison.trajectories[1],
+                Color::new(1.0, 0.7, 0.08, 0.8),
+                0.01,
+            );
+        }
+
+        for robot in &robots {
+            linefollower_gui::graphics::draw::draw_robot(
+                robot.sim.get_state()[0] as f32,
+                robot.sim.get_state()[1] as f32,
+                robot.sim.get_state()[2] as f32 * 180.0 / PI,
+                macroquad_color(robot.color, 1.0),
+            );
+        }
+        // the reference point and the vectors only of the selected robot, to keep the view readable
+        let robot_sim = &robots[selected].sim;
+        // the robots start their reference at the start of its path, so it is wherever the
+        // reference profile has taken it since
+        let pr = robot_sim
+            .get_reference_path()
+            .point_at_time(robot_sim.get_time(), &robot_sim.get_reference_profile());
         draw_circle(pr.x as f32, pr.y as f32, 0.05, PURPLE);
         let tr = robot_sim.reference_tangent();
         // draw tangent vector to reference point
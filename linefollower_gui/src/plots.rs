@@ -0,0 +1,230 @@
+use egui::plot::{Bar, BarChart, Legend, Line, Plot};
+use egui::{Color32, TextStyle};
+
+use crate::history::History;
+
+/// Fill of the bar of a sensor which detects the line
+pub const SENSOR_DETECTED_COLOR: Color32 = Color32::from_rgb(158, 217, 161);
+/// Fill of the bar of a sensor which doesn't detect the line
+pub const SENSOR_UNDETECTED_COLOR: Color32 = Color32::from_rgb(110, 110, 110);
+
+/// A line of a plot: its name in the legend, its color and its values, one per frame
+pub type Series<'a> = (&'a str, Color32, &'a History);
+
+pub fn series_lines(series: &[Series]) -> Vec<Line> {
+    series
+        .iter()
+        .map(|&(name, color, history)| Line::new(history.plot_points()).color(color).name(name))
+        .collect()
+}
+
+/// How a plot shows the value of the line under the cursor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueFormat {
+    /// Digits after the decimal point
+    pub decimals: usize,
+    /// Written after the value, unless it is empty
+    pub unit: &'static str,
+}
+
+impl ValueFormat {
+    /// Label of the value `y` of the line `name`, empty away from the lines, where `name` is
+    pub fn label(&self, name: &str, y: f64) -> String {
+        if name.is_empty() {
+            String::new()
+        } else if self.unit.is_empty() {
+            format!("{}: {:.*}", name, self.decimals, y)
+        } else {
+            format!("{}: {:.*} {}", name, self.decimals, y, self.unit)
+        }
+    }
+}
+
+/// A piece of the description of a plot, in the color of a line when it names it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Text<'a> {
+    Plain(&'a str),
+    Colored(&'a str, Color32),
+}
+
+/// The `items` in their colors, separated by commas but for an "and" before the last one
+pub fn colored_list<'a>(items: &[(&'a str, Color32)]) -> Vec<Text<'a>> {
+    let mut text = Vec::new();
+    for (i, &(item, color)) in items.iter().enumerate() {
+        if i > 0 {
+            text.push(Text::Plain(if i + 1 == items.len() {
+                " and "
+            } else {
+                ", "
+            }));
+        }
+        text.push(Text::Colored(item, color));
+    }
+    text
+}
+
+/// Window with a short description above a plot of each of the `series` over time,
+/// with the same look and (disabled) interactions as every other plot of the simulation
+pub fn plot_window(
+    ctx: &egui::Context,
+    title: &str,
+    description: &[Text],
+    format: ValueFormat,
+    series: &[Series],
+) {
+    egui::Window::new(title).show(ctx, |ui| {
+        ui.horizontal_wrapped(|ui| {
+            // Trick so we don't have to add spaces in the text below:
+            let width = ui.fonts(|f| f.glyph_width(&TextStyle::Body.resolve(ui.style()), ' '));
+            ui.spacing_mut().item_spacing.x = width;
+            for &text in description {
+                match text {
+                    Text::Plain(text) => ui.label(text),
+                    Text::Colored(text, color) => ui.colored_label(color, text),
+                };
+            }
+        });
+        let plot = Plot::new(title)
+            .label_formatter(move |name, value| format.label(name, value.y))
+            .view_aspect(2.0)
+            .allow_zoom(false)
+            .allow_drag(false)
+            .allow_scroll(false)
+            .legend(Legend::default())
+            .show_background(false);
+
+        plot.show(ui, |plot_ui| {
+            for line in series_lines(series) {
+                plot_ui.line(line);
+            }
+        });
+    });
+}
+
+/// A bar per sensor, as high as its reading and filled by whether it detects the line.
+/// The sensors are given from the rightmost to the leftmost one, like the simulation does,
+/// and the bars go from left to right, like the sensors on the robot facing forward
+pub fn sensor_bars(signals: &[f64], detected: &[bool]) -> Vec<Bar> {
+    signals
+        .iter()
+        .zip(detected)
+        .rev()
+        .enumerate()
+        .map(|(i, (&signal, &detected))| {
+            let fill = if detected {
+                SENSOR_DETECTED_COLOR
+            } else {
+                SENSOR_UNDETECTED_COLOR
+            };
+            Bar::new(i as f64, signal).fill(fill)
+        })
+        .collect()
+}
+
+/// Window with a bar chart of the current reading of each sensor, between 0 and 1
+pub fn sensor_window(ctx: &egui::Context, signals: &[f64], detected: &[bool]) {
+    egui::Window::new("Sensor readings").show(ctx, |ui| {
+        ui.label(
+            "This plot shows the reading of each sensor, from the leftmost to the rightmost one.",
+        );
+        let plot = Plot::new("Sensor readings")
+            .view_aspect(2.0)
+            .allow_zoom(false)
+            .allow_drag(false)
+            .allow_scroll(false)
+            .include_y(0.0)
+            .include_y(1.0)
+            .show_background(false);
+        plot.show(ui, |plot_ui| {
+            plot_ui.bar_chart(BarChart::new(sensor_bars(signals, detected)).width(0.8));
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plot_window_builds_a_line_per_series() {
+        let (mut a, mut b, mut c) = (History::new(10), History::new(10), History::new(4));
+        for _ in 0..10 {
+            a.push(0.0);
+            b.push(1.0);
+            c.push(2.0);
+        }
+        let series: [Series; 3] = [
+            ("a", Color32::RED, &a),
+            ("b", Color32::GREEN, &b),
+            ("c", Color32::BLUE, &c),
+        ];
+        assert_eq!(series_lines(&series).len(), 3);
+        assert!(series_lines(&[]).is_empty());
+
+        // lay the window out in a headless context
+        let description = [
+            Text::Plain("A test plot of "),
+            Text::Colored("a", Color32::RED),
+            Text::Plain("."),
+        ];
+        let format = ValueFormat {
+            decimals: 2,
+            unit: "m",
+        };
+        let ctx = egui::Context::default();
+        let _ = ctx.run(egui::RawInput::default(), |ctx| {
+            plot_window(ctx, "Test plot", &description, format, &series);
+        });
+    }
+
+    #[test]
+    fn test_value_labels_keep_the_unit_and_precision() {
+        let format = ValueFormat {
+            decimals: 1,
+            unit: "rad/s",
+        };
+        assert_eq!(format.label("ωl(t)", 1.26), "ωl(t): 1.3 rad/s");
+        assert_eq!(format.label("", 1.26), "");
+        let format = ValueFormat {
+            decimals: 3,
+            unit: "",
+        };
+        assert_eq!(format.label("∫e(t)dt", -0.25), "∫e(t)dt: -0.250");
+    }
+
+    #[test]
+    fn test_colored_list() {
+        let (a, b, c) = (Color32::RED, Color32::GREEN, Color32::BLUE);
+        assert!(colored_list(&[]).is_empty());
+        assert_eq!(colored_list(&[("a", a)]), [Text::Colored("a", a)]);
+        assert_eq!(
+            colored_list(&[("a", a), ("b", b), ("c", c)]),
+            [
+                Text::Colored("a", a),
+                Text::Plain(", "),
+                Text::Colored("b", b),
+                Text::Plain(" and "),
+                Text::Colored("c", c),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sensor_bars_follow_the_sensors_from_left_to_right() {
+        // from the rightmost sensor, which doesn't see the line, to the leftmost one
+        let signals = [0.0, 0.2, 0.9, 0.4, 0.1];
+        let detected = [false, true, true, true, true];
+        let bars = sensor_bars(&signals, &detected);
+        assert_eq!(bars.len(), 5);
+        assert_eq!(bars[0].value, 0.1);
+        assert_eq!(bars[2].value, 0.9);
+        assert_eq!(bars[4].value, 0.0);
+        assert_eq!(bars[0].fill, SENSOR_DETECTED_COLOR);
+        assert_eq!(bars[4].fill, SENSOR_UNDETECTED_COLOR);
+
+        let ctx = egui::Context::default();
+        let _ = ctx.run(egui::RawInput::default(), |ctx| {
+            sensor_window(ctx, &signals, &detected);
+        });
+    }
+}
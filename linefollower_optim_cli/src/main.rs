@@ -1,16 +1,83 @@
-use linefollower_core::geometry::closed_path::predefined_closed_path;
-use linefollower_optim_cli::optimizer::RobotOptimizer;
+use linefollower_core::geometry::closed_path::{predefined_closed_path, predefined_tracks};
+use linefollower_core::geometry::track::SharedTrack;
+use linefollower_core::geometry::track_file::export_predefined_tracks;
+use linefollower_core::ode_solver::ode_system::Vector;
+use linefollower_core::simulation::robot::{ControllerConfig, RobotSimulation};
+use linefollower_core::simulation::run::run_headless;
+use linefollower_optim_cli::optimizer::{
+    ConvergenceConfig, FitnessAggregation, FitnessWeights, ObjectiveKind, RobotOptimizer,
+};
 use std::io::Write;
 use std::sync::Arc;
 
 fn main() {
+    // `export-tracks [dir]` writes the predefined tracks as JSON files instead of optimizing
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.get(1).map(String::as_str) == Some("export-tracks") {
+        let dir = args.get(2).map(String::as_str).unwrap_or("tracks");
+        for filename in export_predefined_tracks(dir).unwrap() {
+            println!("Wrote track to file \"{}\"", filename.display());
+        }
+        return;
+    }
+
+    // `--min-max-error` minimizes the worst deviation from the line instead of the integrated one
+    let objective = if args.iter().any(|arg| arg == "--min-max-error") {
+        ObjectiveKind::MinMaxError
+    } else {
+        ObjectiveKind::IntegratedError
+    };
+
+    // `--trace` also dumps the telemetry of the best run as CSV
+    let trace = args.iter().any(|arg| arg == "--trace");
+
+    // `--patience <generations>` stops the optimization once the best fitness has gone that many
+    // generations without improving by more than `--epsilon <fitness>`, 0 unless given
+    let value_of = |flag: &str| {
+        args.iter().position(|arg| arg == flag).map(|i| {
+            args.get(i + 1)
+                .unwrap_or_else(|| panic!("missing value after {}", flag))
+        })
+    };
+    let convergence = ConvergenceConfig {
+        epsilon: value_of("--epsilon").map_or(0.0, |value| value.parse().unwrap()),
+        patience: value_of("--patience").map(|value| value.parse().unwrap()),
+    };
+
     let main_path_sdf = Arc::new(predefined_closed_path());
 
+    // `--all-tracks` optimizes for the worst of the predefined tracks rather than the default one
+    let (tracks, aggregation): (Vec<SharedTrack>, _) =
+        if args.iter().any(|arg| arg == "--all-tracks") {
+            let tracks = predefined_tracks()
+                .into_iter()
+                .map(|(_, track)| Arc::new(track) as SharedTrack)
+                .collect();
+            (tracks, FitnessAggregation::Min)
+        } else {
+            (vec![main_path_sdf.clone()], FitnessAggregation::Mean)
+        };
+
     let ts = 1.0 / 240.0;
     let t_total = 1200.0;
     let n = (t_total / ts) as usize;
     println!("Running optimization...");
-    let best_ks = RobotOptimizer::new(n, ts, main_path_sdf).find_optimal_multithreaded();
+    let result = RobotOptimizer::new(n, ts, tracks, FitnessWeights::default())
+        .with_objective(objective)
+        .with_aggregation(aggregation)
+        .find_optimal_multithreaded(convergence);
+    if result.stopped_early {
+        println!(
+            "Stopping early at generation {}: the best fitness, {}, improved by at most {} in \
+             the last {} generations",
+            result.generations,
+            result.best_fitness,
+            convergence.epsilon,
+            convergence.patience.unwrap()
+        );
+    }
+    println!("Optimized for {} generations", result.generations);
+    let best_ks = result.best;
     let now = chrono::Local::now();
     let filename = format!("optimal_params_{}.txt", now.format("%Y-%m-%d_%H-%M-%S"));
     let mut file = std::fs::File::create(filename.clone()).unwrap();
@@ -21,4 +88,26 @@ fn main() {
     )
     .unwrap();
     println!("Wrote outputs to file \"{}\"", filename);
+
+    // archive a run with the best parameters, so that it can be compared against other runs
+    let config = ControllerConfig {
+        kp: best_ks[0],
+        ki: best_ks[1],
+        kd: best_ks[2],
+        speed: best_ks[3],
+    };
+    let x0 = Vector::<7>::from_column_slice(&[0.0, -4.0, 0.1, 0.0, 0.0, 0.0, 0.0]);
+    let result = run_headless(x0, &config, main_path_sdf.clone(), ts, t_total);
+    let filename = format!("run_result_{}.json", now.format("%Y-%m-%d_%H-%M-%S"));
+    std::fs::write(&filename, result.to_json().unwrap()).unwrap();
+    println!("Wrote run result to file \"{}\"", filename);
+
+    if trace {
+        let filename = format!("trace_{}.csv", now.format("%Y-%m-%d_%H-%M-%S"));
+        let file = std::fs::File::create(&filename).unwrap();
+        RobotSimulation::from_config(x0, &config, main_path_sdf)
+            .record_csv(file, n, ts)
+            .unwrap();
+        println!("Wrote trace to file \"{}\"", filename);
+    }
 }
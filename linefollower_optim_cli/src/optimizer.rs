@@ -1,39 +1,292 @@
 use cmaes::{CMAESOptions, ObjectiveFunction, ParallelObjectiveFunction, PlotOptions};
-use linefollower_core::simulation::robot::RobotSimulation;
-use linefollower_core::{geometry::closed_path::ClosedPath, ode_solver::ode_system::Vector};
-use std::sync::Arc;
+use linefollower_core::geometry::track::{SharedTrack, Track};
+use linefollower_core::ode_solver::ode_system::Vector;
+use linefollower_core::simulation::robot::{RobotSimulation, OPTIMIZED_CONFIG};
+
+/// What the optimizer maximizes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectiveKind {
+    /// Velocity along the path minus the tracking errors, integrated over the run
+    IntegratedError,
+    /// Negative of the largest distance to the path during the run, so that the gains
+    /// keep the robot close to the line at all times, even at some cost on average
+    MinMaxError,
+}
+
+/// Weights of the terms integrated by the `IntegratedError` objective, to trade speed for
+/// accuracy. The default ones are those the optimized parameters were found with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FitnessWeights {
+    /// Of the reward for the velocity along the path
+    pub velocity: f64,
+    /// Of the error the controller sees
+    pub position_error: f64,
+    /// Of the squared distance to the path
+    pub distance_error: f64,
+}
+
+impl Default for FitnessWeights {
+    fn default() -> Self {
+        Self {
+            velocity: 1.0,
+            position_error: 1.0,
+            distance_error: 100.0,
+        }
+    }
+}
+
+/// How the fitness of a candidate on each of the tracks is combined into a single one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitnessAggregation {
+    /// Average over the tracks
+    Mean,
+    /// Worst over the tracks, so that the parameters don't give up on any of them
+    Min,
+}
+
+/// When to stop the optimization before CMA-ES terminates by itself. The default one never
+/// does, like the optimization always did
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ConvergenceConfig {
+    /// Smallest improvement of the best fitness which counts as progress
+    pub epsilon: f64,
+    /// Generations without progress after which the optimization stops, if any
+    pub patience: Option<usize>,
+}
+
+/// Tells when the best fitness found by the optimizer stops improving
+struct ConvergenceTracker {
+    config: ConvergenceConfig,
+    /// Best fitness at the last generation which made progress
+    best: f64,
+    stale_generations: usize,
+}
+
+impl ConvergenceTracker {
+    fn new(config: ConvergenceConfig) -> Self {
+        Self {
+            config,
+            best: f64::NEG_INFINITY,
+            stale_generations: 0,
+        }
+    }
+
+    /// Records the best fitness found after a generation, returning whether the optimization
+    /// has gone `patience` generations without improving it by more than `epsilon`
+    fn update(&mut self, best: f64) -> bool {
+        if best > self.best + self.config.epsilon {
+            self.best = best;
+            self.stale_generations = 0;
+        } else {
+            self.stale_generations += 1;
+        }
+        self.config
+            .patience
+            .map_or(false, |patience| self.stale_generations >= patience)
+    }
+}
+
+/// Outcome of `RobotOptimizer::find_optimal_multithreaded`
+#[derive(Debug, Clone)]
+pub struct OptimizationResult {
+    /// Best `[kp, ki, kd, speed]` found
+    pub best: cmaes::DVector<f64>,
+    /// Fitness of `best`, before clamping it to the bounds
+    pub best_fitness: f64,
+    /// Generations CMA-ES ran for
+    pub generations: usize,
+    /// Whether the `ConvergenceConfig` stopped the optimization, after `patience` generations
+    /// without progress
+    pub stopped_early: bool,
+}
+
+/// Default lower and upper bounds of `[kp, ki, kd, speed]`: non-negative gains, and a robot
+/// which moves forward, both well around the optimized parameters
+pub const DEFAULT_BOUNDS: [(f64, f64); 4] = [(0.0, 100.0), (0.0, 500.0), (0.0, 100.0), (0.1, 5.0)];
+
+/// Lowest fitness of a run, that of runs which diverge or whose errors grow out of bounds
+pub const DIVERGED_FITNESS: f64 = -1e12;
+
+/// Fitness lost per squared unit a candidate is out of bounds by
+const OUT_OF_BOUNDS_PENALTY: f64 = 1e3;
+/// Largest penalty of a candidate, so that those far out of bounds still get a finite fitness
+const MAX_PENALTY: f64 = 1e12;
 
 pub struct RobotOptimizer {
     max_iter: usize,
-    path: Arc<ClosedPath<f64>>,
+    /// Every candidate runs on each of these
+    paths: Vec<SharedTrack>,
     dt: f64,
+    objective: ObjectiveKind,
+    weights: FitnessWeights,
+    aggregation: FitnessAggregation,
+    bounds: [(f64, f64); 4],
+    /// Whether to save a plot of the optimization once it is over
+    save_plot: bool,
 }
 // PID Constants
-const KP: f64 = 3.130480505558367; //2.565933287511912; //3.49;
-const KI: f64 = 73.01770822094774; //52.33814267275805; //37.46;
-const KD: f64 = 11.273635752474997; //10.549477731373042; //13.79;
-const SPEED: f64 = 1.6710281486754923; //1.4602563968294984; //1.04;
+const KP: f64 = OPTIMIZED_CONFIG.kp;
+const KI: f64 = OPTIMIZED_CONFIG.ki;
+const KD: f64 = OPTIMIZED_CONFIG.kd;
+const SPEED: f64 = OPTIMIZED_CONFIG.speed;
 impl RobotOptimizer {
-    pub fn new(max_iter: usize, dt: f64, path: Arc<ClosedPath<f64>>) -> Self {
-        Self { max_iter, path, dt }
+    /// Runs each candidate on all of `paths`, averaging its fitness over them unless told
+    /// otherwise with `with_aggregation`
+    pub fn new(max_iter: usize, dt: f64, paths: Vec<SharedTrack>, weights: FitnessWeights) -> Self {
+        assert!(!paths.is_empty(), "there must be a track to optimize on");
+        Self {
+            max_iter,
+            paths,
+            dt,
+            objective: ObjectiveKind::IntegratedError,
+            weights,
+            aggregation: FitnessAggregation::Mean,
+            bounds: DEFAULT_BOUNDS,
+            save_plot: true,
+        }
+    }
+
+    pub fn with_objective(mut self, objective: ObjectiveKind) -> Self {
+        self.objective = objective;
+        self
+    }
+
+    pub fn with_aggregation(mut self, aggregation: FitnessAggregation) -> Self {
+        self.aggregation = aggregation;
+        self
+    }
+
+    /// Whether to save a plot of the optimization to `plot_<date>.png`, which it does by default
+    pub fn with_plot(mut self, save_plot: bool) -> Self {
+        self.save_plot = save_plot;
+        self
     }
 
+    /// Lower and upper bounds of `[kp, ki, kd, speed]`, instead of `DEFAULT_BOUNDS`
+    pub fn with_bounds(mut self, bounds: [(f64, f64); 4]) -> Self {
+        assert!(
+            bounds
+                .iter()
+                .all(|(lower, upper)| lower.is_finite() && upper.is_finite() && lower <= upper),
+            "bounds must be finite, the lower ones not exceeding the upper ones: {:?}",
+            bounds
+        );
+        self.bounds = bounds;
+        self
+    }
+
+    /// The closest parameters to `x` within the bounds, and the penalty for how far out of them
+    /// `x` is, so that the fitness of out-of-range candidates still leads the optimizer back
+    fn clamp_to_bounds(&self, x: [f64; 4]) -> ([f64; 4], f64) {
+        let mut clamped = x;
+        let mut penalty = 0.0;
+        for (xi, &(lower, upper)) in clamped.iter_mut().zip(&self.bounds) {
+            let inside = xi.clamp(lower, upper);
+            penalty += OUT_OF_BOUNDS_PENALTY * (*xi - inside).powi(2);
+            *xi = inside;
+        }
+        // also caps the infinite penalty of infinite candidates
+        (clamped, penalty.min(MAX_PENALTY))
+    }
+
+    /// Fitness of a candidate, which is simulated clamped to the bounds and penalized for how
+    /// far out of them it is. It is never below `DIVERGED_FITNESS - MAX_PENALTY`, and never NaN,
+    /// which would throw the optimizer off
     fn evaluate_fitness(&self, kp: f64, ki: f64, kd: f64, speed: f64) -> f64 {
-        let x0 = Vector::<7>::from_column_slice(&[0.0, -4.0, 0.1, 0.0, 0.0, 0.0, 0.0]);
-        let mut robot_sim = RobotSimulation::new(x0, kp, ki, kd, speed, self.path.clone());
-        let mut fitness = 0.0;
-        for _ in 0..self.max_iter {
-            let e = robot_sim.robot_error();
-            let dist_err = robot_sim.robot_sdf_to_path();
-            let dist_err = dist_err * dist_err;
-            let ve = robot_sim.robot_velocity_reward();
-            fitness += (ve - e - 100.0 * dist_err) * self.dt;
-            robot_sim.step(self.dt);
+        let candidate = [kp, ki, kd, speed];
+        if candidate.iter().any(|x| x.is_nan()) {
+            return DIVERGED_FITNESS - MAX_PENALTY;
+        }
+        let ([kp, ki, kd, speed], penalty) = self.clamp_to_bounds(candidate);
+        // max ignores the NaN of diverged runs
+        self.simulate_fitness(kp, ki, kd, speed)
+            .max(DIVERGED_FITNESS)
+            - penalty
+    }
+
+    /// Fitness of the runs with the given parameters on all the tracks, aggregated,
+    /// or NaN if any of them diverges
+    fn simulate_fitness(&self, kp: f64, ki: f64, kd: f64, speed: f64) -> f64 {
+        let fitnesses = self
+            .paths
+            .iter()
+            .map(|path| self.simulate_fitness_on(path, kp, ki, kd, speed));
+        match self.aggregation {
+            FitnessAggregation::Mean => fitnesses.sum::<f64>() / self.paths.len() as f64,
+            // unlike f64::min, keeps the NaN of diverged runs
+            FitnessAggregation::Min => fitnesses.fold(f64::INFINITY, |worst, fitness| {
+                if fitness.is_nan() || fitness < worst {
+                    fitness
+                } else {
+                    worst
+                }
+            }),
         }
-        fitness
     }
 
-    pub fn find_optimal_multithreaded(&self) -> cmaes::DVector<f64> {
+    /// Fitness of a run on `path` with the given parameters, or NaN if it diverges
+    fn simulate_fitness_on(
+        &self,
+        path: &SharedTrack,
+        kp: f64,
+        ki: f64,
+        kd: f64,
+        speed: f64,
+    ) -> f64 {
+        // at the start of the path, a little off its heading
+        let (p, t) = (path.point_at(0.0), path.tangent_at(0.0));
+        let theta = t.y.atan2(t.x) + 0.1;
+        let x0 = Vector::<7>::from_column_slice(&[p.x, p.y, theta, 0.0, 0.0, 0.0, 0.0]);
+        // in phase with the robot on any track, and not only where it starts at the path's start
+        let mut robot_sim =
+            RobotSimulation::new(x0, kp, ki, kd, speed, path.clone()).with_synced_reference();
+        match self.objective {
+            ObjectiveKind::IntegratedError => {
+                let w = self.weights;
+                let mut fitness = 0.0;
+                for _ in 0..self.max_iter {
+                    let e = robot_sim.robot_error();
+                    let dist_err = robot_sim.robot_sdf_to_path();
+                    let dist_err = dist_err * dist_err;
+                    let ve = robot_sim.robot_velocity_reward();
+                    fitness +=
+                        (w.velocity * ve - w.position_error * e - w.distance_error * dist_err)
+                            * self.dt;
+                    robot_sim.step(self.dt);
+                    if robot_sim.is_diverged() {
+                        return f64::NAN;
+                    }
+                }
+                fitness
+            }
+            ObjectiveKind::MinMaxError => {
+                let mut max_error = 0.0f64;
+                for _ in 0..self.max_iter {
+                    max_error = max_error.max(robot_sim.robot_sdf_to_path().abs());
+                    robot_sim.step(self.dt);
+                    if robot_sim.is_diverged() {
+                        return f64::NAN;
+                    }
+                }
+                -max_error
+            }
+        }
+    }
+
+    pub fn find_optimal_multithreaded(&self, convergence: ConvergenceConfig) -> OptimizationResult {
+        self.find_optimal_with_progress(convergence, |_, _| {})
+    }
+
+    /// Like `find_optimal_multithreaded`, calling `progress` after each generation with the
+    /// number of generations run so far and the best fitness found
+    pub fn find_optimal_with_progress<P>(
+        &self,
+        convergence: ConvergenceConfig,
+        mut progress: P,
+    ) -> OptimizationResult
+    where
+        P: FnMut(usize, f64),
+    {
         let x0 = vec![KP, KI, KD, SPEED];
         let mut cmaes_state = CMAESOptions::new(x0, 0.1)
             .mode(cmaes::Mode::Maximize)
@@ -43,16 +296,37 @@ impl RobotOptimizer {
             .enable_printing(1000)
             .build(self)
             .unwrap();
-        let soln = cmaes_state.run_parallel();
-        // get date and time to put in filename
-        let now = chrono::Local::now();
-        let filename = format!("plot_{}.png", now.format("%Y-%m-%d_%H-%M-%S"));
-        cmaes_state
-            .get_plot()
-            .unwrap()
-            .save_to_file(filename, true)
-            .unwrap();
-        soln.overall_best.unwrap().point
+        let mut tracker = ConvergenceTracker::new(convergence);
+        let (best, best_fitness, stopped_early) = loop {
+            if let Some(soln) = cmaes_state.next_parallel() {
+                let best = soln.overall_best.unwrap();
+                progress(cmaes_state.generation(), best.value);
+                break (best.point, best.value, false);
+            }
+            let best = cmaes_state.overall_best_individual().unwrap();
+            progress(cmaes_state.generation(), best.value);
+            if tracker.update(best.value) {
+                break (best.point.clone(), best.value, true);
+            }
+        };
+        if self.save_plot {
+            // get date and time to put in filename
+            let now = chrono::Local::now();
+            let filename = format!("plot_{}.png", now.format("%Y-%m-%d_%H-%M-%S"));
+            cmaes_state
+                .get_plot()
+                .unwrap()
+                .save_to_file(filename, true)
+                .unwrap();
+        }
+        // the best candidate may be out of bounds, in which case the clamped one was simulated
+        let (best, _) = self.clamp_to_bounds([best[0], best[1], best[2], best[3]]);
+        OptimizationResult {
+            best: cmaes::DVector::from_column_slice(&best),
+            best_fitness,
+            generations: cmaes_state.generation(),
+            stopped_early,
+        }
     }
 }
 
@@ -87,3 +361,206 @@ impl<'a> ParallelObjectiveFunction for &'a RobotOptimizer {
         RobotOptimizer::evaluate_parallel(*self, x)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linefollower_core::geometry::closed_path::{predefined_circle, predefined_closed_path};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_min_max_error_objective_is_the_negative_peak_error() {
+        let path: SharedTrack = Arc::new(predefined_closed_path());
+        let (max_iter, dt) = (2400, 1.0 / 240.0);
+        let optimizer =
+            RobotOptimizer::new(max_iter, dt, vec![path.clone()], FitnessWeights::default())
+                .with_objective(ObjectiveKind::MinMaxError);
+        let fitness = optimizer.evaluate_fitness(KP, KI, KD, SPEED);
+
+        let x0 = Vector::<7>::from_column_slice(&[0.0, -4.0, 0.1, 0.0, 0.0, 0.0, 0.0]);
+        let mut robot_sim = RobotSimulation::new(x0, KP, KI, KD, SPEED, path);
+        let mut peak_error = 0.0f64;
+        for _ in 0..max_iter {
+            peak_error = peak_error.max(robot_sim.robot_sdf_to_path().abs());
+            robot_sim.step(dt);
+        }
+        assert!(peak_error > 0.0);
+        assert_eq!(fitness, -peak_error);
+    }
+
+    #[test]
+    fn test_diverging_and_out_of_bounds_candidates_get_a_finite_low_fitness() {
+        let path: SharedTrack = Arc::new(predefined_closed_path());
+        let (max_iter, dt) = (2400, 1.0 / 240.0);
+        // so huge that the state becomes non-finite right away
+        let (kp, ki, kd, speed) = (1e300, 0.0, 1e300, 1e10);
+        for objective in [ObjectiveKind::IntegratedError, ObjectiveKind::MinMaxError] {
+            let unbounded =
+                RobotOptimizer::new(max_iter, dt, vec![path.clone()], FitnessWeights::default())
+                    .with_objective(objective)
+                    .with_bounds([(-1e300, 1e300); 4]);
+            assert!(unbounded.simulate_fitness(kp, ki, kd, speed).is_nan());
+            assert_eq!(
+                unbounded.evaluate_fitness(kp, ki, kd, speed),
+                DIVERGED_FITNESS
+            );
+
+            // clamped to the default bounds, the same candidate doesn't diverge, but does
+            // worse than the closest one within them
+            let bounded =
+                RobotOptimizer::new(max_iter, dt, vec![path.clone()], FitnessWeights::default())
+                    .with_objective(objective);
+            let fitness = bounded.evaluate_fitness(kp, ki, kd, speed);
+            assert!(fitness.is_finite());
+            let [(_, kp_max), _, (_, kd_max), (_, speed_max)] = DEFAULT_BOUNDS;
+            let closest = bounded.evaluate_fitness(kp_max, ki, kd_max, speed_max);
+            assert!(closest > DIVERGED_FITNESS);
+            assert!(fitness < closest - 1e6, "{} vs {}", fitness, closest);
+
+            assert_eq!(
+                bounded.evaluate_fitness(f64::NAN, ki, kd, speed),
+                DIVERGED_FITNESS - MAX_PENALTY
+            );
+        }
+    }
+
+    #[test]
+    fn test_convergence_tracker_stops_after_patience_stale_generations() {
+        let mut tracker = ConvergenceTracker::new(ConvergenceConfig {
+            epsilon: 0.1,
+            patience: Some(3),
+        });
+        // improvements of at most epsilon don't count, but add up until they do
+        let stops = [1.0, 2.0, 2.05, 2.09, 2.15, 2.2, 2.22, 2.24].map(|best| tracker.update(best));
+        assert_eq!(
+            stops,
+            [false, false, false, false, false, false, false, true]
+        );
+
+        // by default, the optimization goes on for as long as CMA-ES does
+        let mut tracker = ConvergenceTracker::new(ConvergenceConfig::default());
+        assert!((0..1000).all(|_| !tracker.update(1.0)));
+    }
+
+    #[test]
+    fn test_worst_track_fitness_favors_parameters_which_do_well_on_both() {
+        let (max_iter, dt) = (2400, 1.0 / 240.0);
+        let default: SharedTrack = Arc::new(predefined_closed_path());
+        // much tighter than any turn of the default track
+        let tight_circle: SharedTrack = Arc::new(predefined_circle(0.3));
+        let fitness_on = |paths: Vec<SharedTrack>, aggregation, candidate| {
+            let (kp, ki, kd, speed) = candidate;
+            RobotOptimizer::new(max_iter, dt, paths, FitnessWeights::default())
+                .with_aggregation(aggregation)
+                .evaluate_fitness(kp, ki, kd, speed)
+        };
+        // a search over a few candidates stands in for CMA-ES
+        let candidates = [
+            (KP, KI, KD, SPEED),
+            (20.0, 0.0, 10.0, 2.0),
+            (10.0, 0.0, 5.0, 1.0),
+            (5.0, 20.0, 5.0, 1.0),
+        ];
+        let best = |paths: Vec<SharedTrack>, aggregation| {
+            let fitness = |&candidate: &(f64, f64, f64, f64)| {
+                fitness_on(paths.clone(), aggregation, candidate)
+            };
+            *candidates
+                .iter()
+                .max_by(|a, b| fitness(a).partial_cmp(&fitness(b)).unwrap())
+                .unwrap()
+        };
+        let on = |path: &SharedTrack, candidate| {
+            fitness_on(vec![path.clone()], FitnessAggregation::Mean, candidate)
+        };
+
+        let single_track = best(vec![default.clone()], FitnessAggregation::Mean);
+        let both_tracks = best(
+            vec![default.clone(), tight_circle.clone()],
+            FitnessAggregation::Min,
+        );
+        assert_ne!(single_track, both_tracks);
+        assert!(on(&default, both_tracks) > 0.0);
+        assert!(on(&tight_circle, both_tracks) > 0.0);
+        assert!(on(&tight_circle, single_track) < 0.0);
+
+        let mean = fitness_on(
+            vec![default.clone(), tight_circle.clone()],
+            FitnessAggregation::Mean,
+            both_tracks,
+        );
+        let expected = (on(&default, both_tracks) + on(&tight_circle, both_tracks)) / 2.0;
+        assert!((mean - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_progress_is_reported_once_per_generation() {
+        let path: SharedTrack = Arc::new(predefined_closed_path());
+        // short runs, and a patience which stops after a few generations, to keep the test quick
+        let optimizer = RobotOptimizer::new(48, 1.0 / 240.0, vec![path], FitnessWeights::default())
+            .with_plot(false);
+        let convergence = ConvergenceConfig {
+            epsilon: 1e9,
+            patience: Some(3),
+        };
+        let mut reports = Vec::new();
+        let result = optimizer.find_optimal_with_progress(convergence, |generation, best| {
+            reports.push((generation, best));
+        });
+        assert!(result.stopped_early);
+        assert_eq!(reports.len(), result.generations);
+        assert_eq!(reports.last().unwrap().1, result.best_fitness);
+        assert!(reports
+            .iter()
+            .enumerate()
+            .all(|(i, &(generation, _))| generation == i + 1));
+        // the best fitness so far can only go up when maximizing
+        assert!(reports.windows(2).all(|w| w[1].1 >= w[0].1));
+    }
+
+    #[test]
+    fn test_distance_weight_favors_parameters_closer_to_the_path() {
+        let path: SharedTrack = Arc::new(predefined_closed_path());
+        let (max_iter, dt) = (7200, 1.0 / 240.0);
+        let mean_distance = |&(kp, ki, kd, speed): &(f64, f64, f64, f64)| {
+            let x0 = Vector::<7>::from_column_slice(&[0.0, -4.0, 0.1, 0.0, 0.0, 0.0, 0.0]);
+            let mut robot_sim =
+                RobotSimulation::new(x0, kp, ki, kd, speed, path.clone()).with_synced_reference();
+            let mut total = 0.0;
+            for _ in 0..max_iter {
+                robot_sim.step(dt);
+                total += robot_sim.robot_sdf_to_path().abs();
+            }
+            total / max_iter as f64
+        };
+        // from the fastest to the most careful
+        let candidates = [
+            (KP, KI, KD, SPEED),
+            (20.0, 0.0, 10.0, 2.0),
+            (10.0, 0.0, 5.0, 1.0),
+            (20.0, 0.0, 10.0, 0.5),
+        ];
+        let best = |weights: FitnessWeights| {
+            let optimizer = RobotOptimizer::new(max_iter, dt, vec![path.clone()], weights);
+            let fitness = |&(kp, ki, kd, speed): &(f64, f64, f64, f64)| {
+                optimizer.evaluate_fitness(kp, ki, kd, speed)
+            };
+            *candidates
+                .iter()
+                .max_by(|a, b| fitness(a).partial_cmp(&fitness(b)).unwrap())
+                .unwrap()
+        };
+        let default = best(FitnessWeights::default());
+        assert_eq!(default, candidates[0]);
+        let careful = best(FitnessWeights {
+            distance_error: 1e6,
+            ..FitnessWeights::default()
+        });
+        assert!(
+            mean_distance(&careful) < mean_distance(&default),
+            "{:?} vs {:?}",
+            careful,
+            default
+        );
+    }
+}
@@ -1,16 +1,29 @@
 use crate::{
     canvas::Canvas,
-    curve_graph::{AddSubPath, CurveGraph},
+    curve_graph::{AddSubPath, AddTrack, CurveGraph, RemoveSubPaths},
     tools::{arc_tool::ArcPathTool, line_tool::LinePathTool, select_tool::SelectTool, tool::Tool},
 };
 use egui::*;
-use linefollower_core::{geometry::track::Track, utils::math::sigmoid};
+use linefollower_core::{
+    geometry::{track::Track, track_file::load_track_auto},
+    utils::math::sigmoid,
+};
 use petgraph::prelude::DiGraph;
 
+/// Distance on the screen within which a click lands on an existing node
+const SNAP_RADIUS: f32 = 30.0;
+
 pub struct PathEditorApp {
     canvas: Canvas,
     tool: Tool,
     curve_graph: CurveGraph,
+    /// Why the last track couldn't be loaded, if it couldn't
+    load_error: Option<String>,
+    /// Why the last subpath drawn with a tool couldn't be added, if it couldn't
+    tool_error: Option<String>,
+    /// Whether to show the cursor position, in world coordinates, over the canvas
+    show_coordinates: bool,
+    coordinate_decimals: usize,
 }
 
 impl PathEditorApp {
@@ -19,8 +32,49 @@ impl PathEditorApp {
             tool: Tool::new(),
             canvas: Canvas::default(),
             curve_graph: DiGraph::new(),
+            load_error: None,
+            tool_error: None,
+            show_coordinates: true,
+            coordinate_decimals: 3,
         }
     }
+
+    /// Node within `SNAP_RADIUS` of the screen position `pos`, in world coordinates
+    fn snap_point(&self, painter: &Painter, pos: Pos2) -> Option<Pos2> {
+        self.curve_graph
+            .raw_nodes()
+            .iter()
+            .map(|node| Pos2::from(node.weight))
+            .find(|&p| self.canvas.to_screen(painter, p).distance(pos) <= SNAP_RADIUS)
+    }
+
+    /// Cursor position in world coordinates and, if it would snap, the node it snaps to
+    fn draw_coordinate_readout(&self, painter: &Painter, hover_pos: Pos2) {
+        let decimals = self.coordinate_decimals;
+        let world = self.canvas.to_world(painter, hover_pos);
+        let mut readout = format!("x: {:.*}  y: {:.*}", decimals, world.x, decimals, world.y);
+        let snapped = if self.tool.snaps_next_click() {
+            self.snap_point(painter, hover_pos)
+        } else {
+            None
+        };
+        if let Some(snapped) = snapped {
+            readout += &format!(
+                "\nsnaps to x: {:.*}  y: {:.*}",
+                decimals, snapped.x, decimals, snapped.y
+            );
+            let highlight = Stroke::new(1.5, Color32::LIGHT_BLUE);
+            self.canvas
+                .draw_circle(painter, highlight, snapped, SNAP_RADIUS);
+        }
+        painter.text(
+            painter.clip_rect().left_top() + vec2(10.0, 10.0),
+            Align2::LEFT_TOP,
+            readout,
+            FontId::monospace(14.0),
+            Color32::WHITE,
+        );
+    }
 }
 
 impl eframe::App for PathEditorApp {
@@ -79,25 +133,26 @@ impl eframe::App for PathEditorApp {
                 ui.expand_to_include_rect(painter.clip_rect());
                 // check for mouse click
                 if response.hovered() {
-                    const SNAP_RADIUS: f32 = 30.0;
                     if ui.input(|i| i.pointer.primary_clicked()) {
                         let pos = ui.input(|i| i.pointer.interact_pos());
-                        if let Some(mut pos) = pos {
-                            // snapping
-                            for node in self.curve_graph.raw_nodes().iter() {
-                                let snap_point =
-                                    self.canvas.to_screen(&painter, node.weight.into());
-                                let distance = snap_point.distance(pos);
-                                if distance <= SNAP_RADIUS {
-                                    pos = snap_point;
-                                    break;
+                        if let Some(pos) = pos {
+                            // snapping, to the exact position of the node so that
+                            // the new subpath is connected to it in the graph
+                            let snapped = if self.tool.snaps_next_click() {
+                                self.snap_point(&painter, pos)
+                            } else {
+                                None
+                            };
+                            let pos =
+                                snapped.unwrap_or_else(|| self.canvas.to_world(&painter, pos));
+                            match self.tool.on_click(pos) {
+                                Some(Ok(subpath)) => {
+                                    self.curve_graph.add_subpath(subpath);
+                                    self.tool_error = None;
+                                    response.mark_changed();
                                 }
-                            }
-                            let pos = self.canvas.to_world(&painter, pos);
-                            let subpath = self.tool.on_click(pos);
-                            if let Some(subpath) = subpath {
-                                self.curve_graph.add_subpath(subpath);
-                                response.mark_changed();
+                                Some(Err(e)) => self.tool_error = Some(e.to_string()),
+                                None => {}
                             }
                         }
                     }
@@ -111,11 +166,26 @@ impl eframe::App for PathEditorApp {
                             SNAP_RADIUS,
                         );
                     }
+                    if self.show_coordinates {
+                        if let Some(hover_pos) = ui.input(|i| i.pointer.hover_pos()) {
+                            self.draw_coordinate_readout(&painter, hover_pos);
+                        }
+                    }
                 }
                 ui.input(|i| {
                     self.tool
                         .on_input(&response, i, ui, &self.canvas, &painter, &self.curve_graph)
                 });
+                // delete the selected subpaths
+                if let Tool::Select(ref mut select) = self.tool {
+                    if ui.input(|i| i.key_pressed(Key::Delete)) {
+                        let edges = select.take_selected_edges();
+                        if !edges.is_empty() {
+                            self.curve_graph.remove_subpaths(&edges);
+                            response.mark_changed();
+                        }
+                    }
+                }
                 self.canvas.draw_subpaths(
                     &painter,
                     self.curve_graph.raw_edges().iter().map(|edge| &edge.weight),
@@ -178,6 +248,40 @@ impl eframe::App for PathEditorApp {
             {
                 self.tool = Tool::Select(SelectTool::default());
             }
+            if let Some(ref tool_error) = self.tool_error {
+                ui.colored_label(
+                    Color32::RED,
+                    format!("Couldn't add the subpath: {}", tool_error),
+                );
+            }
+            ui.separator();
+            ui.checkbox(&mut self.show_coordinates, "Show coordinates");
+            ui.add_enabled(
+                self.show_coordinates,
+                Slider::new(&mut self.coordinate_decimals, 0..=6).text("Decimals"),
+            );
+            ui.separator();
+            // the loaded track is added to the canvas, on top of what is already there
+            if ui.button("Load track").clicked() {
+                let filename = rfd::FileDialog::new()
+                    .add_filter("Track", &["json", "svg"])
+                    .pick_file();
+                if let Some(filename) = filename {
+                    match load_track_auto(filename) {
+                        Ok(track) => {
+                            self.curve_graph.add_track(&track);
+                            self.load_error = None;
+                        }
+                        Err(e) => self.load_error = Some(e.to_string()),
+                    }
+                }
+            }
+            if let Some(ref load_error) = self.load_error {
+                ui.colored_label(
+                    Color32::RED,
+                    format!("Couldn't load the track: {}", load_error),
+                );
+            }
         });
         egui::Window::new("Subpaths").show(ctx, |ui| {
             for subpath in self.curve_graph.raw_edges().iter().map(|edge| &edge.weight) {
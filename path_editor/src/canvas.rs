@@ -37,6 +37,10 @@ pub fn generate_displayable_points(subpath: &SubPath<f64>) -> Vec<Pos2> {
         SubPath::Line(line) => {
             vec![line.p0.into_pos2(), line.p1.into_pos2()]
         }
+        SubPath::Spline(spline) => spline
+            .sample_points_num(100 * spline.waypoints().len())
+            .map(|p| p.into_pos2())
+            .collect::<Vec<_>>(),
     }
 }
 
@@ -5,7 +5,7 @@ use linefollower_core::geometry::track::Track;
 use linefollower_core::{geometry::closed_path::SubPath, utils::traits::Float};
 use mint::Point2;
 use petgraph::prelude::DiGraph;
-use petgraph::stable_graph::NodeIndex;
+use petgraph::stable_graph::{EdgeIndex, NodeIndex};
 use petgraph::visit::EdgeRef;
 
 pub type CurveGraph = DiGraph<Point2<f32>, SubPath<f64>>;
@@ -14,11 +14,11 @@ pub trait AddSubPath<F>
 where
     F: Float,
 {
-    fn add_subpath(&mut self, subpath: SubPath<F>);
+    fn add_subpath(&mut self, subpath: SubPath<F>) -> EdgeIndex;
 }
 
 impl AddSubPath<f64> for CurveGraph {
-    fn add_subpath(&mut self, subpath: SubPath<f64>) {
+    fn add_subpath(&mut self, subpath: SubPath<f64>) -> EdgeIndex {
         let p0: Point2<f32> = subpath.first_point().cast::<f32>().into();
         let p1: Point2<f32> = subpath.last_point().cast::<f32>().into();
         const MIN_DISTANCE_SQR: f32 = 1e-10;
@@ -27,9 +27,6 @@ impl AddSubPath<f64> for CurveGraph {
         // before adding the nodes, check if they are close enough to existing nodes
         for node in self.node_indices() {
             let p = self[node];
-            fn distance_sqr(p0: Point2<f32>, p1: Point2<f32>) -> f32 {
-                (p0.x - p1.x).powi(2) + (p0.y - p1.y).powi(2)
-            }
             if distance_sqr(p, p0) <= MIN_DISTANCE_SQR {
                 i0 = Some(node);
             }
@@ -39,9 +36,70 @@ impl AddSubPath<f64> for CurveGraph {
         }
         // add nodes if they don't exist
         let i0 = i0.unwrap_or_else(|| self.add_node(p0));
-        let i1 = i1.unwrap_or_else(|| self.add_node(p1));
+        // a subpath which closes on itself, like a spline path, starts and ends at the same node
+        let i1 = match i1 {
+            Some(i1) => i1,
+            None if distance_sqr(p0, p1) <= MIN_DISTANCE_SQR => i0,
+            None => self.add_node(p1),
+        };
         // add the edge
-        self.add_edge(i0, i1, subpath);
+        self.add_edge(i0, i1, subpath)
+    }
+}
+
+fn distance_sqr(p0: Point2<f32>, p1: Point2<f32>) -> f32 {
+    (p0.x - p1.x).powi(2) + (p0.y - p1.y).powi(2)
+}
+
+pub trait AddTrack {
+    fn add_track(&mut self, track: &ClosedPath<f64>) -> Vec<NodeIndex>;
+}
+
+impl AddTrack for CurveGraph {
+    /// Adds all the subpaths of `track`, merging their endpoints into the same nodes
+    /// like `add_subpath` does, so that they form a cycle.
+    ///
+    /// Returns the nodes at the start of each subpath, in order.
+    fn add_track(&mut self, track: &ClosedPath<f64>) -> Vec<NodeIndex> {
+        track
+            .subpaths()
+            .iter()
+            .map(|subpath| {
+                let edge = self.add_subpath(subpath.clone());
+                self.edge_endpoints(edge).unwrap().0
+            })
+            .collect()
+    }
+}
+
+pub trait RemoveSubPaths {
+    fn remove_subpaths(&mut self, edges: &[EdgeIndex]);
+}
+
+impl RemoveSubPaths for CurveGraph {
+    /// Removes the subpaths given by `edges`, along with the nodes which are
+    /// left without any subpath, so that they don't get in the way of `valid_track`.
+    fn remove_subpaths(&mut self, edges: &[EdgeIndex]) {
+        // removing an edge or a node moves the last one into its index,
+        // so going from the highest index down keeps the remaining ones valid
+        let mut edges = edges.to_vec();
+        edges.sort_unstable_by(|a, b| b.cmp(a));
+        edges.dedup();
+        let mut endpoints = Vec::with_capacity(2 * edges.len());
+        for edge in edges {
+            if let Some((source, target)) = self.edge_endpoints(edge) {
+                endpoints.push(source);
+                endpoints.push(target);
+                self.remove_edge(edge);
+            }
+        }
+        endpoints.sort_unstable_by(|a, b| b.cmp(a));
+        endpoints.dedup();
+        for node in endpoints {
+            if self.neighbors_undirected(node).next().is_none() {
+                self.remove_node(node);
+            }
+        }
     }
 }
 
@@ -56,7 +114,7 @@ impl ValidTrack for CurveGraph {
     /// This method checks if the nodes given by `node_indices` forms a valid track
     /// and, if it does, it returns the corresponding closed path.
     fn valid_track(&self, node_indices: &[NodeIndex]) -> Option<ClosedPath<f64>> {
-        if node_indices.len() < 2 {
+        if node_indices.is_empty() {
             return None;
         }
         let first = node_indices[0];
@@ -90,3 +148,105 @@ impl ValidTrack for CurveGraph {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linefollower_core::geometry::closed_path::predefined_tracks;
+    use linefollower_core::geometry::line_path::LinePath;
+    use linefollower_core::geometry::spline_path::SplinePath;
+    use linefollower_core::geometry::track_file::{track_from_json, track_to_json};
+
+    fn line(x0: f64, y0: f64, x1: f64, y1: f64) -> SubPath<f64> {
+        SubPath::Line(LinePath::new(
+            nalgebra::Point2::new(x0, y0),
+            nalgebra::Point2::new(x1, y1),
+        ))
+    }
+
+    #[test]
+    fn test_removing_subpaths_leaves_no_dangling_nodes() {
+        let mut graph = CurveGraph::new();
+        // a square track, plus a stray line hanging off one of its corners
+        graph.add_subpath(line(0.0, 0.0, 1.0, 0.0));
+        graph.add_subpath(line(1.0, 0.0, 1.0, 1.0));
+        graph.add_subpath(line(1.0, 1.0, 0.0, 1.0));
+        graph.add_subpath(line(0.0, 1.0, 0.0, 0.0));
+        graph.add_subpath(line(1.0, 1.0, 2.0, 2.0));
+        assert_eq!((graph.node_count(), graph.edge_count()), (5, 5));
+        // the corner has two outgoing edges because of the stray line
+        let nodes = graph.node_indices().take(4).collect::<Vec<_>>();
+        assert!(graph.valid_track(&nodes).is_none());
+
+        let stray = graph.edge_indices().last().unwrap();
+        graph.remove_subpaths(&[stray]);
+        assert_eq!((graph.node_count(), graph.edge_count()), (4, 4));
+        let nodes = graph.node_indices().collect::<Vec<_>>();
+        assert!(graph.valid_track(&nodes).is_some());
+
+        // the corners stay while other sides still go through them
+        let sides = graph.edge_indices().take(2).collect::<Vec<_>>();
+        graph.remove_subpaths(&sides);
+        assert_eq!((graph.node_count(), graph.edge_count()), (3, 2));
+        assert!(graph
+            .node_indices()
+            .all(|node| graph.neighbors_undirected(node).next().is_some()));
+        let rest = graph.edge_indices().collect::<Vec<_>>();
+        graph.remove_subpaths(&rest);
+        assert_eq!((graph.node_count(), graph.edge_count()), (0, 0));
+    }
+
+    #[test]
+    fn test_saved_tracks_load_back_into_the_graph() {
+        let mut tracks = predefined_tracks()
+            .into_iter()
+            .map(|(_, track)| track)
+            .collect::<Vec<_>>();
+        let waypoints = [(0.0, 0.0), (2.0, 0.0), (2.5, 1.5), (0.5, 2.0)]
+            .iter()
+            .map(|&(x, y)| nalgebra::Point2::new(x, y))
+            .collect();
+        tracks.push(SplinePath::new(waypoints).into());
+        for track in tracks {
+            let json = track_to_json(&track).unwrap();
+            let mut graph = CurveGraph::new();
+            let nodes = graph.add_track(&track_from_json(&json).unwrap());
+            // each junction is a single node
+            assert_eq!(graph.node_count(), track.subpaths().len());
+            assert_eq!(graph.edge_count(), track.subpaths().len());
+            let loaded = graph.valid_track(&nodes).unwrap();
+            let to_json = |subpaths: &[SubPath<f64>]| serde_json::to_string(subpaths).unwrap();
+            assert_eq!(to_json(loaded.subpaths()), to_json(track.subpaths()));
+        }
+    }
+
+    #[test]
+    fn test_loop_built_from_snapped_clicks_is_a_valid_track() {
+        use crate::utils::IntoPoint2;
+        use egui::Pos2;
+        use linefollower_core::geometry::arc_path::ArcPath;
+        // the editor snaps a click to the position of the node, as stored in the graph
+        let snapped = |graph: &CurveGraph, node: NodeIndex| -> nalgebra::Point2<f64> {
+            Pos2::from(graph[node]).into_point2()
+        };
+        let mut graph = CurveGraph::new();
+        let start = graph.add_subpath(line(0.0, 0.0, 1.0, 0.0));
+        let (first, corner) = graph.edge_endpoints(start).unwrap();
+        // a half circle out of the corner, its radius and angles computed from the click
+        // like the arc tool does, so its endpoints pick up rounding errors
+        let center = nalgebra::Point2::new(1.0, 0.5);
+        let v0 = snapped(&graph, corner) - center;
+        let theta0 = v0.y.atan2(v0.x);
+        let arc = ArcPath::new(center, v0.norm(), theta0, theta0 + std::f64::consts::PI);
+        let arc = graph.add_subpath(SubPath::Arc(arc));
+        let top = graph.edge_endpoints(arc).unwrap().1;
+        let p = snapped(&graph, top);
+        graph.add_subpath(line(p.x, p.y, 0.0, 1.0));
+        let last = graph.node_indices().last().unwrap();
+        let (p, q) = (snapped(&graph, last), snapped(&graph, first));
+        graph.add_subpath(line(p.x, p.y, q.x, q.y));
+        assert_eq!((graph.node_count(), graph.edge_count()), (4, 4));
+        let nodes = graph.node_indices().collect::<Vec<_>>();
+        assert!(graph.valid_track(&nodes).is_some());
+    }
+}
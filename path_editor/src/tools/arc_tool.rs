@@ -4,7 +4,7 @@ use crate::{canvas::Canvas, utils::IntoPos2};
 
 use super::super::utils::IntoPoint2;
 use egui::{Color32, InputState, Key, Painter, Pos2, Response, Stroke, Ui};
-use linefollower_core::geometry::{arc_path::ArcPath, closed_path::SubPath};
+use linefollower_core::geometry::{arc_path::ArcPath, closed_path::SubPath, error::GeometryError};
 use nalgebra::{Point2, Vector2};
 
 #[derive(PartialEq)]
@@ -40,7 +40,8 @@ impl ArcPathTool {
             self.counterclockwise = !self.counterclockwise;
         }
     }
-    pub fn on_click(&mut self, p: Pos2) -> Option<SubPath<f64>> {
+    /// The arc, once its center and both of its ends are placed
+    pub fn on_click(&mut self, p: Pos2) -> Option<Result<SubPath<f64>, GeometryError>> {
         match self.state {
             ArcPathToolState::Start => {
                 self.state = ArcPathToolState::CenterPoint;
@@ -61,15 +62,14 @@ impl ArcPathTool {
                 let mut theta1 = self.vector_angle(v1);
                 self.correct_angle(&mut theta1);
 
-                Some(SubPath::Arc(ArcPath::new(
-                    self.center,
-                    self.r,
-                    self.theta0,
-                    theta1,
-                )))
+                Some(ArcPath::try_new(self.center, self.r, self.theta0, theta1).map(SubPath::Arc))
             }
         }
     }
+    /// The first click places the center, which isn't part of the arc
+    pub fn snaps_next_click(&self) -> bool {
+        self.state != ArcPathToolState::Start
+    }
     pub fn draw(&self, ui: &Ui, canvas: &Canvas, painter: &Painter) {
         match self.state {
             ArcPathToolState::Start => {}
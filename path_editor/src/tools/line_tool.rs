@@ -1,6 +1,8 @@
 use crate::{canvas::Canvas, utils::IntoPos2};
 use egui::*;
-use linefollower_core::geometry::{closed_path::SubPath, line_path::LinePath};
+use linefollower_core::geometry::{
+    closed_path::SubPath, error::GeometryError, line_path::LinePath,
+};
 use nalgebra::Point2;
 
 use super::super::utils::IntoPoint2;
@@ -30,7 +32,8 @@ impl LinePathTool {
         }
     }
     pub fn on_input(&mut self, _response: &Response, _input: &InputState) {}
-    pub fn on_click(&mut self, p: Pos2) -> Option<SubPath<f64>> {
+    /// The line, once both of its endpoints are placed
+    pub fn on_click(&mut self, p: Pos2) -> Option<Result<SubPath<f64>, GeometryError>> {
         match self.state {
             LinePathToolState::Start => {
                 self.state = LinePathToolState::OnePoint;
@@ -40,7 +43,7 @@ impl LinePathTool {
             LinePathToolState::OnePoint => {
                 self.state = LinePathToolState::Start;
                 let p1 = p.into_point2();
-                Some(SubPath::Line(LinePath::new(self.p0, p1)))
+                Some(LinePath::try_new(self.p0, p1).map(SubPath::Line))
             }
         }
     }
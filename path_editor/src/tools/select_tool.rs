@@ -4,8 +4,9 @@ use crate::{
 };
 use egui::{Color32, InputState, Painter, Pos2, Response, Ui};
 use linefollower_core::geometry::closed_path::{ClosedPath, SubPath};
+use linefollower_core::geometry::track_file::track_to_json;
 use mint::Point2;
-use petgraph::stable_graph::NodeIndex;
+use petgraph::stable_graph::{EdgeIndex, NodeIndex};
 use std::io::prelude::*;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -20,6 +21,8 @@ pub struct SelectTool {
     closed_path: Option<ClosedPath<f64>>,
     closed_path_json: Option<String>,
     save_file_name: String,
+    /// Subpaths within the last selection, which the Delete key removes
+    selected_edges: Vec<EdgeIndex>,
 }
 
 impl SelectTool {
@@ -30,11 +33,18 @@ impl SelectTool {
             closed_path: None,
             closed_path_json: None,
             save_file_name: String::new(),
+            selected_edges: Vec::new(),
         }
     }
     pub fn ui(&mut self, ui: &mut Ui) {
         ui.label("Selected Track");
         ui.separator();
+        if !self.selected_edges.is_empty() {
+            ui.label(format!(
+                "{} subpath(s) selected, press Delete to remove them",
+                self.selected_edges.len()
+            ));
+        }
         match self.closed_path_json {
             Some(ref mut closed_path_json) => {
                 ui.text_edit_singleline(&mut self.save_file_name);
@@ -91,9 +101,12 @@ impl SelectTool {
             }
             SelectToolState::OnceClicked => {
                 if response.hovered() && input.pointer.primary_clicked() {
+                    self.selected_edges = self
+                        .selected_edges(ui, canvas, painter, graph)
+                        .unwrap_or_default();
                     self.closed_path = self.selected_track(ui, canvas, painter, graph);
                     if let Some(ref closed_path) = self.closed_path {
-                        let json = serde_json::to_string_pretty(closed_path).unwrap();
+                        let json = track_to_json(closed_path).unwrap();
                         self.closed_path_json = Some(json);
                     }
                     self.state = SelectToolState::Start;
@@ -163,6 +176,30 @@ impl SelectTool {
             }
         }
     }
+    /// Edges of the subpaths whose endpoints are both among the selected points
+    pub fn selected_edges(
+        &self,
+        ui: &Ui,
+        canvas: &Canvas,
+        painter: &Painter,
+        graph: &CurveGraph,
+    ) -> Option<Vec<EdgeIndex>> {
+        let selected_points = self.selected_points(ui, canvas, painter, graph)?;
+        let selected_edges = graph
+            .edge_indices()
+            .filter(|&edge| {
+                let (source, target) = graph.edge_endpoints(edge).unwrap();
+                selected_points.contains(&source) && selected_points.contains(&target)
+            })
+            .collect();
+        Some(selected_edges)
+    }
+    /// Empties the selection, returning the subpaths it had so they can be removed
+    pub fn take_selected_edges(&mut self) -> Vec<EdgeIndex> {
+        self.closed_path = None;
+        self.closed_path_json = None;
+        std::mem::take(&mut self.selected_edges)
+    }
     pub fn selected_track(
         &self,
         ui: &Ui,
@@ -1,5 +1,5 @@
 use egui::{InputState, Painter, Pos2, Response, Ui};
-use linefollower_core::geometry::closed_path::SubPath;
+use linefollower_core::geometry::{closed_path::SubPath, error::GeometryError};
 
 use crate::{canvas::Canvas, curve_graph::CurveGraph};
 
@@ -34,7 +34,7 @@ impl Tool {
             Tool::Select(tool) => tool.on_input(response, input, ui, canvas, painter, graph),
         }
     }
-    pub fn on_click(&mut self, p: Pos2) -> Option<SubPath<f64>> {
+    pub fn on_click(&mut self, p: Pos2) -> Option<Result<SubPath<f64>, GeometryError>> {
         match self {
             Tool::Free(_) => None,
             Tool::ArcPath(tool) => tool.on_click(p),
@@ -42,6 +42,16 @@ impl Tool {
             Tool::Select(_) => None,
         }
     }
+    /// Whether the next click places an endpoint of a subpath. Only those snap to the existing
+    /// nodes, so that the subpath reuses them instead of adding near-duplicates next to them
+    pub fn snaps_next_click(&self) -> bool {
+        match self {
+            Tool::Free(_) => false,
+            Tool::ArcPath(tool) => tool.snaps_next_click(),
+            Tool::LinePath(_) => true,
+            Tool::Select(_) => false,
+        }
+    }
     pub fn draw(&self, ui: &Ui, canvas: &Canvas, painter: &Painter) {
         match self {
             Tool::Free(_) => {}